@@ -8,11 +8,15 @@ fn main() {
     let binding = arg.unwrap_or("".into());
     let pattern = binding.as_str();
 
-    let manager = FontManager::new();
+    let manager = FontManager::new().expect("Failed to create font manager");
 
     for source_type in manager.sources() {
         render_table(source_type, &manager, pattern);
     }
+
+    for diagnostic in manager.diagnostics() {
+        println!("skipped: {}", diagnostic);
+    }
 }
 
 #[allow(unused)]
@@ -1,4 +1,9 @@
-use gosub_fontmanager::{FontManager, FontSourceType, FontStyle};
+use gosub_fontmanager::{FontManager, FontSourceType, FontStyle, SvgOptions, SvgText};
+
+/// Size (in pixels per em) the text is drawn at
+const FONT_SIZE: f32 = 16.0;
+/// Width (in pixels) at which lines are wrapped
+const MAX_WIDTH: f32 = 600.0;
 // const TEST_STRING1: &str = "A B C D E\n \u{EA84} a b c d e";
 
 const TEST_STRING: &str = r"A B C D E F G H I J K L M N O P Q R S T U V W X Y Z
@@ -25,95 +30,25 @@ Hello world from the Gosub FontManager system!
 fn main() {
     colog::init();
 
-    let manager = FontManager::new();
+    let manager = FontManager::new().expect("Failed to create font manager");
 
-    let arg = std::env::args().nth(1);
-    let binding = arg.unwrap_or("arial".into());
-    let font = binding.as_str();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let embed = args.iter().any(|arg| arg == "--embed");
+    let font = args.iter().find(|arg| !arg.starts_with("--")).map(|arg| arg.as_str()).unwrap_or("arial");
 
-    let info = manager.find(FontSourceType::Fontkit, &[font], FontStyle::Normal);
-    if info.is_none() {
+    let Some(info) = manager.find(FontSourceType::Fontkit, &[font], FontStyle::Normal) else {
         eprintln!("Font not found: {}", font);
         return;
-    }
-
-    let fontkit = manager.find_fontkit();
-    let face = fontkit.load_freetype_font(&info.unwrap()).unwrap();
-    char_to_svg(face, TEST_STRING);
-}
-
-fn char_to_svg(face: freetype::Face, content: &str) {
-    face.set_char_size(10 * 64, 0, 10, 0).unwrap();
-
-    println!("<?xml version=\"1.0\" standalone=\"no\"?>");
-    println!("<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\"");
-    println!("\"http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd\">");
-    println!("<svg viewBox=\"0 0 100 200\" xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\">");
-
-    let mut x_pos = -155.0;
-    let mut y_pos =  10.0;
-
-    for c in content.chars() {
-        if c == '\n' {
-            x_pos = -155.0;
-            y_pos += 10.0;
-            continue;
-        }
-
-        if c == ' ' {
-            x_pos += 2.5;
-            continue;
-        }
+    };
 
-        let result = face.load_char(c as usize, freetype::face::LoadFlag::NO_SCALE);
-        if result.is_err() {
-            continue;
-        }
+    let options = SvgOptions {
+        text: if embed { SvgText::EmbeddedFont } else { SvgText::Outlines },
+        max_width: Some(MAX_WIDTH),
+        ..Default::default()
+    };
 
-        let glyph = face.glyph();
-        let metrics = glyph.metrics();
-        // let xmin = metrics.horiBearingX - 5;
-        let width = metrics.width + 10;
-        // let ymin = -metrics.horiBearingY - 5;
-        // let height = metrics.height + 10;
-        // let scale_factor = 10.0 / width as f32;
-        let scale_factor = 0.0056;
-
-        let outline = glyph.outline().unwrap();
-
-        for contour in outline.contours_iter() {
-            let start = contour.start();
-
-            // dbg!(x_pos, y_pos);
-            println!("<g transform=\"translate({}, {}) scale({})\">", (x_pos - 1.0), (y_pos - 1.0), scale_factor);
-
-            println!(
-                "<path fill=\"none\" stroke=\"black\" stroke-width=\"16\" d=\"M {} {}",
-                start.x, -start.y
-            );
-            for curve in contour {
-                draw_curve(curve);
-            }
-            println!("Z \" />");
-            println!("</g>");
-        }
-
-        x_pos += width as f32 * scale_factor;
-        x_pos += 1.0;
+    match manager.text_to_svg(TEST_STRING, &info, FONT_SIZE, &options) {
+        Ok(svg) => print!("{}", svg),
+        Err(e) => eprintln!("Unable to generate SVG: {}", e),
     }
-    println!("</svg>");
 }
-
-
-fn draw_curve(curve: freetype::outline::Curve) {
-    match curve {
-        freetype::outline::Curve::Line(pt) => println!("L {} {}", pt.x, -pt.y),
-        freetype::outline::Curve::Bezier2(pt1, pt2) => {
-            println!("Q {} {} {} {}", pt1.x, -pt1.y, pt2.x, -pt2.y)
-        }
-        freetype::outline::Curve::Bezier3(pt1, pt2, pt3) => println!(
-            "C {} {} {} {} {} {}",
-            pt1.x, -pt1.y, pt2.x, -pt2.y, pt3.x, -pt3.y
-        ),
-    }
-}
\ No newline at end of file
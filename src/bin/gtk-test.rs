@@ -23,7 +23,7 @@ fn main() -> glib::ExitCode {
 }
 
 fn build_ui(app: &Application) {
-    let font_manager = FontManager::new();
+    let font_manager = FontManager::new().expect("Failed to create font manager");
     let _ = font_manager.find(FontSourceType::Pango,&["comic sans ms"], FontStyle::Normal).expect("Failed to find font Comic Sans MS");
     let _ = font_manager.find(FontSourceType::Pango, &["Arial"], FontStyle::Normal).expect("Failed to find font Arial");
 
@@ -1,25 +1,12 @@
-use gtk4::{glib, pango, Application, ApplicationWindow, DrawingArea};
+use std::cell::RefCell;
+use gtk4::{glib, Application, ApplicationWindow, DrawingArea};
 use gtk4::prelude::{ApplicationExt, ApplicationExtManual, DrawingAreaExt, DrawingAreaExtManual, GtkWindowExt, WidgetExt};
-use image::Rgba;
 use parley::layout::{Alignment, Layout, PositionedLayoutItem};
 use parley::style::{FontWeight, StyleProperty};
 use parley::{InlineBox, LayoutContext};
+use vello::peniko::Color;
 use gosub_fontmanager::{FontInfo, FontManager, FontSourceType, FontStyle};
-
-const RENDER_GLYPHS_PER_RUN : bool = false;
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct ColorBrush {
-    color: Rgba<u8>,
-}
-
-impl Default for ColorBrush {
-    fn default() -> Self {
-        Self {
-            color: Rgba([0, 0, 0, 255]),
-        }
-    }
-}
+use gosub_fontmanager::render::cairo::CairoRenderer;
 
 const APP_ID: &str = "io.gosub.font-manager.gtk-test";
 
@@ -46,9 +33,13 @@ fn build_ui(app: &Application) {
         .title("GTK Font Renderer")
         .build();
 
-    let manager = FontManager::new();
+    let manager = FontManager::new().expect("Failed to create font manager");
     let font_info = manager.find(FontSourceType::Fontkit, &["comic sans ms"], FontStyle::Normal).unwrap();
 
+    let mut renderer = CairoRenderer::new().expect("Failed to create cairo renderer");
+    renderer.inline_box_color = Some(Color::rgb8(0, 0, 255));
+    let renderer = RefCell::new(renderer);
+
     // let text = "Some text here. Let's make it a bit longer so that line wrapping kicks in 😊. And also some اللغة العربية arabic text.\nThis is underline and strikethrough text";
     // let text = "hello world. This is a test to see if it works! abcdefghhijklmnopqrstuvwxyz ABCDEFGHIJKLMNOPQRSTUVWXYZ 0123456790";
     let text = gosub_fontmanager::flatland::TEXT;
@@ -69,8 +60,8 @@ fn build_ui(app: &Application) {
             let layout = create_layout(&manager, &font_info, text, width as f64, fs);
             let h = layout.height();
 
-            // Draw the layout with pango
-            draw(&manager, &font_info, &cr, layout, 100.0, height);
+            // Draw the layout with cairo
+            draw(&manager, &mut renderer.borrow_mut(), &font_info, cr, layout, 100.0, height);
             height += h + 50.0;
         }
 
@@ -92,109 +83,42 @@ fn build_ui(app: &Application) {
     window.present();
 }
 
-fn draw(manager: &FontManager, font: &FontInfo, cr: &gtk4::cairo::Context, layout: Layout<ColorBrush>, offset_x: f32, offset_y: f32) {
+fn draw(manager: &FontManager, renderer: &mut CairoRenderer, font: &FontInfo, cr: &gtk4::cairo::Context, layout: Layout<Color>, offset_x: f32, offset_y: f32) {
     for line in layout.lines() {
         for item in line.items() {
-            match item {
-                PositionedLayoutItem::GlyphRun(glyph_run) => {
-                    let run_x = offset_x + glyph_run.offset();
-                    let run_y = offset_y + glyph_run.baseline();
-
-                    // @Todo: how do we find the height of the font. We need this because the baseline
-                    // does only tell us the start of the bottom (the actual baseline). But we do not
-                    // have any (easy) way to find the height of the font. We need this to draw the
-                    // rectangle around the text.
-
-                    // let font_height = 48.0; // completely arbitrary value
-                    // cr.rectangle(
-                    //     run_x as f64,
-                    //     run_y as f64 - font_height,
-                    //     glyph_run.advance() as f64,
-                    //     font_height,
-                    // );
-                    // cr.set_source_rgba(rng.random(), rng.random(), rng.random(), 0.5);
-                    // let _ = cr.fill();
-
-
-                    let pango = manager.find_pango();
-                    let font = pango.load_font(&font).unwrap();
-
-                    if RENDER_GLYPHS_PER_RUN {
-                        // Render a whole glyph run at once. This does not work correctly
-
-                        // convert glyph_run.glyphs() to a vector of GlyphInfo
-                        let mut glyphs = Vec::new();
-                        for g in glyph_run.positioned_glyphs() {
-                            glyphs.push(g);
-                        }
-                        dbg!(&glyphs);
-
-                        let mut gs = pango::GlyphString::new();
-                        gs.set_size(glyphs.len() as i32);
-                        for (i, glyph) in glyphs.iter().enumerate() {
-                            let m = gs.glyph_info_mut();
-                            m[i].set_glyph(glyph.id as u32);
-                            m[i].geometry_mut().set_x_offset(glyph.x as i32);
-                            m[i].geometry_mut().set_y_offset(glyph.y as i32);
-                        }
-
-                        cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-                        cr.move_to(run_x as f64, run_y as f64);
-                        pangocairo::functions::show_glyph_string(
-                            cr,
-                            &font,
-                            &mut gs,
-                        );
-                    } else {
-                        // Render per glyph
-                        let mut c_x = run_x;
-                        for g in glyph_run.glyphs() {
-                            let g_x = c_x + g.x;
-                            let g_y = run_y + g.y;
-                            c_x += g.advance;
-
-                            let mut gs = pango::GlyphString::new();
-                            gs.set_size(1);
-                            let m = gs.glyph_info_mut();
-                            m[0].set_glyph(g.id as u32);
-
-                            cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-                            cr.move_to(g_x as f64, g_y as f64);
-                            pangocairo::functions::show_glyph_string(
-                                cr,
-                                &font,
-                                &mut gs,
-                            );
-                        }
-                    }
-                }
-                PositionedLayoutItem::InlineBox(inline_box) => {
+            if let PositionedLayoutItem::GlyphRun(glyph_run) = item {
+                let run_x = offset_x + glyph_run.offset();
+                let run_y = offset_y + glyph_run.baseline();
+
+                // Draw a box around the glyph run, from the ascent to the descent of the font
+                if let Ok(metrics) = manager.metrics(font, glyph_run.run().font_size()) {
                     cr.rectangle(
-                        (offset_x + inline_box.x) as f64,
-                         (offset_y + inline_box.y) as f64,
-                        inline_box.width as f64,
-                        inline_box.height as f64,
+                        run_x as f64,
+                        (run_y - metrics.ascent) as f64,
+                        glyph_run.advance() as f64,
+                        (metrics.ascent + metrics.descent) as f64,
                     );
-                    cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-                    let _ = cr.stroke();
-                    cr.set_source_rgba(0.0, 0.0, 1.0, 1.0);
+                    cr.set_source_rgba(0.0, 0.5, 1.0, 0.2);
                     let _ = cr.fill();
                 }
-            };
+            }
         }
     }
+
+    // gtk4 uses a newer version of cairo-rs than the font manager, but both wrap the same cairo context
+    let cr = unsafe { cairo::Context::from_raw_none(cr.to_raw_none() as *mut cairo::ffi::cairo_t) };
+    if let Err(e) = renderer.draw_layout(&cr, &layout, offset_x as f64, offset_y as f64) {
+        println!("Failed to draw layout: {}", e);
+    }
 }
 
-fn create_layout(manager: &FontManager, font: &FontInfo, text: &str, width: f64, font_size: f64) -> Layout<ColorBrush> {
+fn create_layout(manager: &FontManager, font: &FontInfo, text: &str, width: f64, font_size: f64) -> Layout<Color> {
     let display_scale = 1.0_f32;
     let max_advance = Some(width as f32 * display_scale);
 
-    let text_color = Rgba([0, 0, 0, 255]);
-
     let mut layout_cx = LayoutContext::new();
 
-    let text_brush = ColorBrush { color: text_color };
-    let brush_style = StyleProperty::Brush(text_brush);
+    let brush_style = StyleProperty::Brush(Color::BLACK);
     let bold_style = StyleProperty::FontWeight(FontWeight::EXTRA_BLACK);
     // let underline_style = StyleProperty::Underline(true);
     // let strikethrough_style = StyleProperty::Strikethrough(true);
@@ -229,7 +153,7 @@ fn create_layout(manager: &FontManager, font: &FontInfo, text: &str, width: f64,
         height: 30.0,
     });
 
-    let mut layout: Layout<ColorBrush> = builder.build(text);
+    let mut layout: Layout<Color> = builder.build(text);
 
     layout.break_all_lines(max_advance);
     layout.align(max_advance, Alignment::Start);
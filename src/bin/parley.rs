@@ -1,28 +1,12 @@
 use image::codecs::png::PngEncoder;
 use image::{self, Pixel, Rgba, RgbaImage};
-use parley::layout::{Alignment, Glyph, GlyphRun, Layout, PositionedLayoutItem};
+use parley::layout::{Alignment, Layout, PositionedLayoutItem};
 use parley::style::{FontWeight, StyleProperty};
 use parley::{InlineBox, LayoutContext};
 use std::fs::File;
-use swash::scale::image::Content;
-use swash::scale::{Render, ScaleContext, Scaler, Source, StrikeWith};
-use swash::zeno;
-use swash::FontRef;
-use zeno::{Format, Vector};
-use gosub_fontmanager::{FontManager, FontSourceType, FontStyle};
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct ColorBrush {
-    color: Rgba<u8>,
-}
-
-impl Default for ColorBrush {
-    fn default() -> Self {
-        Self {
-            color: Rgba([0, 0, 0, 255]),
-        }
-    }
-}
+use vello::peniko::Color;
+use gosub_fontmanager::{Antialias, FontManager, FontSourceType, FontStyle, RasterOptions};
+use gosub_fontmanager::render::swash::{RgbaBuffer, SwashRenderer};
 
 fn main() {
     colog::init();
@@ -30,12 +14,12 @@ fn main() {
     let display_scale = 1.0;
     let max_advance = Some(400.0 * display_scale);
 
-    let text_color = Rgba([0,0,0,255]);
+    let text_color = Color::BLACK;
     let bg_color = Rgba([255,255,255,255]);
 
     let padding = 20;
 
-    let manager = FontManager::new();
+    let manager = FontManager::new().expect("Failed to create font manager");
     let parley = manager.find_parley();
 
     let font_info = manager.find(FontSourceType::Parley, &["consolas", "verdana", "comic sans ms", "arial"], FontStyle::Normal).expect("font not found");
@@ -43,10 +27,8 @@ fn main() {
     let font_cx = parley.context();
 
     let mut layout_cx = LayoutContext::new();
-    let mut scale_cx = ScaleContext::new();
 
-    let text_brush = ColorBrush { color: text_color };
-    let brush_style = StyleProperty::Brush(text_brush);
+    let brush_style = StyleProperty::Brush(text_color);
     let bold_style = StyleProperty::FontWeight(FontWeight::EXTRA_BLACK);
     let underline_style = StyleProperty::Underline(true);
     let strikethrough_style = StyleProperty::Strikethrough(true);
@@ -79,7 +61,7 @@ fn main() {
         height: 30.0,
     });
 
-    let mut layout: Layout<ColorBrush> = builder.build(text);
+    let mut layout: Layout<Color> = builder.build(text);
 
     layout.break_all_lines(max_advance);
     layout.align(max_advance, Alignment::Start);
@@ -88,25 +70,29 @@ fn main() {
     let height = layout.height().ceil() as u32 + (padding * 2);
     let mut img = RgbaImage::from_pixel(width, height, bg_color);
 
+    // Inline boxes are not drawn by the renderer
     for line in layout.lines() {
         for item in line.items() {
-            match item {
-                PositionedLayoutItem::GlyphRun(glyph_run) => {
-                    render_glyph_run(&mut scale_cx, &glyph_run, &mut img, padding);
-                }
-                PositionedLayoutItem::InlineBox(inline_box) => {
-                    for x_off in 0..(inline_box.width.floor() as u32) {
-                        for y_off in 0..(inline_box.height.floor() as u32) {
-                            let x = inline_box.x as u32 + x_off + padding;
-                            let y = inline_box.y as u32 + y_off + padding;
-                            img.put_pixel(x, y, Rgba([0, 0, 0, 64]));
-                        }
+            if let PositionedLayoutItem::InlineBox(inline_box) = item {
+                for x_off in 0..(inline_box.width.floor() as u32) {
+                    for y_off in 0..(inline_box.height.floor() as u32) {
+                        let x = inline_box.x as u32 + x_off + padding;
+                        let y = inline_box.y as u32 + y_off + padding;
+                        img.get_pixel_mut(x, y).blend(&Rgba([0, 0, 0, 64]));
                     }
                 }
-            };
+            }
         }
     }
 
+    let mut renderer = SwashRenderer::new(RasterOptions {
+        antialias: Antialias::SubpixelRgb,
+        hinting: true,
+        subpixel_positioning: true,
+    });
+    let mut buffer = RgbaBuffer::new(&mut img, width, height).expect("Failed to wrap image");
+    renderer.draw_layout(&mut buffer, &layout, padding as f32, padding as f32).expect("Failed to draw layout");
+
     // Write image to PNG file in examples/_output dir
     let output_path = {
         let path = std::path::PathBuf::from(file!());
@@ -125,143 +111,3 @@ fn main() {
     println!("Image written to: {:?}", output_path);
 }
 
-
-fn render_glyph_run(
-    context: &mut ScaleContext,
-    glyph_run: &GlyphRun<'_, ColorBrush>,
-    img: &mut RgbaImage,
-    padding: u32,
-) {
-    // Resolve properties of the GlyphRun
-    let mut run_x = glyph_run.offset();
-    let run_y = glyph_run.baseline();
-    let style = glyph_run.style();
-    let color = style.brush;
-
-    // Get the "Run" from the "GlyphRun"
-    let run = glyph_run.run();
-
-    // Resolve properties of the Run
-    let font = run.font();
-    let font_size = run.font_size();
-    let normalized_coords = run.normalized_coords();
-
-    // Convert from parley::Font to swash::FontRef
-    let font_ref = FontRef::from_index(font.data.as_ref(), font.index as usize).unwrap();
-
-    // Build a scaler. As the font properties are constant across an entire run of glyphs
-    // we can build one scaler for the run and reuse it for each glyph.
-    let mut scaler = context
-        .builder(font_ref)
-        .size(font_size)
-        .hint(true)
-        .normalized_coords(normalized_coords)
-        .build();
-
-    // Iterates over the glyphs in the GlyphRun
-    for glyph in glyph_run.glyphs() {
-        let glyph_x = run_x + glyph.x + (padding as f32);
-        let glyph_y = run_y - glyph.y + (padding as f32);
-        run_x += glyph.advance;
-
-        render_glyph(img, &mut scaler, color, glyph, glyph_x, glyph_y);
-    }
-
-    // Draw decorations: underline & strikethrough
-    let style = glyph_run.style();
-    let run_metrics = run.metrics();
-    if let Some(decoration) = &style.underline {
-        let offset = decoration.offset.unwrap_or(run_metrics.underline_offset);
-        let size = decoration.size.unwrap_or(run_metrics.underline_size);
-        render_decoration(img, glyph_run, decoration.brush, offset, size, padding);
-    }
-    if let Some(decoration) = &style.strikethrough {
-        let offset = decoration
-            .offset
-            .unwrap_or(run_metrics.strikethrough_offset);
-        let size = decoration.size.unwrap_or(run_metrics.strikethrough_size);
-        render_decoration(img, glyph_run, decoration.brush, offset, size, padding);
-    }
-}
-
-fn render_decoration(
-    img: &mut RgbaImage,
-    glyph_run: &GlyphRun<'_, ColorBrush>,
-    brush: ColorBrush,
-    offset: f32,
-    width: f32,
-    padding: u32,
-) {
-    let y = glyph_run.baseline() - offset;
-    for pixel_y in y as u32..(y + width) as u32 {
-        for pixel_x in glyph_run.offset() as u32..(glyph_run.offset() + glyph_run.advance()) as u32
-        {
-            img.get_pixel_mut(pixel_x + padding, pixel_y + padding)
-                .blend(&brush.color);
-        }
-    }
-}
-
-fn render_glyph(
-    img: &mut RgbaImage,
-    scaler: &mut Scaler<'_>,
-    brush: ColorBrush,
-    glyph: Glyph,
-    glyph_x: f32,
-    glyph_y: f32,
-) {
-    // Compute the fractional offset
-    // You'll likely want to quantize this in a real renderer
-    let offset = Vector::new(glyph_x.fract(), glyph_y.fract());
-
-    // Render the glyph using swash
-    let rendered_glyph = Render::new(
-        // Select our source order
-        &[
-            Source::ColorOutline(0),
-            Source::ColorBitmap(StrikeWith::BestFit),
-            Source::Outline,
-        ],
-    )
-        // Select the simple alpha (non-subpixel) format
-        .format(Format::Alpha)
-        // Apply the fractional offset
-        .offset(offset)
-        // Render the image
-        .render(scaler, glyph.id)
-        .unwrap();
-
-    let glyph_width = rendered_glyph.placement.width;
-    let glyph_height = rendered_glyph.placement.height;
-    let glyph_x = (glyph_x.floor() as i32 + rendered_glyph.placement.left) as u32;
-    let glyph_y = (glyph_y.floor() as i32 - rendered_glyph.placement.top) as u32;
-
-    match rendered_glyph.content {
-        Content::Mask => {
-            let mut i = 0;
-            let bc = brush.color;
-            for pixel_y in 0..glyph_height {
-                for pixel_x in 0..glyph_width {
-                    let x = glyph_x + pixel_x;
-                    let y = glyph_y + pixel_y;
-                    let alpha = rendered_glyph.data[i];
-                    let color = Rgba([bc[0], bc[1], bc[2], alpha]);
-                    img.get_pixel_mut(x, y).blend(&color);
-                    i += 1;
-                }
-            }
-        }
-        Content::SubpixelMask => unimplemented!(),
-        Content::Color => {
-            let row_size = glyph_width as usize * 4;
-            for (pixel_y, row) in rendered_glyph.data.chunks_exact(row_size).enumerate() {
-                for (pixel_x, pixel) in row.chunks_exact(4).enumerate() {
-                    let x = glyph_x + pixel_x as u32;
-                    let y = glyph_y + pixel_y as u32;
-                    let color = Rgba(pixel.try_into().expect("Not RGBA"));
-                    img.get_pixel_mut(x, y).blend(&color);
-                }
-            }
-        }
-    };
-}
\ No newline at end of file
@@ -8,34 +8,104 @@ use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::window::{Window, WindowId};
 use vello::kurbo::{Affine, Circle, Ellipse, Line, RoundedRect, Stroke};
 use vello::peniko::Color;
+use parley::Layout;
+use gosub_fontmanager::{FontManager, FontQuery, FontSourceType};
+use gosub_fontmanager::render::{layout_text, TextOptions};
+use gosub_fontmanager::render::vello::{render_to_image, VelloRenderer};
 
 const AA_CONFIGS: [AaConfig; 3] = [AaConfig::Area, AaConfig::Msaa8, AaConfig::Msaa16];
 
+const TEXT: &str = "The quick brown fox jumps over the lazy dog. Text is laid out with parley and drawn with vello.";
+
+/// Renders into a window, or without a window into a PNG file with `vello-test --png <file>`
 fn main() {
     colog::init();
 
+    let manager = FontManager::new().expect("Failed to create font manager");
+    let query = FontQuery::new(&["DejaVu Sans", "Arial"]);
+    let font_info = manager.match_font(FontSourceType::Parley, &query).expect("font not found");
+    let layout = layout_text(manager.find_parley(), TEXT, &font_info, &TextOptions {
+        size: 24.0,
+        max_width: Some(560.0),
+        ..Default::default()
+    });
+
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = args.iter().position(|arg| arg == "--png").and_then(|idx| args.get(idx + 1)) {
+        let mut scene = Scene::new();
+        draw_scene(&mut scene, &mut VelloRenderer::new(), &layout);
+
+        let image = render_to_image(&scene, 640, 640, Color::WHITE).expect("Failed to render scene");
+        image.save(path).expect("Failed to write image");
+        println!("Image written to: {}", path);
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     // event_loop.set_control_flow(ControlFlow::Wait);
 
-    let mut app = App::new();
+    let mut app = App::new(layout);
     let _ = event_loop.run_app(&mut app);
 }
 
+/// Draws some shapes with the text on top
+fn draw_scene(scene: &mut Scene, text_renderer: &mut VelloRenderer, layout: &Layout<Color>) {
+    // Draw an outlined rectangle
+    let stroke = Stroke::new(6.0);
+    let rect = RoundedRect::new(10.0, 10.0, 240.0, 240.0, 20.0);
+    let rect_stroke_color = Color::YELLOW_GREEN;
+    scene.stroke(&stroke, Affine::IDENTITY, rect_stroke_color, None, &rect);
+
+    // Draw a filled circle
+    let circle = Circle::new((420.0, 200.0), 120.0);
+    let circle_fill_color = Color::REBECCA_PURPLE;
+    scene.fill(
+        vello::peniko::Fill::NonZero,
+        Affine::IDENTITY,
+        circle_fill_color,
+        None,
+        &circle,
+    );
+
+    // Draw a filled ellipse
+    let ellipse = Ellipse::new((250.0, 420.0), (100.0, 160.0), -90.0);
+    let ellipse_fill_color = Color::BLUE_VIOLET;
+    scene.fill(
+        vello::peniko::Fill::NonZero,
+        Affine::IDENTITY,
+        ellipse_fill_color,
+        None,
+        &ellipse,
+    );
+
+    // Draw a straight line
+    let line = Line::new((260.0, 20.0), (620.0, 100.0));
+    let line_stroke_color = Color::FIREBRICK;
+    scene.stroke(&stroke, Affine::IDENTITY, line_stroke_color, None, &line);
+
+    // Draw the text
+    text_renderer.draw_layout(scene, layout, Affine::translate((40.0, 520.0)));
+}
+
 
 struct App<'s> {
     render_ctx: RenderContext,
     renderer: Option<Renderer>,
+    text_renderer: VelloRenderer,
     surface: Option<RenderSurface<'s>>,     // Surface must be before window for safety during cleanup
     window: Option<Arc<Window>>,
+    layout: Layout<Color>,
 }
 
 impl App<'_> {
-    fn new() -> Self {
+    fn new(layout: Layout<Color>) -> Self {
         App {
             window: None,
+            layout,
             render_ctx: RenderContext::new(),
             renderer: None,
+            text_renderer: VelloRenderer::new(),
             surface: None,
         }
     }
@@ -119,38 +189,7 @@ impl ApplicationHandler for App<'_> {
                 };
 
                 let mut scene = Scene::new();
-                // Draw an outlined rectangle
-                let stroke = Stroke::new(6.0);
-                let rect = RoundedRect::new(10.0, 10.0, 240.0, 240.0, 20.0);
-                let rect_stroke_color = Color::YELLOW_GREEN;
-                scene.stroke(&stroke, Affine::IDENTITY, rect_stroke_color, None, &rect);
-
-                // Draw a filled circle
-                let circle = Circle::new((420.0, 200.0), 120.0);
-                let circle_fill_color = Color::REBECCA_PURPLE;
-                scene.fill(
-                    vello::peniko::Fill::NonZero,
-                    Affine::IDENTITY,
-                    circle_fill_color,
-                    None,
-                    &circle,
-                );
-
-                // Draw a filled ellipse
-                let ellipse = Ellipse::new((250.0, 420.0), (100.0, 160.0), -90.0);
-                let ellipse_fill_color = Color::BLUE_VIOLET;
-                scene.fill(
-                    vello::peniko::Fill::NonZero,
-                    Affine::IDENTITY,
-                    ellipse_fill_color,
-                    None,
-                    &ellipse,
-                );
-
-                // Draw a straight line
-                let line = Line::new((260.0, 20.0), (620.0, 100.0));
-                let line_stroke_color = Color::FIREBRICK;
-                scene.stroke(&stroke, Affine::IDENTITY, line_stroke_color, None, &line);
+                draw_scene(&mut scene, &mut self.text_renderer, &self.layout);
 
                 let _ = self.renderer.as_mut().unwrap().render_to_surface(
                     &device,
@@ -1,4 +1,30 @@
 pub mod manager;
+pub mod cache;
+pub mod catalogue;
+pub mod color;
+pub mod coverage;
+pub mod error;
+pub mod fallback;
+pub mod features;
+pub mod font_id;
 pub mod font_info;
+pub mod generic;
+pub mod glyph_cache;
+pub mod matching;
+pub mod metrics;
+pub mod outline;
+#[cfg(feature = "source_parley")]
+pub mod render;
+pub mod shaping;
 pub mod sources;
+pub mod svg;
+pub mod synthesis;
+pub mod variation;
+pub mod watcher;
+pub mod woff;
+#[cfg(test)]
+mod fixtures;
+mod monospace;
+mod reader;
+mod subset;
 
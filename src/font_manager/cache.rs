@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::font_manager::font_info::{scanned_variations, FontInfo, FontStyle};
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::sources::FontSourceType;
+
+/// Version of the cache format. Caches with a different version are discarded.
+const CACHE_VERSION: u32 = 4;
+
+/// Directories where fonts are commonly installed. Changes in these directories (like a newly added
+/// font directory) invalidate the cache.
+const FONT_DIRECTORIES: &[&str] = &[
+    "/usr/share/fonts",
+    "/usr/local/share/fonts",
+    "~/.fonts",
+    "~/.local/share/fonts",
+    "/Library/Fonts",
+    "/System/Library/Fonts",
+    "~/Library/Fonts",
+    "C:\\Windows\\Fonts",
+];
+
+/// Fontconfig configuration files and directories. A change in the configuration can add or remove
+/// font directories, so it invalidates the cache as well.
+const FONTCONFIG_FILES: &[&str] = &[
+    "/etc/fonts/fonts.conf",
+    "/etc/fonts/local.conf",
+    "/etc/fonts/conf.d",
+    "~/.fonts.conf",
+    "~/.config/fontconfig/fonts.conf",
+    "~/.config/fontconfig/conf.d",
+];
+
+/// Modification time and size of a file or directory, used to detect changes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Stamp {
+    /// Modification time in nanoseconds since the unix epoch
+    mtime: u64,
+    size: u64,
+}
+
+impl Stamp {
+    /// Returns the stamp of the path, or None when the path does not exist
+    pub(crate) fn of(path: &Path) -> Option<Stamp> {
+        let meta = std::fs::metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+
+        Some(Stamp { mtime, size: meta.len() })
+    }
+}
+
+/// Face as stored in the cache
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedFace {
+    family: String,
+    style: FontStyle,
+    weight: f32,
+    stretch: f32,
+    monospaced: bool,
+    /// False when the face is known to have no variation axes
+    variable: bool,
+    index: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedFile {
+    stamp: Stamp,
+    faces: Vec<CachedFace>,
+}
+
+/// On-disk cache of the faces found by a font source. Faces are stored per font file together with
+/// the modification time and size of the file, so only files that have changed need to be parsed
+/// again. The cache also keeps the state of the font directories (and all directories below them)
+/// and of the fontconfig configuration, so a source can skip enumerating the fonts altogether when
+/// nothing has been installed, removed or reconfigured.
+///
+/// The fontkit source caches all of its faces. The parley source caches the monospace detection it
+/// does on top of fontique, as fontique itself enumerates the fonts from the fontconfig cache on every
+/// start. The pango source lists its fonts from fontconfig (and its cache) as well, and is not cached.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FontCache {
+    version: u32,
+    /// Font directories, their subdirectories and the fontconfig configuration, with their state when
+    /// the cache was written (None when not existing)
+    directories: HashMap<PathBuf, Option<Stamp>>,
+    /// Faces per font file
+    files: HashMap<PathBuf, CachedFile>,
+    /// Location of the cache file
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl FontCache {
+    /// Returns the default location of the cache file for the given source type, or None when there
+    /// is no cache directory on this system.
+    pub fn default_path(source_type: FontSourceType) -> Option<PathBuf> {
+        let name = format!("{:?}", source_type).to_lowercase();
+        dirs::cache_dir().map(|dir| dir.join("gosub").join(format!("fonts-{}.json", name)))
+    }
+
+    /// Loads the cache from the given file. An empty cache is returned when the file does not exist
+    /// or can not be read.
+    pub fn load(path: &Path) -> Self {
+        let cache = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| serde_json::from_slice::<FontCache>(&data).map_err(anyhow::Error::from));
+
+        let mut cache = match cache {
+            Ok(cache) if cache.version == CACHE_VERSION => cache,
+            Ok(_) => {
+                info!(target: LOG_TARGET, "Discarding font cache {} with a different version", path.display());
+                FontCache::default()
+            }
+            Err(e) => {
+                if path.exists() {
+                    warn!(target: LOG_TARGET, "Unable to read font cache {}: {}", path.display(), e);
+                }
+                FontCache::default()
+            }
+        };
+
+        cache.version = CACHE_VERSION;
+        cache.path = Some(path.to_path_buf());
+        cache
+    }
+
+    /// Returns true when the cache has been written before and none of the font directories (or their
+    /// subdirectories, the directories of the cached font files and the fontconfig configuration) have
+    /// changed since.
+    pub fn is_fresh(&self) -> bool {
+        !self.directories.is_empty()
+            && self.directories.iter().all(|(dir, stamp)| Stamp::of(dir) == *stamp)
+    }
+
+    /// Returns the paths of all cached font files
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Returns the cached face with the given index from the font file, when the file has not changed
+    /// since it was cached.
+    pub fn face(&self, path: &Path, index: i32, source_type: FontSourceType) -> Option<FontInfo> {
+        self.faces(path, source_type)?.into_iter().find(|fi| fi.index == Some(index))
+    }
+
+    /// Returns all cached faces from the font file, when the file has not changed since it was cached
+    pub fn faces(&self, path: &Path, source_type: FontSourceType) -> Option<Vec<FontInfo>> {
+        let file = self.files.get(path)?;
+        if Stamp::of(path) != Some(file.stamp) {
+            return None;
+        }
+
+        Some(file.faces.iter().map(|face| FontInfo {
+            style: face.style.clone(),
+            weight: face.weight,
+            stretch: face.stretch,
+            monospaced: face.monospaced,
+            path: Some(path.to_path_buf()),
+            index: Some(face.index),
+            variations: scanned_variations(face.variable),
+            ..FontInfo::new(source_type, face.family.clone())
+        }).collect())
+    }
+
+    /// Replaces the cache with the given faces. Faces without a path (and files that have
+    /// disappeared) are not cached.
+    pub fn update(&mut self, fonts: &[FontInfo]) {
+        self.files.clear();
+        self.directories.clear();
+
+        for fi in fonts {
+            let Some(path) = &fi.path else {
+                continue;
+            };
+
+            if !self.files.contains_key(path) {
+                let Some(stamp) = Stamp::of(path) else {
+                    continue;
+                };
+                self.files.insert(path.clone(), CachedFile { stamp, faces: vec![] });
+            }
+
+            if let Some(file) = self.files.get_mut(path) {
+                file.faces.push(CachedFace {
+                    family: fi.family.clone(),
+                    style: fi.style.clone(),
+                    weight: fi.weight,
+                    stretch: fi.stretch,
+                    monospaced: fi.monospaced,
+                    variable: fi.variations.get().is_none_or(|v| !v.axes.is_empty()),
+                    index: fi.index.unwrap_or(0),
+                });
+            }
+        }
+
+        // Directories that are added below a font directory only change the stamp of their parent, so
+        // every directory below the font directories is stamped
+        let mut directories = Vec::new();
+        let mut visited = HashSet::new();
+        for dir in font_directories() {
+            collect_directories(&dir, &mut visited, &mut directories);
+        }
+        directories.extend(self.files.keys().filter_map(|path| path.parent().map(Path::to_path_buf)));
+        directories.extend(fontconfig_files());
+
+        for dir in directories {
+            let stamp = Stamp::of(&dir);
+            self.directories.insert(dir, stamp);
+        }
+    }
+
+    /// Writes the cache to disk
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first, so a reader never sees a partially written cache
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        info!(target: LOG_TARGET, "Wrote {} cached font files to {}", self.files.len(), path.display());
+        Ok(())
+    }
+}
+
+/// Returns the directories where fonts are commonly installed on this system
+pub(crate) fn font_directories() -> Vec<PathBuf> {
+    FONT_DIRECTORIES.iter().map(|dir| expand_home(dir)).collect()
+}
+
+/// Returns the fontconfig configuration files and directories (including the file set by the
+/// FONTCONFIG_FILE environment variable)
+fn fontconfig_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = FONTCONFIG_FILES.iter().map(|file| expand_home(file)).collect();
+    if let Some(file) = std::env::var_os("FONTCONFIG_FILE") {
+        files.push(PathBuf::from(file));
+    }
+    files
+}
+
+/// Adds the directory and all directories below it. Directories that are reached twice (through
+/// symlinks) are only added once.
+fn collect_directories(dir: &Path, visited: &mut HashSet<PathBuf>, directories: &mut Vec<PathBuf>) {
+    let Ok(canonical) = dir.canonicalize() else {
+        // Directories that do not exist are stamped too, so creating them invalidates the cache
+        directories.push(dir.to_path_buf());
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+    directories.push(dir.to_path_buf());
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.path().is_dir() {
+            collect_directories(&entry.path(), visited, directories);
+        }
+    }
+}
+
+/// Expands a leading `~` to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
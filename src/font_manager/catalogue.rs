@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use crate::font_manager::font_info::FontInfo;
+use crate::font_manager::sources::FontSourceType;
+
+/// Source independent identity of a face
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FaceKey {
+    /// Face found in a font file, identified by the resolved path and the index of the face in the file
+    File(PathBuf, i32),
+    /// Face registered from memory, identified by the address of its data and the index of the face
+    Memory(usize, i32),
+    /// Face that is not backed by a (known) file, identified by its family and style
+    Described(String, String, u32, u32),
+}
+
+/// A face in the catalogue, combining what every source knows about it
+#[derive(Clone, Debug)]
+pub struct CatalogueEntry {
+    /// Fused font info of the face
+    pub info: FontInfo,
+    /// Sources that are able to serve the face, in order of registration
+    pub sources: Vec<FontSourceType>,
+}
+
+/// Catalogue of all faces of all sources, where faces of the same physical font are merged into a
+/// single entry.
+#[derive(Default)]
+pub struct Catalogue {
+    entries: Vec<CatalogueEntry>,
+    keys: HashMap<FaceKey, usize>,
+    /// Entries by path, for faces that are found without an index
+    paths: HashMap<PathBuf, Vec<usize>>,
+    /// Entries that are not registered from memory, by family and style
+    descriptions: HashMap<FaceKey, Vec<usize>>,
+}
+
+impl Catalogue {
+    /// Adds a face found by a source. When the face is already known, the information is merged into
+    /// the existing entry.
+    pub fn insert(&mut self, info: &FontInfo) {
+        match self.position(info) {
+            Some(idx) => {
+                let entry = &mut self.entries[idx];
+                let had_path = entry.info.path.is_some();
+                merge(&mut entry.info, info);
+                if !entry.sources.contains(&info.source_type) {
+                    entry.sources.push(info.source_type);
+                }
+
+                let fused = &self.entries[idx].info;
+                self.keys.entry(key_for(fused)).or_insert(idx);
+                if let (false, Some(path)) = (had_path, &fused.path) {
+                    self.paths.entry(path.clone()).or_default().push(idx);
+                }
+            }
+            None => {
+                let idx = self.entries.len();
+                self.keys.insert(key_for(info), idx);
+                if let Some(path) = &info.path {
+                    self.paths.entry(path.clone()).or_default().push(idx);
+                }
+                if info.data.is_none() {
+                    self.descriptions.entry(described_key(info)).or_default().push(idx);
+                }
+                self.entries.push(CatalogueEntry {
+                    info: info.clone(),
+                    sources: vec![info.source_type],
+                });
+            }
+        }
+    }
+
+    /// Returns all entries in the catalogue
+    pub fn entries(&self) -> &[CatalogueEntry] {
+        &self.entries
+    }
+
+    /// Returns the catalogue entry of the given face (as found by any source)
+    pub fn get(&self, info: &FontInfo) -> Option<&CatalogueEntry> {
+        self.position(info).map(|idx| &self.entries[idx])
+    }
+
+    /// Finds the entry for the face. Faces without an index are matched on path and description, and
+    /// faces without a path are matched on description only.
+    fn position(&self, info: &FontInfo) -> Option<usize> {
+        // Faces registered from memory are never the same as the faces of other sources
+        if info.data.is_some() {
+            return self.keys.get(&key_for(info)).copied();
+        }
+
+        let candidates = match (&info.path, info.index) {
+            (Some(path), None) => self.paths.get(path),
+            (None, _) => self.descriptions.get(&described_key(info)),
+            _ => None,
+        };
+        let found = candidates
+            .and_then(|indices| indices.iter().copied().find(|&idx| same_description(&self.entries[idx].info, info)));
+
+        found.or_else(|| self.keys.get(&key_for(info)).copied())
+    }
+}
+
+/// Returns the key of the face
+pub(crate) fn key_for(info: &FontInfo) -> FaceKey {
+    if let Some(data) = &info.data {
+        return FaceKey::Memory(data.as_ptr() as usize, info.index.unwrap_or(0));
+    }
+
+    match &info.path {
+        Some(path) => FaceKey::File(path.clone(), info.index.unwrap_or(0)),
+        None => described_key(info),
+    }
+}
+
+/// Returns the key of the face by its family and style
+fn described_key(info: &FontInfo) -> FaceKey {
+    FaceKey::Described(
+        info.family.to_lowercase(),
+        info.style.to_string(),
+        info.weight.round() as u32,
+        (info.stretch * 1000.0).round() as u32,
+    )
+}
+
+/// Returns true when both faces have the same family, style, weight and stretch
+fn same_description(a: &FontInfo, b: &FontInfo) -> bool {
+    a.family.eq_ignore_ascii_case(&b.family)
+        && a.style == b.style
+        && (a.weight - b.weight).abs() < 0.5
+        && (a.stretch - b.stretch).abs() < 0.01
+}
+
+/// Merges the information of another source into the fused font info. Information that is already
+/// known is kept, except for the monospaced flag which not every source is able to detect.
+fn merge(fused: &mut FontInfo, other: &FontInfo) {
+    let (path, index) = (fused.path.clone(), fused.index);
+    if fused.path.is_none() {
+        fused.path = other.path.clone();
+    }
+    if fused.index.is_none() {
+        fused.index = other.index;
+    }
+    fused.monospaced |= other.monospaced;
+
+    // A face that now has a path or an index may have a different id, and may be variable
+    if fused.path != path || fused.index != index {
+        fused.file_data = other.file_data.clone();
+        fused.content_hash = Default::default();
+        fused.id = Default::default();
+        fused.variations = other.variations.clone();
+    }
+}
@@ -0,0 +1,258 @@
+//! Color fonts: the color formats of a face, its CPAL palettes and rasterization of COLR and
+//! OpenType-SVG glyphs. Bitmap glyphs (CBDT and sbix) are rasterized by swash.
+
+use anyhow::anyhow;
+use swash::FontRef;
+use vello::peniko::Color;
+use crate::font_manager::reader::Reader;
+
+mod paint;
+mod svg;
+
+const COLR: u32 = u32::from_be_bytes(*b"COLR");
+const CBDT: u32 = u32::from_be_bytes(*b"CBDT");
+const SBIX: u32 = u32::from_be_bytes(*b"sbix");
+const SVG: u32 = u32::from_be_bytes(*b"SVG ");
+
+/// Palette index in COLR glyphs that stands for the text color
+pub const FOREGROUND_INDEX: u16 = 0xFFFF;
+/// Largest width or height (in pixels) of a color glyph that is rasterized
+const MAX_SIZE: f32 = 4096.0;
+
+/// Color glyph formats found in a face
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ColorFormats {
+    /// Layered glyphs with solid colors (COLR version 0)
+    pub colr_v0: bool,
+    /// Paint graphs with gradients, transforms and compositing (COLR version 1)
+    pub colr_v1: bool,
+    /// Embedded bitmaps (CBDT/CBLC)
+    pub cbdt: bool,
+    /// Embedded bitmaps (sbix)
+    pub sbix: bool,
+    /// SVG documents (OpenType-SVG)
+    pub svg: bool,
+}
+
+impl ColorFormats {
+    /// Finds the color tables of the font
+    pub fn from_font(font: &FontRef) -> Self {
+        let (colr_v0, colr_v1) = match font.table(COLR) {
+            Some(data) => colr_versions(data).unwrap_or_default(),
+            None => (false, false),
+        };
+
+        Self {
+            colr_v0,
+            colr_v1,
+            cbdt: font.table(CBDT).is_some(),
+            sbix: font.table(SBIX).is_some(),
+            svg: font.table(SVG).is_some(),
+        }
+    }
+
+    /// Returns true when the face has glyphs in any color format
+    pub fn is_color(&self) -> bool {
+        self.colr_v0 || self.colr_v1 || self.cbdt || self.sbix || self.svg
+    }
+
+    /// Returns true when the face has glyphs that are painted with palette colors
+    pub fn has_colr(&self) -> bool {
+        self.colr_v0 || self.colr_v1
+    }
+}
+
+/// Returns whether the COLR table has version 0 (base glyph records) and version 1 (base glyph list)
+/// glyphs
+fn colr_versions(data: &[u8]) -> Result<(bool, bool), anyhow::Error> {
+    let mut reader = Reader::new(data);
+    let version = reader.u16()?;
+    let num_base_glyphs = reader.u16()?;
+    if version == 0 {
+        return Ok((num_base_glyphs > 0, false));
+    }
+
+    // Skip the version 0 offsets and the layer record count
+    reader.skip(10)?;
+    let base_glyph_list = reader.u32()?;
+    Ok((num_base_glyphs > 0, base_glyph_list != 0))
+}
+
+/// Background a palette is designed for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteUsability {
+    Light,
+    Dark,
+    Both,
+}
+
+/// Color palette from the CPAL table
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub index: u16,
+    pub name: Option<String>,
+    /// Background the palette is designed for, when the font says so
+    pub usability: Option<PaletteUsability>,
+    pub colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn is_light(&self) -> bool {
+        matches!(self.usability, Some(PaletteUsability::Light | PaletteUsability::Both))
+    }
+
+    pub fn is_dark(&self) -> bool {
+        matches!(self.usability, Some(PaletteUsability::Dark | PaletteUsability::Both))
+    }
+}
+
+/// Color formats and palettes of a face
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColorInfo {
+    pub formats: ColorFormats,
+    pub palettes: Vec<Palette>,
+}
+
+impl ColorInfo {
+    /// Reads the color tables and the CPAL palettes of the font
+    pub fn from_font(font: &FontRef) -> Self {
+        let palettes = font.color_palettes()
+            .map(|palette| Palette {
+                index: palette.index(),
+                name: palette.name(Some("en")).or_else(|| palette.name(None)).map(|n| n.chars().collect()),
+                usability: palette.usability().map(|usability| match usability {
+                    swash::Usability::Light => PaletteUsability::Light,
+                    swash::Usability::Dark => PaletteUsability::Dark,
+                    swash::Usability::Both => PaletteUsability::Both,
+                }),
+                colors: (0..palette.len())
+                    .map(|idx| {
+                        let [r, g, b, a] = palette.get(idx);
+                        Color::rgba8(r, g, b, a)
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            formats: ColorFormats::from_font(font),
+            palettes,
+        }
+    }
+}
+
+/// Palette to start from, as selected by CSS `font-palette` (or the `base-palette` of a
+/// `@font-palette-values` rule)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BasePalette {
+    /// The first palette of the font
+    #[default]
+    Normal,
+    /// The first palette for light backgrounds, or the first palette when there is none
+    Light,
+    /// The first palette for dark backgrounds, or the first palette when there is none
+    Dark,
+    /// The palette with this index, or the first palette when there is none
+    Index(u16),
+}
+
+/// Palette selection for color glyphs, like a `@font-palette-values` rule: a base palette with some
+/// of its colors overridden (`override-colors`)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FontPalette {
+    pub base: BasePalette,
+    /// Colors that replace the color at the index of the base palette
+    pub overrides: Vec<(u16, Color)>,
+}
+
+impl FontPalette {
+    pub fn new(base: BasePalette) -> Self {
+        Self { base, overrides: vec![] }
+    }
+
+    /// Adds an override for the color at the index
+    pub fn with_override(mut self, index: u16, color: Color) -> Self {
+        self.overrides.retain(|(idx, _)| *idx != index);
+        self.overrides.push((index, color));
+        self
+    }
+
+    /// Returns the colors of the selected palette with the overrides applied
+    pub fn resolve(&self, palettes: &[Palette]) -> Vec<Color> {
+        let palette = match self.base {
+            BasePalette::Normal => palettes.first(),
+            BasePalette::Light => palettes.iter().find(|p| p.is_light()).or(palettes.first()),
+            BasePalette::Dark => palettes.iter().find(|p| p.is_dark()).or(palettes.first()),
+            BasePalette::Index(idx) => palettes.get(idx as usize).or(palettes.first()),
+        };
+
+        let mut colors = palette.map(|p| p.colors.clone()).unwrap_or_default();
+        for (idx, color) in &self.overrides {
+            if let Some(c) = colors.get_mut(*idx as usize) {
+                *c = *color;
+            }
+        }
+        colors
+    }
+}
+
+/// Rasterized color glyph, in RGBA (not premultiplied)
+#[derive(Clone, Debug)]
+pub struct ColorImage {
+    pub width: u32,
+    pub height: u32,
+    /// Offset from the glyph origin to the left edge of the image
+    pub left: i32,
+    /// Offset from the baseline up to the top edge of the image
+    pub top: i32,
+    pub data: Vec<u8>,
+}
+
+/// Rasterizes a COLR or OpenType-SVG glyph with the colors of the palette (COLRv1 is preferred, then
+/// COLRv0, then SVG). The glyph origin is moved right by the offset (in pixels). Returns None when
+/// the glyph is not a color glyph in these formats, or has no area.
+pub fn rasterize_color_glyph(font: &FontRef, coords: &[i16], glyph_id: u16, size: f32, offset: f32, palette: &[Color], foreground: Color) -> Result<Option<ColorImage>, anyhow::Error> {
+    let index = face_index(font)?;
+    let skrifa_font = skrifa::FontRef::from_index(font.data, index)
+        .map_err(|e| anyhow!("Unable to parse font data: {}", e))?;
+
+    if let Some(image) = paint::rasterize(&skrifa_font, coords, glyph_id, size, offset, palette, foreground) {
+        return Ok(Some(image));
+    }
+
+    if let Some(data) = font.table(SVG) {
+        return svg::rasterize(data, font.metrics(&[]).units_per_em, glyph_id, size, offset);
+    }
+
+    Ok(None)
+}
+
+/// Returns the index of the face in its font data (0 for files that are not a collection)
+fn face_index(font: &FontRef) -> Result<u32, anyhow::Error> {
+    if font.offset == 0 {
+        return Ok(0);
+    }
+
+    let mut reader = Reader::at(font.data, 8)?;
+    let num_fonts = reader.u32()?;
+    for idx in 0..num_fonts {
+        if reader.u32()? == font.offset {
+            return Ok(idx);
+        }
+    }
+
+    Err(anyhow!("Face at offset {} is not found in the font collection", font.offset))
+}
+
+/// Converts a premultiplied RGBA pixmap into a color image
+fn demultiply(width: u32, height: u32, left: i32, top: i32, data: &[u8]) -> ColorImage {
+    let data = data.chunks_exact(4)
+        .flat_map(|pixel| {
+            let a = pixel[3] as u32;
+            let unpremultiply = |c: u8| (c as u32 * 255 + a / 2).checked_div(a).map_or(0, |c| c.min(255) as u8);
+            [unpremultiply(pixel[0]), unpremultiply(pixel[1]), unpremultiply(pixel[2]), pixel[3]]
+        })
+        .collect();
+
+    ColorImage { width, height, left, top, data }
+}
@@ -0,0 +1,449 @@
+use resvg::tiny_skia::{
+    BlendMode, FillRule, GradientStop, LinearGradient, Mask, Paint, Path, PathBuilder, Pixmap, PixmapPaint,
+    Point, Rect, Shader, SpreadMode, Transform,
+};
+use skrifa::color::{Brush, ColorPainter, ColorStop, CompositeMode, Extend};
+use skrifa::instance::{LocationRef, NormalizedCoord, Size};
+use skrifa::outline::{DrawSettings, OutlinePen};
+use skrifa::raw::types::BoundingBox;
+use skrifa::raw::TableProvider;
+use skrifa::{FontRef, GlyphId, MetadataProvider, OutlineGlyphCollection};
+use vello::peniko::Color;
+use crate::font_manager::color::{demultiply, ColorImage, FOREGROUND_INDEX, MAX_SIZE};
+
+/// Paints the COLR glyph (version 1 or 0) with tiny-skia
+pub(super) fn rasterize(font: &FontRef, coords: &[i16], glyph_id: u16, size: f32, offset: f32, palette: &[Color], foreground: Color) -> Option<ColorImage> {
+    let glyph = font.color_glyphs().get(GlyphId::new(glyph_id as u32))?;
+    let coords: Vec<NormalizedCoord> = coords.iter().map(|c| NormalizedCoord::from_bits(*c)).collect();
+    let location = LocationRef::new(&coords);
+    let outlines = font.outline_glyphs();
+
+    // Font units (y up) to pixels (y down), with the glyph origin at (offset, 0)
+    let scale = size / font.head().ok()?.units_per_em() as f32;
+    let base = Transform::from_row(scale, 0.0, 0.0, -scale, offset, 0.0);
+
+    // The first pass finds the area that is painted, the second pass paints it
+    let mut bounds = BoundsPainter {
+        outlines: &outlines,
+        location,
+        transforms: vec![base],
+        clips: vec![],
+        bounds: None,
+    };
+    glyph.paint(location, &mut bounds).ok()?;
+    let bounds = bounds.bounds?;
+    if bounds.width() > MAX_SIZE || bounds.height() > MAX_SIZE {
+        return None;
+    }
+
+    let left = bounds.left().floor() as i32;
+    let top = bounds.top().floor() as i32;
+    let width = (bounds.right().ceil() as i32 - left) as u32;
+    let height = (bounds.bottom().ceil() as i32 - top) as u32;
+
+    let mut painter = Painter {
+        outlines: &outlines,
+        location,
+        palette,
+        foreground,
+        transforms: vec![base.post_translate(-left as f32, -top as f32)],
+        clips: vec![],
+        layers: vec![(Pixmap::new(width, height)?, CompositeMode::SrcOver)],
+    };
+    glyph.paint(location, &mut painter).ok()?;
+    let (pixmap, _) = painter.layers.pop()?;
+
+    Some(demultiply(width, height, left, -top, pixmap.data()))
+}
+
+struct PathPen(PathBuilder);
+
+impl OutlinePen for PathPen {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+        self.0.quad_to(cx0, cy0, x, y);
+    }
+
+    fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+        self.0.cubic_to(cx0, cy0, cx1, cy1, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Returns the outline of the glyph in font units
+fn glyph_path(outlines: &OutlineGlyphCollection, location: LocationRef, glyph_id: GlyphId) -> Option<Path> {
+    let glyph = outlines.get(glyph_id)?;
+    let mut pen = PathPen(PathBuilder::new());
+    glyph.draw(DrawSettings::unhinted(Size::unscaled(), location), &mut pen).ok()?;
+    pen.0.finish()
+}
+
+fn box_path(clip_box: BoundingBox<f32>) -> Option<Path> {
+    let rect = Rect::from_ltrb(clip_box.x_min, clip_box.y_min, clip_box.x_max, clip_box.y_max)?;
+    Some(PathBuilder::from_rect(rect))
+}
+
+fn to_transform(t: skrifa::color::Transform) -> Transform {
+    Transform::from_row(t.xx, t.yx, t.xy, t.yy, t.dx, t.dy)
+}
+
+fn union(a: Option<Rect>, b: Rect) -> Option<Rect> {
+    match a {
+        Some(a) => Rect::from_ltrb(a.left().min(b.left()), a.top().min(b.top()), a.right().max(b.right()), a.bottom().max(b.bottom())),
+        None => Some(b),
+    }
+}
+
+/// Collects the bounds (in pixels) of everything that is filled
+struct BoundsPainter<'a, 'b> {
+    outlines: &'b OutlineGlyphCollection<'a>,
+    location: LocationRef<'a>,
+    transforms: Vec<Transform>,
+    /// Bounds of the clips, None for clips that are empty
+    clips: Vec<Option<Rect>>,
+    bounds: Option<Rect>,
+}
+
+impl BoundsPainter<'_, '_> {
+    fn push_clip_path(&mut self, path: Option<Path>) {
+        let transform = *self.transforms.last().unwrap();
+        let rect = path.and_then(|path| path.transform(transform)).map(|path| path.bounds());
+        let rect = match (self.clips.last(), rect) {
+            (Some(Some(outer)), Some(rect)) => outer.intersect(&rect),
+            (Some(None), _) => None,
+            (None, rect) => rect,
+            (_, None) => None,
+        };
+        self.clips.push(rect);
+    }
+}
+
+impl ColorPainter for BoundsPainter<'_, '_> {
+    fn push_transform(&mut self, transform: skrifa::color::Transform) {
+        let current = *self.transforms.last().unwrap();
+        self.transforms.push(current.pre_concat(to_transform(transform)));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn push_clip_glyph(&mut self, glyph_id: GlyphId) {
+        let path = glyph_path(self.outlines, self.location, glyph_id);
+        self.push_clip_path(path);
+    }
+
+    fn push_clip_box(&mut self, clip_box: BoundingBox<f32>) {
+        self.push_clip_path(box_path(clip_box));
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    fn fill(&mut self, _brush: Brush<'_>) {
+        // Fills without a clip cover everything, which can not be bounded
+        if let Some(Some(rect)) = self.clips.last() {
+            self.bounds = union(self.bounds, *rect);
+        }
+    }
+
+    fn push_layer(&mut self, _composite_mode: CompositeMode) {}
+
+    fn pop_layer(&mut self) {}
+}
+
+/// Paints COLR glyphs onto a pixmap. Layers are painted on pixmaps of their own and composited
+/// onto the layer below when they are popped.
+struct Painter<'a, 'b> {
+    outlines: &'b OutlineGlyphCollection<'a>,
+    location: LocationRef<'a>,
+    palette: &'b [Color],
+    foreground: Color,
+    transforms: Vec<Transform>,
+    clips: Vec<Option<Mask>>,
+    layers: Vec<(Pixmap, CompositeMode)>,
+}
+
+impl Painter<'_, '_> {
+    fn color(&self, palette_index: u16, alpha: f32) -> resvg::tiny_skia::Color {
+        let color = match palette_index {
+            FOREGROUND_INDEX => self.foreground,
+            idx => self.palette.get(idx as usize).copied().unwrap_or(self.foreground),
+        };
+        let alpha = (color.a as f32 / 255.0 * alpha).clamp(0.0, 1.0);
+        resvg::tiny_skia::Color::from_rgba(color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0, alpha)
+            .unwrap_or(resvg::tiny_skia::Color::TRANSPARENT)
+    }
+
+    fn stops(&self, color_stops: &[ColorStop]) -> Vec<GradientStop> {
+        color_stops.iter()
+            .map(|stop| GradientStop::new(stop.offset, self.color(stop.palette_index, stop.alpha)))
+            .collect()
+    }
+
+    /// Returns the color on the color line at t, with the extend mode applied
+    fn color_at(&self, color_stops: &[ColorStop], extend: Extend, t: f32) -> resvg::tiny_skia::Color {
+        let t = match extend {
+            Extend::Repeat => t - t.floor(),
+            Extend::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 { 2.0 - t } else { t }
+            }
+            _ => t.clamp(0.0, 1.0),
+        };
+
+        let Some(first) = color_stops.first() else {
+            return resvg::tiny_skia::Color::TRANSPARENT;
+        };
+        let mut prev = first;
+        for stop in color_stops {
+            if t <= stop.offset {
+                let a = self.color(prev.palette_index, prev.alpha);
+                let b = self.color(stop.palette_index, stop.alpha);
+                let range = stop.offset - prev.offset;
+                let f = if range > 0.0 { (t - prev.offset) / range } else { 1.0 };
+                let mix = |a: f32, b: f32| a + (b - a) * f;
+                return resvg::tiny_skia::Color::from_rgba(mix(a.red(), b.red()), mix(a.green(), b.green()), mix(a.blue(), b.blue()), mix(a.alpha(), b.alpha()))
+                    .unwrap_or(b);
+            }
+            prev = stop;
+        }
+        self.color(prev.palette_index, prev.alpha)
+    }
+
+    fn push_clip_path(&mut self, path: Option<Path>) {
+        let transform = *self.transforms.last().unwrap();
+        let (width, height) = {
+            let (pixmap, _) = self.layers.last().unwrap();
+            (pixmap.width(), pixmap.height())
+        };
+
+        let mask = path.and_then(|path| match self.clips.last() {
+            Some(Some(outer)) => {
+                let mut mask = outer.clone();
+                mask.intersect_path(&path, FillRule::Winding, true, transform);
+                Some(mask)
+            }
+            Some(None) => None,
+            None => {
+                let mut mask = Mask::new(width, height)?;
+                mask.fill_path(&path, FillRule::Winding, true, transform);
+                Some(mask)
+            }
+        });
+        self.clips.push(mask);
+    }
+
+    /// Fills the current clip with a gradient that is computed for every pixel: sweep gradients, which
+    /// tiny-skia does not have, and radial gradients between two circles, where tiny-skia only has
+    /// gradients that start at a point. The function returns the position on the color line for a
+    /// point in font units, or None when the point is not painted.
+    fn fill_gradient(&mut self, color_stops: &[ColorStop], extend: Extend, position: impl Fn(Point) -> Option<f32>) {
+        let Some(inverse) = self.transforms.last().unwrap().invert() else {
+            return;
+        };
+        let (width, height) = {
+            let (pixmap, _) = self.layers.last().unwrap();
+            (pixmap.width(), pixmap.height())
+        };
+        let Some(mut gradient) = Pixmap::new(width, height) else {
+            return;
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut p = Point::from_xy(x as f32 + 0.5, y as f32 + 0.5);
+                inverse.map_point(&mut p);
+                let Some(t) = position(p) else {
+                    continue;
+                };
+                let color = self.color_at(color_stops, extend, t).premultiply().to_color_u8();
+                let idx = ((y * width + x) * 4) as usize;
+                gradient.data_mut()[idx..idx + 4].copy_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+            }
+        }
+
+        let mask = self.clips.last().and_then(|mask| mask.as_ref());
+        let (pixmap, _) = self.layers.last_mut().unwrap();
+        pixmap.draw_pixmap(0, 0, gradient.as_ref(), &PixmapPaint::default(), Transform::identity(), mask);
+    }
+}
+
+/// Returns the position on the color line of a sweep gradient. Angles are clockwise in font units.
+fn sweep_position(p: Point, center: Point, start_angle: f32, end_angle: f32) -> Option<f32> {
+    let angle = (360.0 - (p.y - center.y).atan2(p.x - center.x).to_degrees()).rem_euclid(360.0);
+    let sector = end_angle - start_angle;
+    Some(if sector != 0.0 { (angle - start_angle) / sector } else { 0.0 })
+}
+
+/// Returns the largest t for which the point lies on the circle interpolated between the two circles
+/// (with a radius that is not negative), as in the two point conical gradients of CSS and COLR
+fn radial_position(p: Point, c0: Point, r0: f32, c1: Point, r1: f32) -> Option<f32> {
+    let (cdx, cdy) = (c1.x - c0.x, c1.y - c0.y);
+    let (pdx, pdy) = (p.x - c0.x, p.y - c0.y);
+    let dr = r1 - r0;
+
+    let a = cdx * cdx + cdy * cdy - dr * dr;
+    let b = pdx * cdx + pdy * cdy + r0 * dr;
+    let c = pdx * pdx + pdy * pdy - r0 * r0;
+    let valid = |t: f32| r0 + t * dr >= 0.0;
+
+    if a.abs() < f32::EPSILON {
+        if b.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = c / (2.0 * b);
+        return valid(t).then_some(t);
+    }
+
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t1, t2) = ((b + root) / a, (b - root) / a);
+    let (high, low) = (t1.max(t2), t1.min(t2));
+    if valid(high) {
+        Some(high)
+    } else if valid(low) {
+        Some(low)
+    } else {
+        None
+    }
+}
+
+impl ColorPainter for Painter<'_, '_> {
+    fn push_transform(&mut self, transform: skrifa::color::Transform) {
+        let current = *self.transforms.last().unwrap();
+        self.transforms.push(current.pre_concat(to_transform(transform)));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn push_clip_glyph(&mut self, glyph_id: GlyphId) {
+        let path = glyph_path(self.outlines, self.location, glyph_id);
+        self.push_clip_path(path);
+    }
+
+    fn push_clip_box(&mut self, clip_box: BoundingBox<f32>) {
+        self.push_clip_path(box_path(clip_box));
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    fn fill(&mut self, brush: Brush<'_>) {
+        // An empty clip paints nothing
+        if let Some(None) = self.clips.last() {
+            return;
+        }
+
+        let transform = *self.transforms.last().unwrap();
+        let shader = match brush {
+            Brush::Solid { palette_index, alpha } => Some(Shader::SolidColor(self.color(palette_index, alpha))),
+            Brush::LinearGradient { p0, p1, color_stops, extend } => LinearGradient::new(
+                Point::from_xy(p0.x, p0.y),
+                Point::from_xy(p1.x, p1.y),
+                self.stops(color_stops),
+                spread_mode(extend),
+                transform,
+            ),
+            Brush::RadialGradient { c0, r0, c1, r1, color_stops, extend } => {
+                let (c0, c1) = (Point::from_xy(c0.x, c0.y), Point::from_xy(c1.x, c1.y));
+                self.fill_gradient(color_stops, extend, |p| radial_position(p, c0, r0, c1, r1));
+                return;
+            }
+            Brush::SweepGradient { c0, start_angle, end_angle, color_stops, extend } => {
+                let center = Point::from_xy(c0.x, c0.y);
+                self.fill_gradient(color_stops, extend, |p| sweep_position(p, center, start_angle, end_angle));
+                return;
+            }
+        };
+        let Some(shader) = shader else {
+            return;
+        };
+
+        let paint = Paint { shader, anti_alias: true, ..Default::default() };
+        let mask = self.clips.last().and_then(|mask| mask.as_ref());
+        let (pixmap, _) = self.layers.last_mut().unwrap();
+        let Some(rect) = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32) else {
+            return;
+        };
+        pixmap.fill_rect(rect, &paint, Transform::identity(), mask);
+    }
+
+    fn push_layer(&mut self, composite_mode: CompositeMode) {
+        let (pixmap, _) = self.layers.last().unwrap();
+        match Pixmap::new(pixmap.width(), pixmap.height()) {
+            Some(layer) => self.layers.push((layer, composite_mode)),
+            None => self.layers.push((pixmap.clone(), composite_mode)),
+        }
+    }
+
+    fn pop_layer(&mut self) {
+        if self.layers.len() < 2 {
+            return;
+        }
+        let (layer, composite_mode) = self.layers.pop().unwrap();
+        let paint = PixmapPaint { blend_mode: blend_mode(composite_mode), ..Default::default() };
+        let mask = self.clips.last().and_then(|mask| mask.as_ref());
+        let (pixmap, _) = self.layers.last_mut().unwrap();
+        pixmap.draw_pixmap(0, 0, layer.as_ref(), &paint, Transform::identity(), mask);
+    }
+}
+
+fn spread_mode(extend: Extend) -> SpreadMode {
+    match extend {
+        Extend::Repeat => SpreadMode::Repeat,
+        Extend::Reflect => SpreadMode::Reflect,
+        _ => SpreadMode::Pad,
+    }
+}
+
+fn blend_mode(composite_mode: CompositeMode) -> BlendMode {
+    match composite_mode {
+        CompositeMode::Clear => BlendMode::Clear,
+        CompositeMode::Src => BlendMode::Source,
+        CompositeMode::Dest => BlendMode::Destination,
+        CompositeMode::SrcOver => BlendMode::SourceOver,
+        CompositeMode::DestOver => BlendMode::DestinationOver,
+        CompositeMode::SrcIn => BlendMode::SourceIn,
+        CompositeMode::DestIn => BlendMode::DestinationIn,
+        CompositeMode::SrcOut => BlendMode::SourceOut,
+        CompositeMode::DestOut => BlendMode::DestinationOut,
+        CompositeMode::SrcAtop => BlendMode::SourceAtop,
+        CompositeMode::DestAtop => BlendMode::DestinationAtop,
+        CompositeMode::Xor => BlendMode::Xor,
+        CompositeMode::Plus => BlendMode::Plus,
+        CompositeMode::Screen => BlendMode::Screen,
+        CompositeMode::Overlay => BlendMode::Overlay,
+        CompositeMode::Darken => BlendMode::Darken,
+        CompositeMode::Lighten => BlendMode::Lighten,
+        CompositeMode::ColorDodge => BlendMode::ColorDodge,
+        CompositeMode::ColorBurn => BlendMode::ColorBurn,
+        CompositeMode::HardLight => BlendMode::HardLight,
+        CompositeMode::SoftLight => BlendMode::SoftLight,
+        CompositeMode::Difference => BlendMode::Difference,
+        CompositeMode::Exclusion => BlendMode::Exclusion,
+        CompositeMode::Multiply => BlendMode::Multiply,
+        CompositeMode::HslHue => BlendMode::Hue,
+        CompositeMode::HslSaturation => BlendMode::Saturation,
+        CompositeMode::HslColor => BlendMode::Color,
+        CompositeMode::HslLuminosity => BlendMode::Luminosity,
+        _ => BlendMode::SourceOver,
+    }
+}
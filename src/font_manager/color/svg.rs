@@ -0,0 +1,148 @@
+use std::io::Read;
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg;
+use crate::font_manager::color::{demultiply, ColorImage, MAX_SIZE};
+use crate::font_manager::reader::Reader;
+
+/// Largest size (in bytes) of a decompressed SVG document
+const MAX_DOCUMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Renders the glyph from its document in the SVG table. Documents are in font units with the y
+/// axis pointing down, and the glyph origin at (0, 0).
+pub(super) fn rasterize(table: &[u8], units_per_em: u16, glyph_id: u16, size: f32, offset: f32) -> Result<Option<ColorImage>, anyhow::Error> {
+    let Some((document, single)) = find_document(table, glyph_id)? else {
+        return Ok(None);
+    };
+    let document = decompress(document)?;
+    let tree = usvg::Tree::from_data(&document, &usvg::Options::default())
+        .map_err(|e| anyhow!("Unable to parse SVG document of glyph {}: {}", glyph_id, e))?;
+
+    // Documents can hold several glyphs, each in an element with the id "glyph<id>". A document with
+    // a single glyph may leave out the id.
+    let node = tree.node_by_id(&format!("glyph{}", glyph_id));
+    let bounds = match node {
+        Some(node) => node.abs_layer_bounding_box(),
+        None if single => Some(tree.root().abs_layer_bounding_box()),
+        None => return Ok(None),
+    };
+    let Some(bounds) = bounds else {
+        return Ok(None);
+    };
+
+    let scale = size / units_per_em as f32;
+    if bounds.width() * scale > MAX_SIZE || bounds.height() * scale > MAX_SIZE {
+        return Ok(None);
+    }
+
+    let left = (bounds.left() * scale + offset).floor() as i32;
+    let top = (bounds.top() * scale).floor() as i32;
+    let width = ((bounds.right() * scale + offset).ceil() as i32 - left) as u32;
+    let height = ((bounds.bottom() * scale).ceil() as i32 - top) as u32;
+    let Some(mut pixmap) = Pixmap::new(width, height) else {
+        return Ok(None);
+    };
+
+    let transform = Transform::from_row(scale, 0.0, 0.0, scale, offset - left as f32, -top as f32);
+    match node {
+        Some(node) => {
+            // The node is rendered with its own transform and moved to the top left of its bounds, so
+            // the transforms of its ancestors and the move are added here
+            let own = match node {
+                usvg::Node::Group(group) => group.transform(),
+                _ => Transform::identity(),
+            };
+            let parent = node.abs_transform().pre_concat(own.invert().unwrap_or_default());
+            let transform = transform.pre_concat(parent).pre_translate(bounds.left(), bounds.top());
+            resvg::render_node(node, transform, &mut pixmap.as_mut());
+        }
+        None => resvg::render(&tree, transform, &mut pixmap.as_mut()),
+    }
+
+    Ok(Some(demultiply(width, height, left, -top, pixmap.data())))
+}
+
+/// Returns the document with the glyph, and whether it is the only glyph of the document
+fn find_document(table: &[u8], glyph_id: u16) -> Result<Option<(&[u8], bool)>, anyhow::Error> {
+    let mut reader = Reader::new(table);
+    let _version = reader.u16()?;
+    let list_offset = reader.u32()? as usize;
+
+    let mut reader = Reader::at(table, list_offset)?;
+    let num_entries = reader.u16()?;
+    for _ in 0..num_entries {
+        let start = reader.u16()?;
+        let end = reader.u16()?;
+        let doc_offset = reader.u32()? as usize;
+        let doc_length = reader.u32()? as usize;
+
+        if (start..=end).contains(&glyph_id) {
+            let document = Reader::at(table, list_offset + doc_offset)?.bytes(doc_length)?;
+            return Ok(Some((document, start == end)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Documents may be gzip compressed. Decompressed documents larger than `MAX_DOCUMENT_SIZE` are
+/// rejected.
+fn decompress(document: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    if !document.starts_with(&[0x1f, 0x8b]) {
+        return Ok(document.to_vec());
+    }
+
+    let mut data = Vec::new();
+    GzDecoder::new(document).take(MAX_DOCUMENT_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_DOCUMENT_SIZE {
+        return Err(anyhow!("SVG document is larger than {} bytes when decompressed", MAX_DOCUMENT_SIZE));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// SVG table with a single document for glyph 1
+    fn table(document: &[u8]) -> Vec<u8> {
+        let mut table = vec![];
+        table.extend(0u16.to_be_bytes());
+        table.extend(10u32.to_be_bytes());
+        table.extend(0u32.to_be_bytes());
+        table.extend(1u16.to_be_bytes());
+        table.extend(1u16.to_be_bytes());
+        table.extend(1u16.to_be_bytes());
+        table.extend(14u32.to_be_bytes());
+        table.extend((document.len() as u32).to_be_bytes());
+        table.extend(document);
+        table
+    }
+
+    const DOCUMENT: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg"><rect id="glyph1" x="0" y="-800" width="500" height="800" fill="red"/></svg>"#;
+
+    #[test]
+    fn glyphs_are_rendered_up_to_the_maximum_size() {
+        let image = rasterize(&table(DOCUMENT), 1000, 1, 20.0, 0.0).unwrap().unwrap();
+        assert_eq!((image.width, image.height, image.top), (10, 16, 16));
+
+        assert!(rasterize(&table(DOCUMENT), 1000, 1, 100_000.0, 0.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn compressed_documents_are_limited_in_size() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(DOCUMENT).unwrap();
+        let document = encoder.finish().unwrap();
+        assert!(rasterize(&table(&document), 1000, 1, 20.0, 0.0).unwrap().is_some());
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&vec![b' '; MAX_DOCUMENT_SIZE as usize + 1]).unwrap();
+        let document = encoder.finish().unwrap();
+        assert!(rasterize(&table(&document), 1000, 1, 20.0, 0.0).is_err());
+    }
+}
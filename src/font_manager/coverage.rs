@@ -0,0 +1,67 @@
+use std::ops::RangeInclusive;
+use swash::FontRef;
+
+/// Set of unicode codepoints that a font face can render, stored as sorted, non-overlapping ranges
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl Coverage {
+    /// Creates the coverage from the character map (cmap table) of the given font
+    pub fn from_font(font: &FontRef) -> Self {
+        let mut codepoints = Vec::new();
+        font.charmap().enumerate(|cp, glyph_id| {
+            if glyph_id != 0 {
+                codepoints.push(cp);
+            }
+        });
+
+        Self::from_codepoints(codepoints)
+    }
+
+    /// Creates the coverage from a list of codepoints in any order
+    pub fn from_codepoints(mut codepoints: Vec<u32>) -> Self {
+        codepoints.sort_unstable();
+        codepoints.dedup();
+
+        let mut ranges: Vec<RangeInclusive<u32>> = Vec::new();
+        for cp in codepoints {
+            match ranges.last_mut() {
+                Some(range) if *range.end() + 1 == cp => *range = *range.start()..=cp,
+                _ => ranges.push(cp..=cp),
+            }
+        }
+
+        Self { ranges }
+    }
+
+    /// Returns true when the codepoint is covered
+    pub fn contains(&self, cp: u32) -> bool {
+        self.ranges
+            .binary_search_by(|range| {
+                if *range.end() < cp {
+                    std::cmp::Ordering::Less
+                } else if *range.start() > cp {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    /// Returns the covered codepoint ranges
+    pub fn ranges(&self) -> &[RangeInclusive<u32>] {
+        &self.ranges
+    }
+
+    /// Returns the number of covered codepoints
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|r| (r.end() - r.start() + 1) as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
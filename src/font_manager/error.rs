@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+use crate::font_manager::sources::FontSourceType;
+
+/// Errors that can occur while loading fonts
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontError {
+    /// The font backend of a source could not be initialized
+    BackendInit { source_type: FontSourceType, reason: String },
+    /// A font file could not be read
+    UnreadableFile { path: PathBuf, reason: String },
+    /// The font data is not in a format that is supported, or is corrupt
+    UnsupportedFormat { path: Option<PathBuf>, reason: String },
+    /// The requested face does not exist in the font file or collection
+    FaceIndexOutOfRange { path: Option<PathBuf>, index: usize },
+}
+
+impl std::fmt::Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FontError::BackendInit { source_type, reason } => {
+                write!(f, "Unable to initialize font source {:?}: {}", source_type, reason)
+            }
+            FontError::UnreadableFile { path, reason } => {
+                write!(f, "Unable to read font file {}: {}", path.display(), reason)
+            }
+            FontError::UnsupportedFormat { path: Some(path), reason } => {
+                write!(f, "Unsupported font format in {}: {}", path.display(), reason)
+            }
+            FontError::UnsupportedFormat { path: None, reason } => {
+                write!(f, "Unsupported font format: {}", reason)
+            }
+            FontError::FaceIndexOutOfRange { path: Some(path), index } => {
+                write!(f, "Face index {} is out of range in {}", index, path.display())
+            }
+            FontError::FaceIndexOutOfRange { path: None, index } => {
+                write!(f, "Face index {} is out of range", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
@@ -0,0 +1,89 @@
+use std::ops::Range;
+use crate::font_manager::font_info::FontInfo;
+
+/// A run of text that is rendered with a single font
+#[derive(Clone, Debug)]
+pub struct FontRun {
+    /// Byte range of the run in the original text
+    pub range: Range<usize>,
+    /// Font that is used for this run, or None when no font could render the characters
+    pub font: Option<FontInfo>,
+}
+
+/// Resolves which font must be used for each character in a text. Fonts are tried in order, and
+/// the first font whose character map contains the codepoint is selected.
+pub struct FallbackResolver {
+    /// Fonts to try, in order of preference
+    candidates: Vec<FontInfo>,
+}
+
+impl FallbackResolver {
+    pub fn new(candidates: Vec<FontInfo>) -> Self {
+        Self {
+            candidates,
+        }
+    }
+
+    /// Splits the text into runs that can be rendered by a single font
+    pub fn resolve(&self, text: &str) -> Vec<FontRun> {
+        let mut runs: Vec<(Range<usize>, Option<usize>)> = Vec::new();
+
+        for (offset, ch) in text.char_indices() {
+            let end = offset + ch.len_utf8();
+
+            // Characters that do not need a glyph of their own stick with the current run when possible
+            if let Some((range, current)) = runs.last_mut() {
+                if is_cluster_continuation(ch) && current.is_none_or(|idx| self.has_char(idx, ch) || is_invisible(ch)) {
+                    range.end = end;
+                    continue;
+                }
+            }
+
+            let font = self.find_font(ch);
+            match runs.last_mut() {
+                Some((range, current)) if *current == font => range.end = end,
+                _ => runs.push((offset..end, font)),
+            }
+        }
+
+        runs.into_iter()
+            .map(|(range, idx)| FontRun {
+                range,
+                font: idx.map(|idx| self.candidates[idx].clone()),
+            })
+            .collect()
+    }
+
+    /// Returns the index of the first candidate that can render the given character
+    fn find_font(&self, ch: char) -> Option<usize> {
+        self.candidates.iter().position(|fi| fi.supports_char(ch))
+    }
+
+    /// Returns true when the candidate's character map contains the given character
+    fn has_char(&self, idx: usize, ch: char) -> bool {
+        self.candidates[idx].supports_char(ch)
+    }
+}
+
+/// Returns true when the character belongs to the cluster of the previous character, so it
+/// should not start a run in another font.
+fn is_cluster_continuation(ch: char) -> bool {
+    ch.is_whitespace() || ch.is_control() || is_invisible(ch) || matches!(ch as u32,
+        0x0300..=0x036F |       // Combining diacritical marks
+        0x1AB0..=0x1AFF |       // Combining diacritical marks extended
+        0x1DC0..=0x1DFF |       // Combining diacritical marks supplement
+        0x20D0..=0x20FF |       // Combining diacritical marks for symbols
+        0xFE20..=0xFE2F |       // Combining half marks
+        0x1F3FB..=0x1F3FF       // Emoji skin tone modifiers
+    )
+}
+
+/// Returns true for format characters that are never drawn by themselves
+fn is_invisible(ch: char) -> bool {
+    matches!(ch as u32,
+        0x200B..=0x200F |       // Zero width space, joiners and direction marks
+        0x2060..=0x2064 |       // Word joiner and invisible operators
+        0xFE00..=0xFE0F |       // Variation selectors
+        0xE0100..=0xE01EF       // Variation selectors supplement
+    )
+}
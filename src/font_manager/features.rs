@@ -0,0 +1,266 @@
+use anyhow::anyhow;
+use swash::FontRef;
+use crate::font_manager::reader::{tag_to_string, Reader};
+
+const TAG_GSUB: u32 = u32::from_be_bytes(*b"GSUB");
+const TAG_GPOS: u32 = u32::from_be_bytes(*b"GPOS");
+const TAG_KERN: u32 = u32::from_be_bytes(*b"kern");
+
+/// OpenType feature, as used by `font-feature-settings` and the `font-variant-*` properties
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Standard ligatures (liga)
+    Ligatures,
+    /// Discretionary ligatures (dlig)
+    DiscretionaryLigatures,
+    /// Contextual alternates (calt)
+    ContextualAlternates,
+    /// Kerning (kern)
+    Kerning,
+    /// Small capitals from lowercase letters (smcp)
+    SmallCaps,
+    /// Small capitals from uppercase letters (c2sc)
+    CapitalsToSmallCaps,
+    /// Oldstyle numerals (onum)
+    OldstyleNumerals,
+    /// Lining numerals (lnum)
+    LiningNumerals,
+    /// Tabular numerals (tnum)
+    TabularNumerals,
+    /// Proportional numerals (pnum)
+    ProportionalNumerals,
+    /// Diagonal fractions (frac)
+    Fractions,
+    /// Slashed zero (zero)
+    SlashedZero,
+    /// Ordinals (ordn)
+    Ordinals,
+    /// Superscript (sups)
+    Superscript,
+    /// Subscript (subs)
+    Subscript,
+    /// Stylistic set 1 to 20 (ss01 - ss20)
+    StylisticSet(u8),
+    /// Character variant 1 to 99 (cv01 - cv99)
+    CharacterVariant(u8),
+    /// Any other feature, by its four letter tag
+    Other(String),
+}
+
+impl Feature {
+    /// Returns the feature for a four letter OpenType tag
+    pub fn from_tag(tag: &str) -> Option<Feature> {
+        if tag.len() != 4 || !tag.is_ascii() {
+            return None;
+        }
+
+        // Numbered features have two digits, like ss01 and cv99
+        let numbered = |prefix: &str, max: u8| -> Option<u8> {
+            let digits = tag.strip_prefix(prefix)?;
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let n: u8 = digits.parse().ok()?;
+            (1..=max).contains(&n).then_some(n)
+        };
+
+        Some(match tag {
+            "liga" => Feature::Ligatures,
+            "dlig" => Feature::DiscretionaryLigatures,
+            "calt" => Feature::ContextualAlternates,
+            "kern" => Feature::Kerning,
+            "smcp" => Feature::SmallCaps,
+            "c2sc" => Feature::CapitalsToSmallCaps,
+            "onum" => Feature::OldstyleNumerals,
+            "lnum" => Feature::LiningNumerals,
+            "tnum" => Feature::TabularNumerals,
+            "pnum" => Feature::ProportionalNumerals,
+            "frac" => Feature::Fractions,
+            "zero" => Feature::SlashedZero,
+            "ordn" => Feature::Ordinals,
+            "sups" => Feature::Superscript,
+            "subs" => Feature::Subscript,
+            _ => {
+                if let Some(n) = numbered("ss", 20) {
+                    Feature::StylisticSet(n)
+                } else if let Some(n) = numbered("cv", 99) {
+                    Feature::CharacterVariant(n)
+                } else {
+                    Feature::Other(tag.to_string())
+                }
+            }
+        })
+    }
+
+    /// Returns the four letter OpenType tag of the feature
+    pub fn tag(&self) -> String {
+        match self {
+            Feature::Ligatures => "liga".into(),
+            Feature::DiscretionaryLigatures => "dlig".into(),
+            Feature::ContextualAlternates => "calt".into(),
+            Feature::Kerning => "kern".into(),
+            Feature::SmallCaps => "smcp".into(),
+            Feature::CapitalsToSmallCaps => "c2sc".into(),
+            Feature::OldstyleNumerals => "onum".into(),
+            Feature::LiningNumerals => "lnum".into(),
+            Feature::TabularNumerals => "tnum".into(),
+            Feature::ProportionalNumerals => "pnum".into(),
+            Feature::Fractions => "frac".into(),
+            Feature::SlashedZero => "zero".into(),
+            Feature::Ordinals => "ordn".into(),
+            Feature::Superscript => "sups".into(),
+            Feature::Subscript => "subs".into(),
+            Feature::StylisticSet(n) => format!("ss{:02}", n),
+            Feature::CharacterVariant(n) => format!("cv{:02}", n),
+            Feature::Other(tag) => tag.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.tag())
+    }
+}
+
+/// Set of features to turn on or off while shaping. Features that are not in the set keep the
+/// default of the shaper (like liga and kern, which are on by default).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FontFeatures {
+    settings: Vec<(Feature, u16)>,
+}
+
+impl FontFeatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the value of a CSS `font-feature-settings` property, like `"liga" 0, "ss01", "cv03" 2`.
+    /// The value `normal` results in an empty set.
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        let mut features = Self::new();
+        if value.trim() == "normal" {
+            return Ok(features);
+        }
+
+        for setting in value.split(',') {
+            let mut parts = setting.split_whitespace();
+            let tag = parts.next().ok_or_else(|| anyhow!("Empty feature setting"))?;
+            let tag = tag.strip_prefix(['"', '\''])
+                .and_then(|tag| tag.strip_suffix(['"', '\'']))
+                .ok_or_else(|| anyhow!("Feature tag {} is not quoted", tag))?;
+            let feature = Feature::from_tag(tag).ok_or_else(|| anyhow!("Invalid feature tag {:?}", tag))?;
+
+            let value = match parts.next() {
+                None | Some("on") => 1,
+                Some("off") => 0,
+                Some(value) => value.parse().map_err(|_| anyhow!("Invalid value {:?} for feature {}", value, tag))?,
+            };
+            if parts.next().is_some() {
+                return Err(anyhow!("Unexpected data in feature setting {:?}", setting.trim()));
+            }
+
+            features.set(feature, value);
+        }
+
+        Ok(features)
+    }
+
+    /// Sets the value of a feature (0 is off, 1 is on, higher values select alternates)
+    pub fn set(&mut self, feature: Feature, value: u16) {
+        self.settings.retain(|(f, _)| *f != feature);
+        self.settings.push((feature, value));
+    }
+
+    pub fn enable(mut self, feature: Feature) -> Self {
+        self.set(feature, 1);
+        self
+    }
+
+    pub fn disable(mut self, feature: Feature) -> Self {
+        self.set(feature, 0);
+        self
+    }
+
+    /// Returns the value of the feature, or None when the feature is not set
+    pub fn get(&self, feature: &Feature) -> Option<u16> {
+        self.settings.iter().find(|(f, _)| f == feature).map(|(_, value)| *value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Feature, u16)> {
+        self.settings.iter()
+    }
+
+    /// Returns the settings as OpenType tags with their value
+    pub fn settings(&self) -> Vec<(String, u16)> {
+        self.settings.iter().map(|(feature, value)| (feature.tag(), *value)).collect()
+    }
+}
+
+/// Returns the features found in the GSUB and GPOS tables of the face. A legacy kern table counts as
+/// support for kerning.
+pub(crate) fn supported_features(font: &FontRef) -> Vec<Feature> {
+    let mut features: Vec<Feature> = Vec::new();
+
+    for table in [TAG_GSUB, TAG_GPOS] {
+        let Some(data) = font.table(table) else {
+            continue;
+        };
+        for tag in feature_tags(data).unwrap_or_default() {
+            if let Some(feature) = Feature::from_tag(&tag_to_string(tag)) {
+                if !features.contains(&feature) {
+                    features.push(feature);
+                }
+            }
+        }
+    }
+
+    if font.table(TAG_KERN).is_some() && !features.contains(&Feature::Kerning) {
+        features.push(Feature::Kerning);
+    }
+
+    features
+}
+
+/// Reads the tags of the feature list of a GSUB or GPOS table
+fn feature_tags(data: &[u8]) -> Result<Vec<u32>, anyhow::Error> {
+    // The feature list offset follows the version and the script list offset
+    let feature_list = Reader::at(data, 6)?.u16()? as usize;
+
+    let mut r = Reader::at(data, feature_list)?;
+    let count = r.u16()?;
+    let mut tags = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        tags.push(r.u32()?);
+        r.skip(2)?;
+    }
+
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_features_need_two_digits() {
+        assert_eq!(Feature::from_tag("ss01"), Some(Feature::StylisticSet(1)));
+        assert_eq!(Feature::from_tag("ss20"), Some(Feature::StylisticSet(20)));
+        assert_eq!(Feature::from_tag("cv99"), Some(Feature::CharacterVariant(99)));
+
+        for tag in ["ss+1", "ss 1", "cv+9", "ss00", "ss21"] {
+            assert_eq!(Feature::from_tag(tag), Some(Feature::Other(tag.to_string())));
+        }
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        for tag in ["liga", "smcp", "ss01", "ss12", "cv05", "cv42", "abcd"] {
+            assert_eq!(Feature::from_tag(tag).unwrap().tag(), tag);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Fixtures shared by the tests of the font manager
+
+use std::path::PathBuf;
+use crate::font_manager::font_info::{FontData, FontInfo};
+use crate::font_manager::sources::FontSourceType;
+
+/// Font of the family "Fixture", with the glyphs .notdef, A, B and space
+pub(crate) const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/fixture.ttf");
+
+/// Font data written to a file in the temp directory, which is removed when dropped
+pub(crate) struct TempFont(pub(crate) PathBuf);
+
+impl TempFont {
+    pub(crate) fn new(name: &str, data: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("gosub-fixture-{}-{}.ttf", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        Self(path)
+    }
+
+    /// Returns the face of the fixture font written to the file
+    pub(crate) fn face(&self) -> FontInfo {
+        FontInfo {
+            path: Some(self.0.clone()),
+            index: Some(0),
+            ..FontInfo::new(FontSourceType::Memory, "Fixture")
+        }
+    }
+}
+
+impl Drop for TempFont {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Returns the face of the fixture font, registered from memory
+pub(crate) fn memory_face() -> FontInfo {
+    FontInfo {
+        index: Some(0),
+        data: Some(FontData::new(FIXTURE.to_vec())),
+        ..FontInfo::new(FontSourceType::Memory, "Fixture")
+    }
+}
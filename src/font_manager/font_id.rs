@@ -0,0 +1,72 @@
+use std::str::FromStr;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+
+/// Stable identity of a face instance: a hash of the content of the font data, the index of the face
+/// in the data and the normalized variation coordinates. The same face gets the same id no matter
+/// which source found it (or whether it was loaded from a path or from memory), in every process, so
+/// ids can be used as keys for caches that are shared or stored on disk.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FontId(u128);
+
+impl FontId {
+    /// Returns the id of the face at the index in the font data, instantiated at the normalized
+    /// coordinates (in 2.14 fixed point, in axis order, like the coordinates of parley runs)
+    pub fn new(data: &[u8], index: u32, coords: &[i16]) -> Self {
+        Self::from_content(content_hash(data), index, coords)
+    }
+
+    /// Returns the id for font data with the given content hash
+    pub(crate) fn from_content(hash: u128, index: u32, coords: &[i16]) -> Self {
+        // Axes at their default value are left out, so the default instance has the same id with or
+        // without coordinates
+        let len = coords.iter().rposition(|c| *c != 0).map_or(0, |idx| idx + 1);
+
+        let mut hasher = Xxh3::new();
+        hasher.update(&hash.to_le_bytes());
+        hasher.update(&index.to_le_bytes());
+        for coord in &coords[..len] {
+            hasher.update(&coord.to_le_bytes());
+        }
+        Self(hasher.digest128())
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl From<u128> for FontId {
+    fn from(value: u128) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Display for FontId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl std::fmt::Debug for FontId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FontId({})", self)
+    }
+}
+
+impl FromStr for FontId {
+    type Err = anyhow::Error;
+
+    /// Parses an id in the hexadecimal form it is displayed in
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u128::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|e| anyhow!("Invalid font id '{}': {}", s, e))
+    }
+}
+
+/// Returns the hash of the content of the font data
+pub(crate) fn content_hash(data: &[u8]) -> u128 {
+    xxh3_128(data)
+}
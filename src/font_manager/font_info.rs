@@ -1,7 +1,22 @@
 use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use anyhow::anyhow;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use swash::FontRef;
+use crate::font_manager::color::{ColorFormats, ColorInfo, Palette};
+use crate::font_manager::coverage::Coverage;
+use crate::font_manager::features::{supported_features, Feature};
+use crate::font_manager::font_id::{content_hash, FontId};
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::matching::{STRETCH_NORMAL, WEIGHT_NORMAL};
+use crate::font_manager::metrics::FontMetrics;
+use crate::font_manager::outline::{GlyphOutline, OutlineScaler};
 use crate::font_manager::sources::FontSourceType;
+use crate::font_manager::synthesis::Synthesis;
+use crate::font_manager::variation::{NamedInstance, VariationAxis, Variations};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FontStyle {
     Normal,
     Italic,
@@ -18,6 +33,41 @@ impl std::fmt::Display for FontStyle {
     }
 }
 
+/// Font file data that is kept in memory
+#[derive(Clone)]
+pub struct FontData(Arc<Vec<u8>>);
+
+impl FontData {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(Arc::new(data))
+    }
+
+    /// Returns the shared data
+    pub fn as_arc(&self) -> Arc<Vec<u8>> {
+        self.0.clone()
+    }
+}
+
+impl std::ops::Deref for FontData {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<[u8]> for FontData {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for FontData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FontData({} bytes)", self.0.len())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FontInfo {
     /// Family name of the font (e.g. "Arial")
@@ -34,6 +84,405 @@ pub struct FontInfo {
     pub path: Option<PathBuf>,
     /// Index of the face in the font-file
     pub index: Option<i32>,
+    /// Font data for fonts that are loaded from memory instead of a path
+    pub data: Option<FontData>,
     /// Source type of the font
     pub source_type: FontSourceType,
-}
\ No newline at end of file
+    /// Variation axis coordinates the face is instantiated with, changed through `set_coordinates`
+    pub(crate) coordinates: Vec<(String, f32)>,
+    /// Styles that must be synthesized when rendering, because the matched face does not have them
+    pub synthesis: Synthesis,
+    /// Unicode coverage of the face, computed on first use
+    pub(crate) coverage: Arc<OnceLock<Coverage>>,
+    /// Variation axes and named instances of the face, read on first use
+    pub(crate) variations: Arc<OnceLock<Variations>>,
+    /// Color formats and palettes of the face, read on first use
+    pub(crate) color: Arc<OnceLock<ColorInfo>>,
+    /// OpenType features of the GSUB and GPOS tables, read on first use
+    pub(crate) features: Arc<OnceLock<Vec<Feature>>>,
+    /// Data of the font file, loaded on first use
+    pub(crate) file_data: Arc<OnceLock<FontData>>,
+    /// Hash of the content of the font data, computed on first use
+    pub(crate) content_hash: Arc<OnceLock<u128>>,
+    /// Id of the face instance, computed on first use
+    pub(crate) id: Arc<OnceLock<FontId>>,
+}
+
+/// Returns the variations of a face that a source has found to be variable or static while scanning.
+/// Static faces have no variations, so they are known without reading the font file.
+pub(crate) fn scanned_variations(variable: bool) -> Arc<OnceLock<Variations>> {
+    let variations = OnceLock::new();
+    if !variable {
+        let _ = variations.set(Variations::default());
+    }
+    Arc::new(variations)
+}
+
+/// Faces are equal when they are the same face instance found by the same source: the same path or
+/// font data (by address), face index, coordinates and synthesis. Faces without a path or data are
+/// compared on their family and style. Comparing never reads the font data; use `id` to compare faces
+/// of different sources by their content.
+impl PartialEq for FontInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.source_type == other.source_type
+            && self.path == other.path
+            && self.data_address() == other.data_address()
+            && self.index == other.index
+            && self.synthesis == other.synthesis
+            && self.coordinates.len() == other.coordinates.len()
+            && self.coordinates.iter().zip(&other.coordinates).all(|(a, b)| a.0 == b.0 && a.1.to_bits() == b.1.to_bits())
+            && (self.path.is_some() || self.data.is_some() || self.description() == other.description())
+    }
+}
+
+impl Eq for FontInfo {}
+
+impl std::hash::Hash for FontInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.source_type.hash(state);
+        self.path.hash(state);
+        self.data_address().hash(state);
+        self.index.hash(state);
+        self.synthesis.hash(state);
+        for (tag, value) in &self.coordinates {
+            tag.hash(state);
+            value.to_bits().hash(state);
+        }
+        if self.path.is_none() && self.data.is_none() {
+            self.description().hash(state);
+        }
+    }
+}
+
+impl FontInfo {
+    /// Creates the font info of a face of the family with a normal style, weight and stretch, without
+    /// a path or data. Sources fill in what they know about the face.
+    pub fn new(source_type: FontSourceType, family: impl Into<String>) -> Self {
+        Self {
+            family: family.into(),
+            style: FontStyle::Normal,
+            weight: WEIGHT_NORMAL,
+            stretch: STRETCH_NORMAL,
+            monospaced: false,
+            path: None,
+            index: None,
+            data: None,
+            source_type,
+            coordinates: vec![],
+            synthesis: Synthesis::default(),
+            coverage: Default::default(),
+            variations: Default::default(),
+            color: Default::default(),
+            features: Default::default(),
+            file_data: Default::default(),
+            content_hash: Default::default(),
+            id: Default::default(),
+        }
+    }
+
+    /// Loads the raw data of the font file this face is found in. The file is read once, and the data
+    /// is shared with all clones of the face.
+    pub fn load_data(&self) -> Result<Arc<Vec<u8>>, anyhow::Error> {
+        Ok(self.font_data()?.as_arc())
+    }
+
+    /// Returns the font data of this face, like `load_data`
+    pub(crate) fn font_data(&self) -> Result<FontData, anyhow::Error> {
+        if let Some(data) = self.data.as_ref().or(self.file_data.get()) {
+            return Ok(data.clone());
+        }
+
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No path in font info"))?;
+        let data = FontData::new(std::fs::read(path)?);
+        Ok(self.file_data.get_or_init(|| data).clone())
+    }
+
+    /// Address of the font data of faces registered from memory
+    fn data_address(&self) -> Option<usize> {
+        self.data.as_ref().map(|data| data.as_ptr() as usize)
+    }
+
+    /// Family, style, weight and stretch of the face, as compared for faces without a path or data
+    fn description(&self) -> (&str, &FontStyle, u32, u32) {
+        (&self.family, &self.style, self.weight.to_bits(), self.stretch.to_bits())
+    }
+
+    /// Index of the face in the font data, or 0 when unknown
+    pub fn face_index(&self) -> usize {
+        self.index.unwrap_or(0) as usize
+    }
+
+    /// Returns the stable id of this face instance, computed from the content of the font data, the face
+    /// index and the variation coordinates. Faces without accessible font data get an id from their
+    /// path, or from their family and style.
+    pub fn id(&self) -> FontId {
+        *self.id.get_or_init(|| self.compute_id())
+    }
+
+    /// Returns the variation axis coordinates (in user space, like wght=650) the face is instantiated
+    /// with. Empty for the default instance.
+    pub fn coordinates(&self) -> &[(String, f32)] {
+        &self.coordinates
+    }
+
+    /// Sets the variation axis coordinates of the face. The face becomes a different instance with
+    /// its own id, which is no longer shared with the faces it was cloned from.
+    pub fn set_coordinates(&mut self, coordinates: Vec<(String, f32)>) {
+        self.coordinates = coordinates;
+        self.id = Default::default();
+    }
+
+    /// Returns the hash of the content of the font data this face is found in, or None when the data
+    /// can not be loaded
+    pub fn content_hash(&self) -> Option<u128> {
+        if let Some(hash) = self.content_hash.get() {
+            return Some(*hash);
+        }
+
+        match self.load_data() {
+            Ok(data) => Some(*self.content_hash.get_or_init(|| content_hash(&data))),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Unable to load font data for {}: {}", self.family, e);
+                None
+            }
+        }
+    }
+
+    fn compute_id(&self) -> FontId {
+        let index = self.face_index() as u32;
+        if let Some(hash) = self.content_hash() {
+            return FontId::from_content(hash, index, &self.normalized_coords());
+        }
+
+        let description = match &self.path {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => format!("{}/{}/{}/{}", self.family.to_lowercase(), self.style, self.weight.round(), (self.stretch * 1000.0).round()),
+        };
+        FontId::from_content(content_hash(description.as_bytes()), index, &[])
+    }
+
+    /// Parses the face and calls the function with it. Returns None (and logs a warning) when the
+    /// font data can not be loaded or parsed.
+    fn with_font<T>(&self, f: impl FnOnce(&FontRef) -> T) -> Option<T> {
+        let data = match self.load_data() {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Unable to load font data for {}: {}", self.family, e);
+                return None;
+            }
+        };
+
+        match FontRef::from_index(&data, self.face_index()) {
+            Some(font) => Some(f(&font)),
+            None => {
+                warn!(target: LOG_TARGET, "Unable to parse font data for {}", self.family);
+                None
+            }
+        }
+    }
+
+    /// Returns the unicode coverage of this face. The coverage is read from the cmap table the first
+    /// time it is requested. Faces without accessible font data have an empty coverage.
+    pub fn coverage(&self) -> &Coverage {
+        self.coverage.get_or_init(|| self.with_font(Coverage::from_font).unwrap_or_default())
+    }
+
+    /// Returns the variation axes and named instances of this face, read from the fvar table the
+    /// first time they are requested.
+    pub fn variations(&self) -> &Variations {
+        self.variations.get_or_init(|| self.with_font(Variations::from_font).unwrap_or_default())
+    }
+
+    /// Returns the variation axes of this face (empty when the face is not variable)
+    pub fn axes(&self) -> &[VariationAxis] {
+        &self.variations().axes
+    }
+
+    /// Returns the named instances of this face (empty when the face is not variable)
+    pub fn named_instances(&self) -> &[NamedInstance] {
+        &self.variations().instances
+    }
+
+    /// Returns the color formats and CPAL palettes of this face, read from the font the first time
+    /// they are requested
+    pub fn color_info(&self) -> &ColorInfo {
+        self.color.get_or_init(|| self.with_font(ColorInfo::from_font).unwrap_or_default())
+    }
+
+    /// Returns the color glyph formats (COLR, CBDT, sbix, SVG) found in this face
+    pub fn color_formats(&self) -> ColorFormats {
+        self.color_info().formats
+    }
+
+    /// Returns the color palettes of this face (empty when the face has no CPAL table)
+    pub fn palettes(&self) -> &[Palette] {
+        &self.color_info().palettes
+    }
+
+    /// Returns true when the face is a variable font
+    pub fn is_variable(&self) -> bool {
+        !self.axes().is_empty()
+    }
+
+    /// Returns the value of every axis (in axis order) for the coordinates of this face. Axes without
+    /// a coordinate are at their default value.
+    pub fn design_coordinates(&self) -> Vec<f32> {
+        self.axes().iter()
+            .map(|axis| {
+                self.coordinates.iter()
+                    .find(|(tag, _)| *tag == axis.tag)
+                    .map(|(_, value)| axis.clamp(*value))
+                    .unwrap_or(axis.default)
+            })
+            .collect()
+    }
+
+    /// Returns the normalized coordinates (in 2.14 fixed point, in axis order) for the coordinates of
+    /// this face, as used by swash, parley and vello.
+    pub fn normalized_coords(&self) -> Vec<i16> {
+        if self.coordinates.is_empty() {
+            return vec![];
+        }
+
+        self.with_font(|font| self.normalized_coords_of(font)).unwrap_or_default()
+    }
+
+    pub(crate) fn normalized_coords_of(&self, font: &FontRef) -> Vec<i16> {
+        let settings: Vec<(&str, f32)> = self.coordinates.iter().map(|(tag, value)| (tag.as_str(), *value)).collect();
+        font.variations().normalized_coords(settings).collect()
+    }
+
+    /// Returns the metrics of this face (at its coordinates) for the given font size
+    pub fn metrics(&self, size: f32) -> Result<FontMetrics, anyhow::Error> {
+        let data = self.load_data()?;
+        let font = FontRef::from_index(&data, self.face_index())
+            .ok_or_else(|| anyhow!("Unable to parse font data for {}", self.family))?;
+
+        Ok(FontMetrics::from_font(&font, &self.normalized_coords_of(&font), size))
+    }
+
+    /// Returns the outline of the glyph at the given size. The variation coordinates are applied on
+    /// top of the coordinates of this face, and synthetic bold and italic are applied when needed. Use
+    /// `outline_scaler` for the outlines of more than a single glyph.
+    pub fn glyph_outline(&self, glyph_id: u16, size: f32, coordinates: &[(String, f32)]) -> Result<GlyphOutline, anyhow::Error> {
+        self.outline_scaler(size, coordinates)?.outline(glyph_id)
+    }
+
+    /// Returns a scaler for the outlines of the glyphs of this face at the given size, with the same
+    /// coordinates and synthesis as `glyph_outline`
+    pub fn outline_scaler(&self, size: f32, coordinates: &[(String, f32)]) -> Result<OutlineScaler, anyhow::Error> {
+        let mut merged = self.coordinates.clone();
+        for (tag, value) in coordinates {
+            merged.retain(|(t, _)| t != tag);
+            merged.push((tag.clone(), *value));
+        }
+
+        OutlineScaler::new(self.load_data()?, self.face_index(), size, &merged, self.synthesis)
+    }
+
+    /// Returns the glyph id of the character, or None when the face has no glyph for it
+    pub fn glyph_id(&self, ch: char) -> Option<u16> {
+        if !self.supports_char(ch) {
+            return None;
+        }
+        self.with_font(|font| font.charmap().map(ch)).filter(|id| *id != 0)
+    }
+
+    /// Returns the OpenType features found in the GSUB and GPOS tables of this face, read from the font
+    /// the first time they are requested
+    pub fn supported_features(&self) -> &[Feature] {
+        self.features.get_or_init(|| self.with_font(supported_features).unwrap_or_default())
+    }
+
+    /// Returns true when the face supports the feature. Features like small caps must be synthesized
+    /// when they are not supported.
+    pub fn supports_feature(&self, feature: &Feature) -> bool {
+        self.supported_features().contains(feature)
+    }
+
+    /// Returns true when the face has a glyph for the given character
+    pub fn supports_char(&self, ch: char) -> bool {
+        self.coverage().contains(ch as u32)
+    }
+
+    /// Returns true when the face has a glyph for every character in the string
+    pub fn supports_str(&self, s: &str) -> bool {
+        let coverage = self.coverage();
+        s.chars().all(|ch| coverage.contains(ch as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::fixtures::{TempFont, FIXTURE};
+
+    #[test]
+    fn file_data_is_loaded_once() {
+        let file = TempFont::new("load-once", FIXTURE);
+        let face = file.face();
+        assert_eq!(&face.load_data().unwrap()[..], FIXTURE);
+
+        // The file is not read again, even when it is gone
+        std::fs::remove_file(&file.0).unwrap();
+        let clone = face.clone();
+        assert!(Arc::ptr_eq(&face.load_data().unwrap(), &clone.load_data().unwrap()));
+        assert!(face.metrics(16.0).is_ok());
+        assert_eq!(clone.glyph_id('A'), Some(1));
+    }
+
+    #[test]
+    fn outline_scaler_matches_single_glyph_outlines() {
+        let file = TempFont::new("outlines", FIXTURE);
+        let face = file.face();
+
+        let mut scaler = face.outline_scaler(32.0, &[]).unwrap();
+        for glyph_id in 0..4 {
+            assert_eq!(scaler.outline(glyph_id).unwrap(), face.glyph_outline(glyph_id, 32.0, &[]).unwrap());
+        }
+        assert!(scaler.outline(1).unwrap().bounds().is_some());
+        assert!(scaler.outline(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn faces_are_compared_without_reading_the_data() {
+        use std::hash::{BuildHasher, RandomState};
+
+        // The file does not exist, so the data can not be read
+        let face = FontInfo {
+            path: Some(std::env::temp_dir().join("gosub-missing-font.ttf")),
+            index: Some(0),
+            ..FontInfo::new(FontSourceType::Memory, "Fixture")
+        };
+
+        let mut instance = face.clone();
+        instance.set_coordinates(vec![("wght".into(), 700.0)]);
+        let hasher = RandomState::new();
+        assert_eq!(face, face.clone());
+        assert_eq!(hasher.hash_one(&face), hasher.hash_one(face.clone()));
+        assert_ne!(face, instance);
+        #[cfg(feature = "source_fontkit")]
+        assert_ne!(face, FontInfo { source_type: FontSourceType::Fontkit, ..face.clone() });
+        assert!(face.file_data.get().is_none() && face.id.get().is_none());
+
+        // Faces without a path or data are compared on their description
+        let described = FontInfo::new(FontSourceType::Memory, "Fixture");
+        assert_eq!(described, FontInfo::new(FontSourceType::Memory, "Fixture"));
+        assert_ne!(described, FontInfo { weight: 700.0, ..described.clone() });
+    }
+
+    #[test]
+    fn setting_coordinates_resets_the_id() {
+        let file = TempFont::new("coordinates", FIXTURE);
+        let face = file.face();
+        let id = face.id();
+
+        let mut instance = face.clone();
+        instance.set_coordinates(vec![("wght".into(), 700.0)]);
+        assert!(instance.id.get().is_none());
+        assert_eq!(face.id.get(), Some(&id));
+
+        // The fixture has no variation axes, so every instance is the same
+        assert_eq!(instance.id(), id);
+    }
+}
@@ -0,0 +1,69 @@
+/// Generic font families as defined by CSS. A generic family is resolved to one or more concrete
+/// families that are installed on the system.
+///
+/// See: https://www.w3.org/TR/css-fonts-4/#generic-font-families
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GenericFamily {
+    Serif,
+    SansSerif,
+    Monospace,
+    Cursive,
+    Fantasy,
+    SystemUi,
+    Emoji,
+    Math,
+}
+
+impl GenericFamily {
+    pub const ALL: [GenericFamily; 8] = [
+        GenericFamily::Serif,
+        GenericFamily::SansSerif,
+        GenericFamily::Monospace,
+        GenericFamily::Cursive,
+        GenericFamily::Fantasy,
+        GenericFamily::SystemUi,
+        GenericFamily::Emoji,
+        GenericFamily::Math,
+    ];
+
+    /// Parses a CSS generic family keyword (case-insensitive)
+    pub fn parse(name: &str) -> Option<GenericFamily> {
+        let name = name.trim();
+        GenericFamily::ALL.into_iter().find(|g| g.keyword().eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the CSS keyword of the generic family. These keywords are also the aliases that
+    /// fontconfig uses for the generic families.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            GenericFamily::Serif => "serif",
+            GenericFamily::SansSerif => "sans-serif",
+            GenericFamily::Monospace => "monospace",
+            GenericFamily::Cursive => "cursive",
+            GenericFamily::Fantasy => "fantasy",
+            GenericFamily::SystemUi => "system-ui",
+            GenericFamily::Emoji => "emoji",
+            GenericFamily::Math => "math",
+        }
+    }
+
+    /// Well known families that are tried when a source is unable to resolve the generic family
+    pub fn default_families(&self) -> &'static [&'static str] {
+        match self {
+            GenericFamily::Serif => &["Times New Roman", "DejaVu Serif", "Liberation Serif", "Noto Serif"],
+            GenericFamily::SansSerif => &["Arial", "DejaVu Sans", "Liberation Sans", "Noto Sans", "Helvetica"],
+            GenericFamily::Monospace => &["Courier New", "DejaVu Sans Mono", "Liberation Mono", "Noto Sans Mono", "Consolas"],
+            GenericFamily::Cursive => &["Comic Sans MS", "URW Chancery L", "Apple Chancery"],
+            GenericFamily::Fantasy => &["Impact", "Papyrus"],
+            GenericFamily::SystemUi => &["Cantarell", "Ubuntu", "Segoe UI", "Noto Sans", "DejaVu Sans"],
+            GenericFamily::Emoji => &["Noto Color Emoji", "Apple Color Emoji", "Segoe UI Emoji", "Twemoji"],
+            GenericFamily::Math => &["STIX Two Math", "Cambria Math", "Latin Modern Math", "DejaVu Math TeX Gyre"],
+        }
+    }
+}
+
+impl std::fmt::Display for GenericFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
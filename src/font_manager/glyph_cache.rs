@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use anyhow::anyhow;
+use log::warn;
+use swash::scale::image::Content;
+use swash::scale::{Render, ScaleContext, Source, StrikeWith};
+use swash::zeno::{Format, Vector};
+use swash::FontRef;
+use vello::peniko::Color;
+use crate::font_manager::color::{rasterize_color_glyph, ColorFormats, ColorInfo, FontPalette, Palette};
+use crate::font_manager::font_id::{content_hash, FontId};
+use crate::font_manager::font_info::FontInfo;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::synthesis::Synthesis;
+
+/// Width and height of a new atlas page in pixels
+pub const DEFAULT_PAGE_SIZE: u32 = 1024;
+/// Number of pages of each format that are kept before pages are evicted (unless all of them are in
+/// use in the current frame)
+pub const DEFAULT_MAX_PAGES: usize = 4;
+/// Number of horizontal subpixel positions a glyph is rendered at
+pub const SUBPIXEL_STEPS: u8 = 4;
+/// Empty pixels between glyphs in the atlas, so glyphs do not bleed into each other when sampled
+const PADDING: u32 = 1;
+
+/// How the edges of glyphs are smoothed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Antialias {
+    /// A single coverage value per pixel
+    #[default]
+    Grayscale,
+    /// Coverage per subpixel, for LCD screens with red, green and blue subpixels from left to right
+    SubpixelRgb,
+    /// Coverage per subpixel, for LCD screens with blue, green and red subpixels from left to right
+    SubpixelBgr,
+}
+
+/// Options for rasterizing glyphs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RasterOptions {
+    pub antialias: Antialias,
+    /// Outlines are hinted (fitted to the pixel grid), which makes small text sharper
+    pub hinting: bool,
+    /// Glyphs are rendered at fractions of a pixel horizontally, instead of at whole pixels
+    pub subpixel_positioning: bool,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        Self {
+            antialias: Antialias::Grayscale,
+            hinting: false,
+            subpixel_positioning: true,
+        }
+    }
+}
+
+/// Colors of color glyphs (COLR and OpenType-SVG)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GlyphColors {
+    /// Palette selection (CSS `font-palette`) for COLR glyphs
+    pub palette: FontPalette,
+    /// Text color, for COLR layers that are painted in the current color
+    pub foreground: Color,
+}
+
+impl Default for GlyphColors {
+    fn default() -> Self {
+        Self {
+            palette: FontPalette::default(),
+            foreground: Color::BLACK,
+        }
+    }
+}
+
+/// Pixel format of an atlas page
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AtlasFormat {
+    /// One byte of coverage per pixel
+    Mask,
+    /// Four bytes per pixel, with the coverage of the red, green and blue subpixels (the fourth byte
+    /// is not used). The order of the bytes does not depend on the subpixel order of the screen.
+    Subpixel,
+    /// Four bytes (RGBA) per pixel, for color glyphs like emoji
+    Color,
+}
+
+impl AtlasFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            AtlasFormat::Mask => 1,
+            AtlasFormat::Subpixel | AtlasFormat::Color => 4,
+        }
+    }
+}
+
+/// Row of glyphs with the same height in an atlas page
+#[derive(Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Start of the free space at the end of the shelf
+    x: u32,
+}
+
+/// Texture with rasterized glyphs. The version changes every time glyphs are added or the page is
+/// cleared, so a renderer only has to upload the page again when the version differs from the one
+/// it uploaded.
+#[derive(Debug)]
+pub struct AtlasPage {
+    format: AtlasFormat,
+    size: u32,
+    data: Vec<u8>,
+    version: u64,
+    shelves: Vec<Shelf>,
+    /// Tick at which a glyph of this page was last used
+    last_used: u64,
+    /// Frame in which a glyph of this page was last used
+    frame: u64,
+}
+
+impl AtlasPage {
+    fn new(format: AtlasFormat, size: u32) -> Self {
+        Self {
+            format,
+            size,
+            data: vec![0; size as usize * size as usize * format.bytes_per_pixel()],
+            version: 0,
+            shelves: Vec::new(),
+            last_used: 0,
+            frame: 0,
+        }
+    }
+
+    pub fn format(&self) -> AtlasFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size
+    }
+
+    /// Pixel data of the page, row by row without padding
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Finds room for an image of the given size. The shelf that wastes the least height is used,
+    /// and a new shelf is started when no shelf fits.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (w, h) = (width + PADDING, height + PADDING);
+
+        let best = self.shelves.iter_mut()
+            .filter(|shelf| shelf.height >= h && self.size - shelf.x >= w)
+            .min_by_key(|shelf| shelf.height - h);
+        if let Some(shelf) = best {
+            let x = shelf.x;
+            shelf.x += w;
+            return Some((x, shelf.y));
+        }
+
+        let y = self.shelves.last().map(|shelf| shelf.y + shelf.height).unwrap_or(0);
+        if w > self.size || y + h > self.size {
+            return None;
+        }
+        self.shelves.push(Shelf { y, height: h, x: w });
+        Some((0, y))
+    }
+
+    /// Copies the pixels of an image of the given size into the page at the given position
+    fn write(&mut self, x: u32, y: u32, width: u32, height: u32, data: &[u8]) {
+        let bpp = self.format.bytes_per_pixel();
+        let row_len = width as usize * bpp;
+        for row in 0..height as usize {
+            let src = &data[row * row_len..(row + 1) * row_len];
+            let start = ((y as usize + row) * self.size as usize + x as usize) * bpp;
+            self.data[start..start + row_len].copy_from_slice(src);
+        }
+        self.version += 1;
+    }
+
+    fn clear(&mut self) {
+        self.data.fill(0);
+        self.shelves.clear();
+        self.version += 1;
+    }
+}
+
+/// Everything that changes the rasterized image of a glyph
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    /// Id of the face, without variation coordinates
+    font: FontId,
+    glyph_id: u16,
+    /// Size in 1/64 pixels
+    size: u32,
+    subpixel: u8,
+    coords: Vec<i16>,
+    synthesis: Synthesis,
+    antialias: Antialias,
+    hinting: bool,
+    /// Hash of the glyph colors, 0 for faces without COLR or SVG glyphs
+    colors: u64,
+}
+
+/// Location of a rasterized glyph in the atlas
+#[derive(Clone, Copy, Debug)]
+struct AtlasEntry {
+    page: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    left: i32,
+    top: i32,
+}
+
+/// Rasterized glyph in the atlas, placed for drawing at a position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphImage {
+    /// Index of the atlas page with the image
+    pub page: usize,
+    pub format: AtlasFormat,
+    /// Position of the image in the page
+    pub atlas_x: u32,
+    pub atlas_y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Position (in whole pixels) of the top left corner of the image on the target
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Cache of rasterized glyphs, packed into atlas pages. Glyphs are rendered with swash at one of
+/// `SUBPIXEL_STEPS` horizontal positions (unless subpixel positioning is turned off). When a glyph
+/// does not fit and all pages of its format are in use, the least recently used page is cleared,
+/// which drops all glyphs on that page.
+///
+/// Pages that have been used in the current frame are never cleared, so the glyph images that are
+/// returned stay valid until `begin_frame` is called. When all pages of a format are used in the
+/// current frame, a page is added beyond the maximum number of pages.
+pub struct GlyphCache {
+    context: ScaleContext,
+    options: RasterOptions,
+    page_size: u32,
+    max_pages: usize,
+    pages: Vec<AtlasPage>,
+    /// Cached glyphs, None for glyphs without an image (like spaces)
+    entries: HashMap<GlyphKey, Option<AtlasEntry>>,
+    /// Color formats of the faces
+    color_formats: HashMap<FontId, ColorFormats>,
+    /// CPAL palettes of the faces with COLR glyphs
+    palettes: HashMap<FontId, Vec<Palette>>,
+    /// Content hashes of font data by the id of the blob that holds it
+    blob_hashes: HashMap<u64, u128>,
+    /// Increased on every lookup, to find the least recently used page
+    tick: u64,
+    /// Increased by `begin_frame`
+    frame: u64,
+}
+
+impl Default for GlyphCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAGE_SIZE, DEFAULT_MAX_PAGES)
+    }
+}
+
+impl GlyphCache {
+    /// Creates a cache with square pages of the given size, and at most max_pages pages for each format
+    pub fn new(page_size: u32, max_pages: usize) -> Self {
+        Self {
+            context: ScaleContext::new(),
+            options: RasterOptions::default(),
+            page_size,
+            max_pages: max_pages.max(1),
+            pages: Vec::new(),
+            entries: HashMap::new(),
+            color_formats: HashMap::new(),
+            palettes: HashMap::new(),
+            blob_hashes: HashMap::new(),
+            tick: 0,
+            frame: 0,
+        }
+    }
+
+    pub fn options(&self) -> RasterOptions {
+        self.options
+    }
+
+    /// Sets the options for glyphs that are rasterized from now on. Glyphs that are rasterized with
+    /// other options stay in the cache until they are evicted.
+    pub fn set_options(&mut self, options: RasterOptions) {
+        self.options = options;
+    }
+
+    /// Starts a new frame. Glyph images that have been returned before may become invalid, as the
+    /// pages they are on can be cleared from now on.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Returns the id of the face at the index in font data that is shared in a blob with the given
+    /// id, like the fonts of parley runs (`font.data.id()`). The data of every blob is hashed once.
+    pub fn blob_font_id(&mut self, blob_id: u64, data: &[u8], index: u32) -> FontId {
+        let hash = *self.blob_hashes.entry(blob_id).or_insert_with(|| content_hash(data));
+        FontId::from_content(hash, index, &[])
+    }
+
+    /// Returns the glyph of the face at the given size, rasterized for drawing with its origin at
+    /// (x, y) on the target. The coordinates and synthesis of the face are applied. Returns None for
+    /// glyphs without an image.
+    pub fn get(&mut self, font_info: &FontInfo, glyph_id: u16, size: f32, x: f32, y: f32) -> Result<Option<GlyphImage>, anyhow::Error> {
+        let data = font_info.load_data()?;
+        let font = FontRef::from_index(&data, font_info.face_index())
+            .ok_or_else(|| anyhow!("Unable to parse font data for {}", font_info.family))?;
+        let coords = font_info.normalized_coords_of(&font);
+
+        let hash = font_info.content_hash().unwrap_or_else(|| content_hash(&data));
+        let font_id = FontId::from_content(hash, font_info.face_index() as u32, &[]);
+
+        self.get_from_font(font_id, &font, &coords, font_info.synthesis, glyph_id, size, x, y)
+    }
+
+    /// Returns the glyph of an already parsed face, like the fonts of parley runs. The glyphs are
+    /// cached under the font id, which must be the id of the face without variation coordinates (see
+    /// `blob_font_id` and `FontId::new`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_from_font(&mut self, font_id: FontId, font: &FontRef, coords: &[i16], synthesis: Synthesis, glyph_id: u16, size: f32, x: f32, y: f32) -> Result<Option<GlyphImage>, anyhow::Error> {
+        self.get_colored(font_id, font, coords, synthesis, &GlyphColors::default(), glyph_id, size, x, y)
+    }
+
+    /// Returns the glyph of an already parsed face like `get_from_font`, with the colors for color
+    /// glyphs. COLR glyphs are painted with the selected palette, OpenType-SVG glyphs are rendered
+    /// from their documents, and bitmap glyphs are taken from the strike closest to the size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_colored(&mut self, font_id: FontId, font: &FontRef, coords: &[i16], synthesis: Synthesis, colors: &GlyphColors, glyph_id: u16, size: f32, x: f32, y: f32) -> Result<Option<GlyphImage>, anyhow::Error> {
+        let (origin_x, subpixel) = match self.options.subpixel_positioning {
+            true => quantize(x),
+            false => (x.round() as i32, 0),
+        };
+        let origin_y = y.round() as i32;
+
+        let key = GlyphKey {
+            font: font_id,
+            glyph_id,
+            size: (size * 64.0).round() as u32,
+            subpixel,
+            coords: coords.to_vec(),
+            synthesis,
+            antialias: self.options.antialias,
+            hinting: self.options.hinting,
+            colors: 0,
+        };
+        let formats = self.color_formats(font_id, font);
+        let key = GlyphKey {
+            colors: if formats.has_colr() || formats.svg { hash_colors(colors) } else { 0 },
+            ..key
+        };
+
+        self.tick += 1;
+        let entry = match self.entries.get(&key) {
+            Some(entry) => *entry,
+            None => {
+                let entry = self.rasterize(font, &key, size, colors)?;
+                self.entries.insert(key, entry);
+                entry
+            }
+        };
+
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        let page = &mut self.pages[entry.page];
+        page.last_used = self.tick;
+        page.frame = self.frame;
+
+        Ok(Some(GlyphImage {
+            page: entry.page,
+            format: page.format,
+            atlas_x: entry.x,
+            atlas_y: entry.y,
+            width: entry.width,
+            height: entry.height,
+            x: origin_x + entry.left,
+            y: origin_y - entry.top,
+        }))
+    }
+
+    /// Returns the color formats of the face, which are read once for every font id
+    pub fn color_formats(&mut self, font_id: FontId, font: &FontRef) -> ColorFormats {
+        *self.color_formats.entry(font_id).or_insert_with(|| ColorFormats::from_font(font))
+    }
+
+    /// Returns a copy of the pixels of the image, in the format of its page, row by row
+    pub fn image_data(&self, image: &GlyphImage) -> Vec<u8> {
+        let Some(page) = self.pages.get(image.page) else {
+            return vec![];
+        };
+
+        let bpp = page.format.bytes_per_pixel();
+        let row_len = image.width as usize * bpp;
+        let mut data = Vec::with_capacity(row_len * image.height as usize);
+        for row in 0..image.height as usize {
+            let start = ((image.atlas_y as usize + row) * page.size as usize + image.atlas_x as usize) * bpp;
+            data.extend_from_slice(&page.data[start..start + row_len]);
+        }
+        data
+    }
+
+    /// Returns all atlas pages. Page indices stay the same for the lifetime of the cache.
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.pages
+    }
+
+    pub fn page(&self, idx: usize) -> Option<&AtlasPage> {
+        self.pages.get(idx)
+    }
+
+    /// Returns the number of cached glyphs
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all glyphs. The pages are kept (empty) so indices stay valid.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.color_formats.clear();
+        self.palettes.clear();
+        self.blob_hashes.clear();
+        for page in self.pages.iter_mut() {
+            page.clear();
+        }
+    }
+
+    /// Renders the glyph and stores it in a page
+    fn rasterize(&mut self, font: &FontRef, key: &GlyphKey, size: f32, colors: &GlyphColors) -> Result<Option<AtlasEntry>, anyhow::Error> {
+        if key.colors != 0 {
+            match self.rasterize_color(font, key, size, colors) {
+                Ok(Some(entry)) => return Ok(Some(entry)),
+                Ok(None) => {}
+                Err(e) => warn!(target: LOG_TARGET, "Unable to render color glyph {}, using its outline: {}", key.glyph_id, e),
+            }
+        }
+
+        let mut scaler = self.context.builder(*font)
+            .size(size)
+            .hint(key.hinting)
+            .normalized_coords(&key.coords)
+            .build();
+
+        let format = match key.antialias {
+            Antialias::Grayscale => Format::Alpha,
+            Antialias::SubpixelRgb => Format::Subpixel,
+            Antialias::SubpixelBgr => Format::subpixel_bgra(),
+        };
+
+        let image = Render::new(&[
+            Source::ColorOutline(0),
+            Source::ColorBitmap(StrikeWith::BestFit),
+            Source::Outline,
+        ])
+            .format(format)
+            .offset(Vector::new(key.subpixel as f32 / SUBPIXEL_STEPS as f32, 0.0))
+            .embolden(key.synthesis.embolden_strength(size))
+            .transform(key.synthesis.transform())
+            .render(&mut scaler, key.glyph_id)
+            .ok_or_else(|| anyhow!("Unable to render glyph {}", key.glyph_id))?;
+
+        let (width, height) = (image.placement.width, image.placement.height);
+        if width == 0 || height == 0 {
+            return Ok(None);
+        }
+
+        let format = match image.content {
+            Content::Mask => AtlasFormat::Mask,
+            Content::SubpixelMask => AtlasFormat::Subpixel,
+            Content::Color => AtlasFormat::Color,
+        };
+
+        let (page, x, y) = self.allocate(format, width, height)
+            .ok_or_else(|| anyhow!("Glyph {} ({}x{}) does not fit in an atlas page", key.glyph_id, width, height))?;
+        self.pages[page].write(x, y, width, height, &image.data);
+
+        Ok(Some(AtlasEntry {
+            page,
+            x,
+            y,
+            width,
+            height,
+            left: image.placement.left,
+            top: image.placement.top,
+        }))
+    }
+
+    /// Renders a COLR or SVG glyph into a color page. Returns None when the glyph is not a color glyph
+    /// in these formats.
+    fn rasterize_color(&mut self, font: &FontRef, key: &GlyphKey, size: f32, colors: &GlyphColors) -> Result<Option<AtlasEntry>, anyhow::Error> {
+        let palettes = self.palettes.entry(key.font).or_insert_with(|| ColorInfo::from_font(font).palettes);
+        let palette = colors.palette.resolve(palettes);
+        let offset = key.subpixel as f32 / SUBPIXEL_STEPS as f32;
+        let Some(image) = rasterize_color_glyph(font, &key.coords, key.glyph_id, size, offset, &palette, colors.foreground)? else {
+            return Ok(None);
+        };
+
+        let (page, x, y) = self.allocate(AtlasFormat::Color, image.width, image.height)
+            .ok_or_else(|| anyhow!("Glyph {} ({}x{}) does not fit in an atlas page", key.glyph_id, image.width, image.height))?;
+        self.pages[page].write(x, y, image.width, image.height, &image.data);
+
+        Ok(Some(AtlasEntry {
+            page,
+            x,
+            y,
+            width: image.width,
+            height: image.height,
+            left: image.left,
+            top: image.top,
+        }))
+    }
+
+    /// Finds room in a page of the format. A new page is added while there are less than max_pages
+    /// of the format, otherwise the least recently used page that is not used in the current frame is
+    /// evicted. A page is added anyway when all pages are used in the current frame.
+    fn allocate(&mut self, format: AtlasFormat, width: u32, height: u32) -> Option<(usize, u32, u32)> {
+        if width + PADDING > self.page_size || height + PADDING > self.page_size {
+            return None;
+        }
+
+        for (idx, page) in self.pages.iter_mut().enumerate().filter(|(_, page)| page.format == format) {
+            if let Some((x, y)) = page.allocate(width, height) {
+                return Some((idx, x, y));
+            }
+        }
+
+        let count = self.pages.iter().filter(|page| page.format == format).count();
+        let evict = self.pages.iter()
+            .enumerate()
+            .filter(|(_, page)| page.format == format && page.frame < self.frame)
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(idx, _)| idx);
+
+        let idx = match evict {
+            Some(idx) if count >= self.max_pages => {
+                self.entries.retain(|_, entry| entry.is_none_or(|entry| entry.page != idx));
+                self.pages[idx].clear();
+                idx
+            }
+            _ => {
+                self.pages.push(AtlasPage::new(format, self.page_size));
+                self.pages.len() - 1
+            }
+        };
+        self.pages[idx].frame = self.frame;
+
+        let (x, y) = self.pages[idx].allocate(width, height)?;
+        Some((idx, x, y))
+    }
+}
+
+fn hash_colors(colors: &GlyphColors) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    colors.hash(&mut hasher);
+    // 0 is used for faces without color glyphs
+    hasher.finish().max(1)
+}
+
+/// Splits a horizontal position into a whole pixel and a subpixel step
+fn quantize(x: f32) -> (i32, u8) {
+    let steps = (x * SUBPIXEL_STEPS as f32).round() as i32;
+    (steps.div_euclid(SUBPIXEL_STEPS as i32), steps.rem_euclid(SUBPIXEL_STEPS as i32) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::fixtures::FIXTURE;
+
+    fn glyph(cache: &mut GlyphCache, font_id: FontId, glyph_id: u16, size: f32) -> GlyphImage {
+        let font = FontRef::from_index(FIXTURE, 0).unwrap();
+        cache.get_from_font(font_id, &font, &[], Synthesis::default(), glyph_id, size, 0.0, 0.0).unwrap().unwrap()
+    }
+
+    #[test]
+    fn glyphs_are_cached_by_font_id() {
+        let mut cache = GlyphCache::new(256, 1);
+        let font_id = FontId::new(FIXTURE, 0, &[]);
+
+        let first = glyph(&mut cache, font_id, 1, 32.0);
+        assert_eq!(glyph(&mut cache, font_id, 1, 32.0), first);
+        assert_eq!(cache.len(), 1);
+
+        // The same data at the same address is a different font when its id differs
+        glyph(&mut cache, FontId::from(1), 1, 32.0);
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.blob_font_id(7, FIXTURE, 0), font_id);
+    }
+
+    #[test]
+    fn pages_used_in_the_current_frame_are_not_evicted() {
+        // Every glyph takes up a page of its own
+        let mut cache = GlyphCache::new(40, 1);
+        let font_id = FontId::new(FIXTURE, 0, &[]);
+
+        let a = glyph(&mut cache, font_id, 1, 32.0);
+        let b = glyph(&mut cache, font_id, 2, 32.0);
+        assert_ne!(a.page, b.page);
+        assert_eq!(cache.pages().len(), 2);
+        assert_eq!(cache.len(), 2);
+
+        // In the next frame, the least recently used page is evicted instead of adding a page
+        cache.begin_frame();
+        glyph(&mut cache, font_id, 2, 32.0);
+        let a = glyph(&mut cache, font_id, 1, 40.0);
+        assert_eq!(a.page, 0);
+        assert_eq!(cache.pages().len(), 2);
+        assert_eq!(cache.len(), 2);
+    }
+}
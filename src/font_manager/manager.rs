@@ -1,5 +1,24 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+use crate::font_manager::cache::font_directories;
+use crate::font_manager::catalogue::Catalogue;
+use crate::font_manager::error::FontError;
+use crate::font_manager::fallback::{FallbackResolver, FontRun};
 use crate::font_manager::font_info::{FontInfo, FontStyle};
-use crate::font_manager::sources::{FontSource, FontSourceType};
+use crate::font_manager::generic::GenericFamily;
+use crate::font_manager::matching::{instantiate, match_face, FontQuery, STRETCH_NORMAL, WEIGHT_NORMAL};
+use crate::font_manager::metrics::FontMetrics;
+use crate::font_manager::outline::{GlyphOutline, OutlineScaler};
+use crate::font_manager::shaping;
+use crate::font_manager::shaping::{ShapeOptions, ShapedText};
+use crate::font_manager::sources::{resolve_symlink, FontSource, FontSourceType};
+use crate::font_manager::sources::memory::{FontDescriptors, MemorySource};
+use crate::font_manager::svg;
+use crate::font_manager::svg::SvgOptions;
+use crate::font_manager::watcher::{FontChange, FontUpdate, FontUpdateCallback, FontWatcher};
+use crate::font_manager::woff;
 #[cfg(feature = "source_fontkit")]
 use crate::font_manager::sources::fontkit::FontKitSource;
 #[cfg(feature = "source_pango")]
@@ -10,6 +29,9 @@ use crate::font_manager::sources::parley::ParleySource;
 #[allow(dead_code)]
 pub const LOG_TARGET: &str = "font-manager";
 
+/// Interval in which the font directories are scanned for changes when watching
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
 pub struct FontManager {
     #[cfg(feature = "source_fontkit")]
     fontkit: FontKitSource,
@@ -17,18 +39,61 @@ pub struct FontManager {
     pango: PangoSource,
     #[cfg(feature = "source_parley")]
     parley: ParleySource,
+    memory: MemorySource,
+    /// User preferred families for generic families, tried before the families of the source
+    generic_preferences: HashMap<GenericFamily, Vec<String>>,
+    /// Faces of all sources, merged by physical font
+    catalogue: Catalogue,
+    /// Watcher of the font directories, when enabled
+    watcher: Option<FontWatcher>,
+    /// Callbacks that are called when fonts are added or removed
+    subscribers: Vec<FontUpdateCallback>,
 }
 
 impl FontManager {
-    pub fn new() -> Self {
-        Self {
+    pub fn new() -> Result<Self, FontError> {
+        let mut manager = Self {
             #[cfg(feature = "source_fontkit")]
-            fontkit: FontKitSource::new(),
+            fontkit: FontKitSource::new()?,
             #[cfg(feature = "source_parley")]
-            parley: ParleySource::new(),
+            parley: ParleySource::new()?,
             #[cfg(feature = "source_pango")]
-            pango: PangoSource::new(),
+            pango: PangoSource::new()?,
+            memory: MemorySource::new()?,
+            generic_preferences: HashMap::new(),
+            catalogue: Catalogue::default(),
+            watcher: None,
+            subscribers: Vec::new(),
+        };
+        manager.rebuild_catalogue();
+
+        Ok(manager)
+    }
+
+    /// Builds the catalogue from the fonts of all sources
+    fn rebuild_catalogue(&mut self) {
+        // Sources that know the path and index of a face are added first, so faces of sources that
+        // don't can be matched against them.
+        let mut catalogue = Catalogue::default();
+        for source_type in self.catalogue_order() {
+            for fi in self.available_fonts(source_type) {
+                catalogue.insert(&fi);
+            }
         }
+        self.catalogue = catalogue;
+    }
+
+    /// Returns the source types in the order they are added to the catalogue
+    fn catalogue_order(&self) -> Vec<FontSourceType> {
+        vec![
+            #[cfg(feature = "source_fontkit")]
+            FontSourceType::Fontkit,
+            #[cfg(feature = "source_parley")]
+            FontSourceType::Parley,
+            #[cfg(feature = "source_pango")]
+            FontSourceType::Pango,
+            FontSourceType::Memory,
+        ]
     }
 
     // Returns all compiled in font sources
@@ -41,10 +106,26 @@ impl FontManager {
         v.push(FontSourceType::Pango);
         #[cfg(feature = "source_parley")]
         v.push(FontSourceType::Parley);
+        v.push(FontSourceType::Memory);
 
         v
     }
 
+    /// Returns the errors of all font files that have been skipped by the sources
+    pub fn diagnostics(&self) -> Vec<FontError> {
+        let mut diagnostics = Vec::new();
+
+        #[cfg(feature = "source_fontkit")]
+        diagnostics.extend_from_slice(self.fontkit.diagnostics());
+        #[cfg(feature = "source_pango")]
+        diagnostics.extend_from_slice(self.pango.diagnostics());
+        #[cfg(feature = "source_parley")]
+        diagnostics.extend_from_slice(self.parley.diagnostics());
+        diagnostics.extend_from_slice(self.memory.diagnostics());
+
+        diagnostics
+    }
+
     /// Returns all available fonts for given source-type
     pub fn available_fonts(&self, source_type: FontSourceType) -> Vec<FontInfo> {
         let mut fonts = match source_type {
@@ -54,23 +135,328 @@ impl FontManager {
             FontSourceType::Pango => self.pango.available_fonts().to_vec(),
             #[cfg(feature = "source_parley")]
             FontSourceType::Parley => self.parley.available_fonts().to_vec(),
+            FontSourceType::Memory => self.memory.available_fonts().to_vec(),
         };
 
         fonts.sort_by_key(|fi| fi.family.clone());
         fonts
     }
 
+    /// Returns all fonts regardless of the source they are found by. Faces that are found by multiple
+    /// sources are returned once, combining the information of all sources.
+    pub fn all_fonts(&self) -> Vec<FontInfo> {
+        let mut fonts: Vec<FontInfo> = self.catalogue.entries().iter().map(|e| e.info.clone()).collect();
+
+        fonts.sort_by_key(|fi| fi.family.clone());
+        fonts
+    }
+
+    /// Returns all faces contained in the given font file (more than one for TTC/OTC collections),
+    /// ordered by their index in the file.
+    pub fn faces_in_file(&self, path: &Path) -> Vec<FontInfo> {
+        let path = resolve_symlink(path.to_path_buf());
+
+        let mut faces: Vec<FontInfo> = self.catalogue.entries().iter()
+            .filter(|e| e.info.path.as_ref() == Some(&path))
+            .map(|e| e.info.clone())
+            .collect();
+
+        faces.sort_by_key(|fi| fi.index);
+        faces
+    }
+
+    /// Returns the sources that are able to serve the given face
+    pub fn sources_for(&self, info: &FontInfo) -> Vec<FontSourceType> {
+        self.catalogue.get(info).map(|e| e.sources.clone()).unwrap_or_default()
+    }
+
+    /// Returns the catalogue of faces of all sources
+    pub fn catalogue(&self) -> &Catalogue {
+        &self.catalogue
+    }
+
+    /// Sets the preferred families for a generic family. These are tried (in order) before the
+    /// families that the font source itself maps the generic family onto.
+    pub fn set_generic_family(&mut self, generic: GenericFamily, families: &[&str]) {
+        self.generic_preferences.insert(generic, families.iter().map(|f| f.to_string()).collect());
+    }
+
+    /// Removes the preferred families for a generic family
+    pub fn clear_generic_family(&mut self, generic: GenericFamily) {
+        self.generic_preferences.remove(&generic);
+    }
+
+    /// Returns the installed families the generic family resolves to for the given source type, in
+    /// order of preference: user preferences first, then the families of the source, and finally a
+    /// list of well known families. Families that have no faces are left out.
+    pub fn resolve_generic(&self, source_type: FontSourceType, generic: GenericFamily) -> Vec<String> {
+        let source_families = match source_type {
+            #[cfg(feature = "source_fontkit")]
+            FontSourceType::Fontkit => self.fontkit.generic_families(generic),
+            #[cfg(feature = "source_pango")]
+            FontSourceType::Pango => self.pango.generic_families(generic),
+            #[cfg(feature = "source_parley")]
+            FontSourceType::Parley => self.parley.generic_families(generic),
+            FontSourceType::Memory => self.memory.generic_families(generic),
+        };
+
+        let candidates = self.generic_preferences.get(&generic).into_iter().flatten().cloned()
+            .chain(source_families)
+            .chain(generic.default_families().iter().map(|f| f.to_string()));
+
+        let mut families: Vec<String> = Vec::new();
+        for family in candidates {
+            if families.iter().any(|f| f.eq_ignore_ascii_case(&family)) {
+                continue;
+            }
+            if !self.named_family_faces(source_type, &family).is_empty() {
+                families.push(family);
+            }
+        }
+
+        families
+    }
+
+    /// Returns all faces of the given family. Generic family keywords (like `sans-serif`) resolve to
+    /// the faces of the first installed family they map onto.
+    fn family_faces(&self, source_type: FontSourceType, family: &str) -> Vec<FontInfo> {
+        // A family registered from memory with a generic name is taken literally
+        let faces = self.named_family_faces(source_type, family);
+        if !faces.is_empty() {
+            return faces;
+        }
+
+        let Some(generic) = GenericFamily::parse(family) else {
+            return faces;
+        };
+
+        self.resolve_generic(source_type, generic)
+            .first()
+            .map(|family| self.named_family_faces(source_type, family))
+            .unwrap_or_default()
+    }
+
+    /// Returns all faces of the given family. Fonts registered from memory take precedence: when the
+    /// family has been registered from memory, installed fonts with the same family name are ignored.
+    fn named_family_faces(&self, source_type: FontSourceType, family: &str) -> Vec<FontInfo> {
+        let faces: Vec<FontInfo> = self.memory.available_fonts().iter()
+            .filter(|fi| fi.family.eq_ignore_ascii_case(family))
+            .cloned()
+            .collect();
+
+        if !faces.is_empty() || source_type == FontSourceType::Memory {
+            return faces;
+        }
+
+        self.available_fonts(source_type)
+            .into_iter()
+            .filter(|fi| fi.family.eq_ignore_ascii_case(family))
+            .collect()
+    }
+
+    /// Finds a face with the given style in the first family that has one. When none of the families
+    /// has the style, an italic is replaced by an oblique face (or the other way around), and then by
+    /// a normal face that is marked for synthetic italic.
     pub fn find(&self, source_type: FontSourceType, families: &[&str], style: FontStyle) -> Option<FontInfo> {
         for &fam in families {
-            for fi in self.available_fonts(source_type) {
-                if fi.family.eq_ignore_ascii_case(fam) && fi.style == style {
-                    return Some(fi.clone());
+            for fi in self.family_faces(source_type, fam) {
+                if fi.style == style {
+                    return Some(fi);
+                }
+            }
+        }
+
+        let fallbacks = match style {
+            FontStyle::Normal => return None,
+            FontStyle::Italic => [FontStyle::Oblique, FontStyle::Normal],
+            FontStyle::Oblique => [FontStyle::Italic, FontStyle::Normal],
+        };
+        for &fam in families {
+            let faces = self.family_faces(source_type, fam);
+            for fallback in &fallbacks {
+                // The face closest to the regular weight and width is the best stand-in
+                let closest = faces.iter()
+                    .filter(|fi| fi.style == *fallback)
+                    .min_by(|a, b| regular_distance(a).total_cmp(&regular_distance(b)));
+                if let Some(mut fi) = closest.cloned() {
+                    fi.synthesis.italic = *fallback == FontStyle::Normal;
+                    return Some(fi);
                 }
             }
         }
 
         None
     }
+
+    /// Finds the best matching font for the given query, following the CSS font matching algorithm.
+    /// Families are tried in order, and the first family that has any faces is used to select a face.
+    /// When the face is a variable font, the variation coordinates for the query are set on the face.
+    pub fn match_font(&self, source_type: FontSourceType, query: &FontQuery) -> Option<FontInfo> {
+        for fam in &query.families {
+            let faces = self.family_faces(source_type, fam);
+            if let Some(fi) = match_face(&faces, query) {
+                return Some(instantiate(fi, query));
+            }
+        }
+
+        None
+    }
+
+    /// Splits the text into runs, where each run is assigned the first font that can render all of
+    /// its characters. The primary fonts are tried first, then all other fonts of the source type
+    /// (fonts closest in style, weight and stretch to the first primary font are preferred).
+    pub fn resolve_fallback(&self, source_type: FontSourceType, primary: &[FontInfo], text: &str) -> Vec<FontRun> {
+        let mut candidates = primary.to_vec();
+
+        let mut system = self.available_fonts(source_type);
+        if let Some(first) = primary.first() {
+            system.sort_by_key(|fi| (
+                fi.style != first.style,
+                (fi.weight - first.weight).abs() as i32,
+                ((fi.stretch - first.stretch).abs() * 100.0) as i32,
+            ));
+        }
+        for fi in system {
+            let seen = candidates.iter().any(|c| c.path.is_some() && c.path == fi.path && c.index == fi.index);
+            if !seen {
+                candidates.push(fi);
+            }
+        }
+
+        FallbackResolver::new(candidates).resolve(text)
+    }
+
+    /// Registers font data (TTF, OTF, a TTC/OTC collection, WOFF or WOFF2) from memory, for instance a
+    /// font loaded through a CSS `@font-face` rule. The registered faces can be found through `find` and
+    /// `match_font` with any source type, and are made available to the parley font context.
+    pub fn register_font_data(&mut self, data: Vec<u8>, descriptors: FontDescriptors) -> Result<Vec<FontInfo>, FontError> {
+        let data = woff::decode(data)?;
+        let fonts = self.memory.register(data, &descriptors)?;
+        for fi in &fonts {
+            self.catalogue.insert(fi);
+        }
+
+        #[cfg(feature = "source_parley")]
+        self.parley.register_fonts(&fonts);
+
+        Ok(fonts)
+    }
+
+    /// Returns all fonts of the given source type that have a glyph for the given character
+    pub fn fonts_covering(&self, source_type: FontSourceType, ch: char) -> Vec<FontInfo> {
+        self.available_fonts(source_type)
+            .into_iter()
+            .filter(|fi| fi.supports_char(ch))
+            .collect()
+    }
+
+    /// Returns the metrics of the face at the given font size, as needed for line heights, `ex` and
+    /// `cap` units and text decorations
+    pub fn metrics(&self, info: &FontInfo, size: f32) -> Result<FontMetrics, anyhow::Error> {
+        info.metrics(size)
+    }
+
+    /// Returns the outline of a glyph of the face at the given size (in pixels per em). This works for
+    /// faces of every source, including fonts registered from memory. The variation coordinates (in
+    /// user space, like wght=650) are applied on top of the coordinates of the face.
+    pub fn glyph_outline(&self, info: &FontInfo, glyph_id: u16, size: f32, variation_coords: &[(String, f32)]) -> Result<GlyphOutline, anyhow::Error> {
+        info.glyph_outline(glyph_id, size, variation_coords)
+    }
+
+    /// Returns a scaler for the outlines of many glyphs of the face, which loads and parses the face
+    /// only once
+    pub fn outline_scaler(&self, info: &FontInfo, size: f32, variation_coords: &[(String, f32)]) -> Result<OutlineScaler, anyhow::Error> {
+        info.outline_scaler(size, variation_coords)
+    }
+
+    /// Shapes the text with the face at the given size (in pixels per em). Shaping uses the font data
+    /// itself, so the glyphs are the same for every renderer and every source the face came from.
+    pub fn shape(&self, text: &str, info: &FontInfo, size: f32, options: &ShapeOptions) -> Result<ShapedText, anyhow::Error> {
+        shaping::shape(text, info, size, options)
+    }
+
+    /// Shapes the text with the face at the given size (in pixels) and returns it as an SVG document
+    pub fn text_to_svg(&self, text: &str, info: &FontInfo, size: f32, options: &SvgOptions) -> Result<String, anyhow::Error> {
+        svg::text_to_svg(text, info, size, options)
+    }
+}
+
+impl FontManager {
+    /// Starts watching the system and user font directories for installed and removed fonts
+    pub fn watch_font_directories(&mut self) {
+        self.watch_directories(font_directories(), WATCH_INTERVAL);
+    }
+
+    /// Starts watching the given directories for installed and removed fonts. Changes are applied
+    /// with `process_font_changes`.
+    pub fn watch_directories(&mut self, directories: Vec<PathBuf>, interval: Duration) {
+        self.watcher = Some(FontWatcher::new(directories, interval));
+    }
+
+    /// Stops watching the font directories
+    pub fn unwatch(&mut self) {
+        self.watcher = None;
+    }
+
+    /// Adds a callback that is called whenever fonts have been added or removed
+    pub fn subscribe(&mut self, callback: impl Fn(&FontUpdate) + 'static) {
+        self.subscribers.push(Box::new(callback));
+    }
+
+    /// Applies all changes found by the watcher to the sources, and notifies the subscribers. This
+    /// should be called regularly (for instance once per frame) when watching font directories.
+    pub fn process_font_changes(&mut self) -> FontUpdate {
+        let changes = self.watcher.as_ref().map(|w| w.changes()).unwrap_or_default();
+
+        let mut update = FontUpdate::default();
+        for change in &changes {
+            let result = self.apply_font_change(change);
+            update.added.extend(result.added);
+            update.removed.extend(result.removed);
+        }
+
+        if !changes.is_empty() {
+            self.rebuild_catalogue();
+        }
+        if !update.is_empty() {
+            info!(target: LOG_TARGET, "Fonts changed: {} faces added, {} faces removed", update.added.len(), update.removed.len());
+            for subscriber in &self.subscribers {
+                subscriber(&update);
+            }
+        }
+
+        update
+    }
+
+    /// Applies a single font file change to all sources
+    fn apply_font_change(&mut self, change: &FontChange) -> FontUpdate {
+        let mut update = FontUpdate::default();
+
+        let (remove, add) = match change {
+            FontChange::Added(path) => (None, Some(path)),
+            FontChange::Removed(path) => (Some(path), None),
+            FontChange::Modified(path) => (Some(path), Some(path)),
+        };
+
+        if let Some(path) = remove {
+            #[cfg(feature = "source_fontkit")]
+            update.removed.extend(self.fontkit.remove_font_file(path));
+            #[cfg(feature = "source_pango")]
+            update.removed.extend(self.pango.remove_font_file(path));
+            #[cfg(feature = "source_parley")]
+            update.removed.extend(self.parley.remove_font_file(path));
+        }
+        if let Some(path) = add {
+            #[cfg(feature = "source_fontkit")]
+            update.added.extend(self.fontkit.add_font_file(path));
+            #[cfg(feature = "source_pango")]
+            update.added.extend(self.pango.add_font_file(path));
+            #[cfg(feature = "source_parley")]
+            update.added.extend(self.parley.add_font_file(path));
+        }
+
+        update
+    }
 }
 
 impl FontManager {
@@ -148,4 +534,10 @@ impl FontManager {
     //
     //     Ok(ps.get_font_stack(font_info.family.clone()))
     // }
-}
\ No newline at end of file
+}
+
+/// Returns how far the face is from the regular weight and width. A step in width (like condensed
+/// to semi-condensed) counts about as much as a step in weight.
+fn regular_distance(fi: &FontInfo) -> f32 {
+    (fi.weight - WEIGHT_NORMAL).abs() + (fi.stretch - STRETCH_NORMAL).abs() * 800.0
+}
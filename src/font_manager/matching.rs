@@ -0,0 +1,268 @@
+use crate::font_manager::font_info::{FontInfo, FontStyle};
+use crate::font_manager::synthesis::Synthesis;
+use crate::font_manager::variation::OBLIQUE_ANGLE;
+
+/// Weight of a "normal" font
+pub const WEIGHT_NORMAL: f32 = 400.0;
+/// Stretch of a "normal" font
+pub const STRETCH_NORMAL: f32 = 1.0;
+/// Weight from which bold is synthesized for faces that are lighter
+const BOLD_THRESHOLD: f32 = 600.0;
+
+/// A query for a font, as described by the CSS font properties
+#[derive(Clone, Debug)]
+pub struct FontQuery {
+    /// Family names in order of preference
+    pub families: Vec<String>,
+    /// Desired style
+    pub style: FontStyle,
+    /// Desired weight (400 normal, 700 bold)
+    pub weight: f32,
+    /// Desired stretch (1.0 normal, < 1.0 condensed)
+    pub stretch: f32,
+    /// Explicit variation axis values (`font-variation-settings`), like ("wght", 650.0)
+    pub variations: Vec<(String, f32)>,
+    /// Styles that may be synthesized when the family has no face for them (`font-synthesis`)
+    pub synthesis: Synthesis,
+}
+
+impl Default for FontQuery {
+    fn default() -> Self {
+        Self {
+            families: vec![],
+            style: FontStyle::Normal,
+            weight: WEIGHT_NORMAL,
+            stretch: STRETCH_NORMAL,
+            variations: vec![],
+            synthesis: Synthesis::ALL,
+        }
+    }
+}
+
+impl FontQuery {
+    pub fn new(families: &[&str]) -> Self {
+        Self {
+            families: families.iter().map(|f| f.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_style(mut self, style: FontStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_stretch(mut self, stretch: f32) -> Self {
+        self.stretch = stretch;
+        self
+    }
+
+    pub fn with_variation(mut self, tag: &str, value: f32) -> Self {
+        self.variations.push((tag.to_string(), value));
+        self
+    }
+
+    pub fn with_synthesis(mut self, synthesis: Synthesis) -> Self {
+        self.synthesis = synthesis;
+        self
+    }
+}
+
+/// Selects the best matching face out of the faces of a single family. This implements the
+/// font style matching of the CSS Fonts Level 4 specification (section 5.2, step 4): stretch is
+/// narrowed down first, then style and finally weight. Variable fonts match any weight and stretch
+/// within the range of their wght and wdth axes.
+///
+/// See: https://www.w3.org/TR/css-fonts-4/#font-style-matching
+pub fn match_face<'a>(faces: &'a [FontInfo], query: &FontQuery) -> Option<&'a FontInfo> {
+    if faces.is_empty() {
+        return None;
+    }
+
+    let faces: Vec<&FontInfo> = faces.iter().collect();
+
+    let stretch = match_stretch(&faces, query.stretch)?;
+    let faces: Vec<&FontInfo> = faces.into_iter().filter(|fi| in_range(stretch_range(fi), stretch)).collect();
+
+    let style = match_style(&faces, &query.style)?;
+    let faces: Vec<&FontInfo> = faces.into_iter().filter(|fi| styles(fi).contains(&style)).collect();
+
+    let weight = match_weight(&faces, query.weight)?;
+    faces.into_iter().find(|fi| in_range(weight_range(fi), weight))
+}
+
+/// Returns the matched face with the variation axis coordinates set for the query. Weight, stretch
+/// and style are mapped onto the wght, wdth, slnt and ital axes, and explicit variations of the
+/// query are applied last. Bold and italic are marked for synthesis when the face can not be
+/// rendered bold or slanted itself (and the query allows it).
+pub fn instantiate(face: &FontInfo, query: &FontQuery) -> FontInfo {
+    let mut face = face.clone();
+    face.synthesis = synthesis(&face, query);
+    if !face.is_variable() {
+        return face;
+    }
+
+    let mut coordinates: Vec<(String, f32)> = Vec::new();
+    let mut set = |tag: &str, value: f32| {
+        coordinates.retain(|(t, _)| t != tag);
+        coordinates.push((tag.to_string(), value));
+    };
+
+    let variations = face.variations();
+    if variations.axis("wght").is_some() {
+        set("wght", query.weight);
+    }
+    if variations.axis("wdth").is_some() {
+        set("wdth", query.stretch * 100.0);
+    }
+    if face.style == FontStyle::Normal && query.style != FontStyle::Normal {
+        if query.style == FontStyle::Italic && variations.axis("ital").is_some() {
+            set("ital", 1.0);
+        } else if variations.axis("slnt").is_some() {
+            set("slnt", -OBLIQUE_ANGLE);
+        }
+    }
+    for (tag, value) in &query.variations {
+        set(tag, *value);
+    }
+
+    // Only keep coordinates of existing axes, clamped to their range
+    let coordinates = coordinates.into_iter()
+        .filter_map(|(tag, value)| variations.axis(&tag).map(|axis| (tag, axis.clamp(value))))
+        .collect();
+
+    face.set_coordinates(coordinates);
+    face
+}
+
+/// Returns the synthesis needed to render the face for the query. Bold is synthesized for weights of
+/// 600 and up when the face can not be rendered at 600 or more, italic when the face can not be
+/// rendered italic or oblique at all.
+fn synthesis(face: &FontInfo, query: &FontQuery) -> Synthesis {
+    let bold = query.weight >= BOLD_THRESHOLD && weight_range(face).1 < BOLD_THRESHOLD;
+    let italic = query.style != FontStyle::Normal && styles(face) == [FontStyle::Normal];
+
+    Synthesis {
+        bold: bold && query.synthesis.bold,
+        italic: italic && query.synthesis.italic,
+    }
+}
+
+/// Returns the weights a face can be rendered at
+fn weight_range(fi: &FontInfo) -> (f32, f32) {
+    match fi.variations().axis("wght") {
+        Some(axis) => (axis.min, axis.max),
+        None => (fi.weight, fi.weight),
+    }
+}
+
+/// Returns the stretches a face can be rendered at
+fn stretch_range(fi: &FontInfo) -> (f32, f32) {
+    match fi.variations().axis("wdth") {
+        Some(axis) => (axis.min / 100.0, axis.max / 100.0),
+        None => (fi.stretch, fi.stretch),
+    }
+}
+
+/// Returns the styles a face can be rendered in. Variable fonts with a slant or italic axis can be
+/// rendered oblique or italic as well.
+fn styles(fi: &FontInfo) -> Vec<FontStyle> {
+    let mut styles = vec![fi.style.clone()];
+
+    if fi.style == FontStyle::Normal {
+        let variations = fi.variations();
+        if variations.axis("slnt").is_some_and(|axis| axis.min < 0.0) {
+            styles.push(FontStyle::Oblique);
+        }
+        if variations.axis("ital").is_some_and(|axis| axis.max >= 1.0) {
+            styles.push(FontStyle::Italic);
+        }
+    }
+
+    styles
+}
+
+fn in_range((min, max): (f32, f32), value: f32) -> bool {
+    (min..=max).contains(&value)
+}
+
+/// Returns the value closest to the desired value within each range, ranges that contain the
+/// desired value result in the desired value itself.
+fn range_values(ranges: &[(f32, f32)], desired: f32) -> Vec<f32> {
+    ranges.iter().map(|(min, max)| desired.clamp(*min, *max)).collect()
+}
+
+/// Finds the stretch value to use. Narrower stretches are preferred for values at or below
+/// normal, wider stretches for values above normal.
+fn match_stretch(faces: &[&FontInfo], desired: f32) -> Option<f32> {
+    let ranges: Vec<(f32, f32)> = faces.iter().map(|fi| stretch_range(fi)).collect();
+    let values = range_values(&ranges, desired);
+
+    if values.contains(&desired) {
+        return Some(desired);
+    }
+
+    let narrower = closest_below(&values, desired);
+    let wider = closest_above(&values, desired);
+
+    if desired <= STRETCH_NORMAL {
+        narrower.or(wider)
+    } else {
+        wider.or(narrower)
+    }
+}
+
+/// Finds the style to use. Italic falls back to oblique and then normal, oblique falls back to
+/// italic and then normal, and normal falls back to oblique and then italic.
+fn match_style(faces: &[&FontInfo], desired: &FontStyle) -> Option<FontStyle> {
+    let order = match desired {
+        FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+        FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+        FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+    };
+
+    order.into_iter().find(|style| faces.iter().any(|fi| styles(fi).contains(style)))
+}
+
+/// Finds the weight to use, following the rules for weights below 400, between 400 and 500
+/// (inclusive) and above 500.
+fn match_weight(faces: &[&FontInfo], desired: f32) -> Option<f32> {
+    let ranges: Vec<(f32, f32)> = faces.iter().map(|fi| weight_range(fi)).collect();
+    let values = range_values(&ranges, desired);
+
+    if values.contains(&desired) {
+        return Some(desired);
+    }
+
+    if (400.0..=500.0).contains(&desired) {
+        // First look at weights between the desired weight and 500 (ascending)
+        let up_to_500: Vec<f32> = values.iter().copied().filter(|w| *w <= 500.0).collect();
+        if let Some(w) = closest_above(&up_to_500, desired) {
+            return Some(w);
+        }
+
+        // Then weights below the desired weight (descending), then weights above 500 (ascending)
+        return closest_below(&values, desired).or_else(|| closest_above(&values, desired));
+    }
+
+    if desired < 400.0 {
+        closest_below(&values, desired).or_else(|| closest_above(&values, desired))
+    } else {
+        closest_above(&values, desired).or_else(|| closest_below(&values, desired))
+    }
+}
+
+/// Returns the largest value that is smaller than the desired value
+fn closest_below(values: &[f32], desired: f32) -> Option<f32> {
+    values.iter().copied().filter(|v| *v < desired).reduce(f32::max)
+}
+
+/// Returns the smallest value that is larger than the desired value
+fn closest_above(values: &[f32], desired: f32) -> Option<f32> {
+    values.iter().copied().filter(|v| *v > desired).reduce(f32::min)
+}
@@ -0,0 +1,79 @@
+use swash::FontRef;
+use crate::font_manager::reader::Reader;
+
+/// OS/2 table tag
+const OS2: u32 = u32::from_be_bytes(*b"OS/2");
+
+/// Metrics of a face at a given font size, in pixels. Vertical values use a y axis that points up
+/// from the baseline, except for the descent, which is the (positive) distance below the baseline.
+///
+/// The metrics are read from the hhea, OS/2 and post tables (the OS/2 typographic metrics are used
+/// when the font asks for it), with the MVAR deltas applied for variable fonts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// Size the metrics are scaled to
+    pub size: f32,
+    /// Number of font design units per em
+    pub units_per_em: u16,
+    /// Distance from the baseline to the top of the line
+    pub ascent: f32,
+    /// Distance from the baseline to the bottom of the line
+    pub descent: f32,
+    /// Recommended additional space between lines
+    pub line_gap: f32,
+    /// Height of lowercase letters (like "x"). Assumed to be 0.5em when the font does not tell.
+    pub x_height: f32,
+    /// Height of uppercase letters (like "H"). Assumed to be the ascent when the font does not tell.
+    pub cap_height: f32,
+    /// Position of the top of the underline, usually negative
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    /// Position of the top of the strikeout line
+    pub strikeout_position: f32,
+    pub strikeout_thickness: f32,
+}
+
+impl FontMetrics {
+    /// Reads the metrics of the face at the normalized variation coordinates, scaled to the size
+    pub fn from_font(font: &FontRef, coords: &[i16], size: f32) -> Self {
+        let m = font.metrics(coords);
+        let units_per_em = m.units_per_em.max(1);
+        let scale = size / units_per_em as f32;
+
+        // Swash only reports a single stroke size (the underline thickness), so the strikeout size is
+        // read from the OS/2 table itself.
+        let strikeout_size = font.table(OS2)
+            .and_then(|os2| Reader::at(os2, 26).and_then(|mut r| r.i16()).ok())
+            .filter(|size| *size > 0)
+            .map(|size| size as f32)
+            .unwrap_or(m.stroke_size);
+
+        let x_height = if m.x_height > 0.0 { m.x_height * scale } else { size / 2.0 };
+        let cap_height = if m.cap_height > 0.0 { m.cap_height * scale } else { m.ascent * scale };
+        let strikeout_position = if m.strikeout_offset > 0.0 {
+            m.strikeout_offset * scale
+        } else {
+            // Halfway the lowercase letters, centered on the line
+            (x_height + strikeout_size * scale) / 2.0
+        };
+
+        Self {
+            size,
+            units_per_em,
+            ascent: m.ascent * scale,
+            descent: m.descent * scale,
+            line_gap: m.leading * scale,
+            x_height,
+            cap_height,
+            underline_position: m.underline_offset * scale,
+            underline_thickness: m.stroke_size * scale,
+            strikeout_position,
+            strikeout_thickness: strikeout_size * scale,
+        }
+    }
+
+    /// Returns the height of a line (ascent, descent and line gap together)
+    pub fn line_height(&self) -> f32 {
+        self.ascent + self.descent + self.line_gap
+    }
+}
@@ -0,0 +1,141 @@
+#[cfg(any(feature = "source_fontkit", feature = "source_parley"))]
+use std::path::Path;
+use swash::FontRef;
+#[cfg(any(feature = "source_fontkit", feature = "source_parley"))]
+use crate::font_manager::reader::FontFile;
+use crate::font_manager::reader::Reader;
+#[cfg(any(feature = "source_fontkit", feature = "source_parley"))]
+use crate::font_manager::woff::write_sfnt;
+
+/// PANOSE family type for latin text faces
+const PANOSE_LATIN_TEXT: u8 = 2;
+/// PANOSE proportion of monospaced latin text faces
+const PANOSE_MONOSPACED: u8 = 9;
+
+/// Characters whose advance widths are compared when the font tables do not say whether the face is
+/// monospaced. Narrow and wide glyphs, so proportional faces are detected reliably.
+const PROBE_CHARS: &[char] = &['i', 'l', 'm', 'M', 'W', '0', '.', ' '];
+
+/// Returns true when the face is monospaced. The isFixedPitch flag of the post table is checked
+/// first, then the PANOSE proportion in the OS/2 table. When neither is conclusive, the advance
+/// widths of a few common glyphs are compared.
+pub(crate) fn is_monospaced(font: &FontRef) -> bool {
+    if let Some(monospaced) = from_tables(font.table(tag(b"post")), font.table(tag(b"OS/2"))) {
+        return monospaced;
+    }
+
+    from_advances(font)
+}
+
+/// Same as `is_monospaced`, for a face in a font file. Only the post and OS/2 tables are read from
+/// the file. When the advance widths must be compared, a font is built from the few tables that
+/// are needed for that.
+#[cfg(any(feature = "source_fontkit", feature = "source_parley"))]
+pub(crate) fn is_monospaced_file(path: &Path, index: usize) -> Result<bool, anyhow::Error> {
+    let mut file = FontFile::open(path, index)?;
+    let post = file.table(tag(b"post"))?;
+    let os2 = file.table(tag(b"OS/2"))?;
+
+    if let Some(monospaced) = from_tables(post.as_deref(), os2.as_deref()) {
+        return Ok(monospaced);
+    }
+
+    let mut tables = Vec::new();
+    for name in [b"cmap", b"head", b"hhea", b"hmtx", b"maxp"] {
+        if let Some(data) = file.table(tag(name))? {
+            tables.push((tag(name), data));
+        }
+    }
+
+    let data = write_sfnt(0x00010000, &tables);
+    Ok(FontRef::from_index(&data, 0).is_some_and(|font| from_advances(&font)))
+}
+
+/// Decides on the post and OS/2 tables. Returns None when the tables are missing or inconclusive.
+fn from_tables(post: Option<&[u8]>, os2: Option<&[u8]>) -> Option<bool> {
+    if let Some(post) = post {
+        // isFixedPitch is found after the version, italic angle and underline position and thickness
+        if Reader::at(post, 12).and_then(|mut r| r.u32()).is_ok_and(|fixed| fixed != 0) {
+            return Some(true);
+        }
+    }
+
+    // The PANOSE classification starts at offset 32, the proportion is its fourth byte. Only latin
+    // text faces use the proportion for monospacing, and many fonts leave it set to "any" (0) or
+    // "no fit" (1).
+    let panose = Reader::at(os2?, 32).and_then(|mut r| r.bytes(4)).ok()?;
+    if panose[0] != PANOSE_LATIN_TEXT || panose[3] <= 1 {
+        return None;
+    }
+
+    Some(panose[3] == PANOSE_MONOSPACED)
+}
+
+/// Returns true when all glyphs of the probe characters found in the face have the same advance
+fn from_advances(font: &FontRef) -> bool {
+    let charmap = font.charmap();
+    let metrics = font.glyph_metrics(&[]);
+
+    let advances: Vec<f32> = PROBE_CHARS.iter()
+        .map(|&c| charmap.map(c))
+        .filter(|&glyph_id| glyph_id != 0)
+        .map(|glyph_id| metrics.advance_width(glyph_id))
+        .filter(|&advance| advance > 0.0)
+        .collect();
+
+    // A single glyph says nothing about the other glyphs
+    advances.len() >= 2 && advances.iter().all(|&advance| advance == advances[0])
+}
+
+fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*name)
+}
+
+#[cfg(all(test, any(feature = "source_fontkit", feature = "source_parley")))]
+mod tests {
+    use super::*;
+    use crate::font_manager::fixtures::{TempFont, FIXTURE};
+
+    /// Offset of the table record for the table
+    fn record(data: &[u8], table: u32) -> usize {
+        let num_tables = u16::from_be_bytes([data[4], data[5]]) as usize;
+        (0..num_tables)
+            .map(|i| 12 + 16 * i)
+            .find(|&rec| u32::from_be_bytes(data[rec..rec + 4].try_into().unwrap()) == table)
+            .unwrap()
+    }
+
+    #[test]
+    fn file_detection_matches_font_detection() {
+        let file = TempFont::new("proportional", FIXTURE);
+        let font = FontRef::from_index(FIXTURE, 0).unwrap();
+        assert_eq!(is_monospaced_file(&file.0, 0).unwrap(), is_monospaced(&font));
+    }
+
+    #[test]
+    fn fixed_pitch_flag_is_read_from_the_file() {
+        let mut data = FIXTURE.to_vec();
+        let rec = record(&data, tag(b"post"));
+        let post = u32::from_be_bytes(data[rec + 8..rec + 12].try_into().unwrap()) as usize;
+        data[post + 12..post + 16].copy_from_slice(&1u32.to_be_bytes());
+
+        let file = TempFont::new("fixed-pitch", &data);
+        assert!(is_monospaced_file(&file.0, 0).unwrap());
+    }
+
+    #[test]
+    fn table_beyond_the_file_is_rejected() {
+        let mut data = FIXTURE.to_vec();
+        let rec = record(&data, tag(b"OS/2"));
+        data[rec + 12..rec + 16].copy_from_slice(&0xFFFF_FFF0u32.to_be_bytes());
+
+        let file = TempFont::new("oversized-table", &data);
+        assert!(is_monospaced_file(&file.0, 0).is_err());
+    }
+
+    #[test]
+    fn missing_face_is_rejected() {
+        let file = TempFont::new("missing-face", FIXTURE);
+        assert!(is_monospaced_file(&file.0, 1).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use anyhow::anyhow;
+use swash::scale::{ScaleContext, Scaler};
+use swash::zeno::Verb;
+use swash::{CacheKey, FontRef};
+use crate::font_manager::synthesis::Synthesis;
+
+/// Point of a glyph outline, in pixels. The y axis points up from the baseline.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Command of a glyph outline path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathCommand {
+    /// Starts a new contour at the point
+    MoveTo(Point),
+    /// Straight line to the point
+    LineTo(Point),
+    /// Quadratic bezier curve through a control point to the point
+    QuadTo(Point, Point),
+    /// Cubic bezier curve through two control points to the point
+    CurveTo(Point, Point, Point),
+    /// Closes the current contour
+    Close,
+}
+
+/// Outline of a single glyph, independent of the font backend
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GlyphOutline {
+    pub commands: Vec<PathCommand>,
+}
+
+/// Scales the outlines of the glyphs of a face at one size and variation instance. The font data and
+/// the scale context (which caches the state of the face) are reused for every glyph.
+pub struct OutlineScaler {
+    data: Arc<Vec<u8>>,
+    offset: u32,
+    key: CacheKey,
+    context: ScaleContext,
+    size: f32,
+    coords: Vec<i16>,
+    synthesis: Synthesis,
+}
+
+impl OutlineScaler {
+    /// Creates a scaler for the face with the given index in the font data. The coordinates are in
+    /// user space (like wght=650).
+    pub(crate) fn new(data: Arc<Vec<u8>>, index: usize, size: f32, coordinates: &[(String, f32)], synthesis: Synthesis) -> Result<Self, anyhow::Error> {
+        let font = FontRef::from_index(&data, index).ok_or_else(|| anyhow!("Face {} not found in the font data", index))?;
+
+        let settings: Vec<(&str, f32)> = coordinates.iter().map(|(tag, value)| (tag.as_str(), *value)).collect();
+        let coords = font.variations().normalized_coords(settings).collect();
+        let (offset, key) = (font.offset, font.key);
+
+        Ok(Self { data, offset, key, context: ScaleContext::new(), size, coords, synthesis })
+    }
+
+    /// Returns the outline of the glyph. Glyphs without an outline (like bitmap glyphs) result in an
+    /// error, glyphs without contours (like spaces) in an empty outline.
+    pub fn outline(&mut self, glyph_id: u16) -> Result<GlyphOutline, anyhow::Error> {
+        // The same cache key is used for every glyph, so the scale context finds the face it has seen before
+        let font = FontRef { data: &self.data, offset: self.offset, key: self.key };
+        let mut scaler = self.context.builder(font)
+            .size(self.size)
+            .normalized_coords(&self.coords)
+            .build();
+
+        GlyphOutline::from_scaler(&mut scaler, glyph_id, self.size, self.synthesis)
+    }
+}
+
+impl GlyphOutline {
+    /// Scales the outline of the glyph to the size (in pixels per em), with the variation axes set to
+    /// the given coordinates (in user space, like wght=650) and the synthesis applied. Glyphs without
+    /// an outline (like bitmap glyphs) result in an error, glyphs without contours (like spaces) in an
+    /// empty outline.
+    pub fn from_font(font: &FontRef, glyph_id: u16, size: f32, coordinates: &[(String, f32)], synthesis: Synthesis) -> Result<Self, anyhow::Error> {
+        let settings: Vec<(&str, f32)> = coordinates.iter().map(|(tag, value)| (tag.as_str(), *value)).collect();
+
+        let mut context = ScaleContext::new();
+        let mut scaler = context.builder(*font)
+            .size(size)
+            .variations(settings)
+            .build();
+
+        Self::from_scaler(&mut scaler, glyph_id, size, synthesis)
+    }
+
+    /// Scales the outline of the glyph with a scaler that is already set up for size and variations
+    pub(crate) fn from_scaler(scaler: &mut Scaler, glyph_id: u16, size: f32, synthesis: Synthesis) -> Result<Self, anyhow::Error> {
+        let mut outline = scaler.scale_outline(glyph_id)
+            .ok_or_else(|| anyhow!("Glyph {} has no outline", glyph_id))?;
+
+        if synthesis.bold {
+            let strength = synthesis.embolden_strength(size);
+            outline.embolden(strength, strength);
+        }
+        if let Some(transform) = synthesis.transform() {
+            outline.transform(&transform);
+        }
+
+        let mut points = outline.points().iter().map(|p| Point::new(p.x, p.y));
+        let mut next = || points.next().ok_or_else(|| anyhow!("Glyph {} has a malformed outline", glyph_id));
+
+        let mut commands = Vec::with_capacity(outline.verbs().len());
+        for verb in outline.verbs() {
+            let command = match verb {
+                Verb::MoveTo => PathCommand::MoveTo(next()?),
+                Verb::LineTo => PathCommand::LineTo(next()?),
+                Verb::QuadTo => PathCommand::QuadTo(next()?, next()?),
+                Verb::CurveTo => PathCommand::CurveTo(next()?, next()?, next()?),
+                Verb::Close => PathCommand::Close,
+            };
+            commands.push(command);
+        }
+
+        Ok(Self { commands })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Returns the outline as SVG path data. The y axis is flipped, as it points down in SVG.
+    pub fn to_svg_path(&self) -> String {
+        let mut d = String::new();
+        for command in &self.commands {
+            if !d.is_empty() {
+                d.push(' ');
+            }
+            match command {
+                PathCommand::MoveTo(p) => d.push_str(&format!("M{} {}", p.x, -p.y)),
+                PathCommand::LineTo(p) => d.push_str(&format!("L{} {}", p.x, -p.y)),
+                PathCommand::QuadTo(c, p) => d.push_str(&format!("Q{} {} {} {}", c.x, -c.y, p.x, -p.y)),
+                PathCommand::CurveTo(c1, c2, p) => {
+                    d.push_str(&format!("C{} {} {} {} {} {}", c1.x, -c1.y, c2.x, -c2.y, p.x, -p.y))
+                }
+                PathCommand::Close => d.push('Z'),
+            }
+        }
+        d
+    }
+
+    /// Returns the minimum and maximum point of the outline, or None for an empty outline. Control
+    /// points are included, so the bounds may be slightly larger than the actual glyph.
+    pub fn bounds(&self) -> Option<(Point, Point)> {
+        let mut points = self.commands.iter().flat_map(|command| match *command {
+            PathCommand::MoveTo(p) | PathCommand::LineTo(p) => vec![p],
+            PathCommand::QuadTo(c, p) => vec![c, p],
+            PathCommand::CurveTo(c1, c2, p) => vec![c1, c2, p],
+            PathCommand::Close => vec![],
+        });
+
+        let first = points.next()?;
+        Some(points.fold((first, first), |(min, max), p| {
+            (Point::new(min.x.min(p.x), min.y.min(p.y)), Point::new(max.x.max(p.x), max.y.max(p.y)))
+        }))
+    }
+}
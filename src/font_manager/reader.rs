@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use anyhow::anyhow;
+
+/// Big-endian reader over font data. All reads fail with an error instead of panicking when the
+/// data is truncated.
+#[derive(Clone)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Creates a reader at the given offset of the data
+    pub fn at(data: &'a [u8], offset: usize) -> Result<Self, anyhow::Error> {
+        if offset > data.len() {
+            return Err(anyhow!("Offset {} is out of bounds (length {})", offset, data.len()));
+        }
+        Ok(Self { data, pos: offset })
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), anyhow::Error> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        if len > self.remaining() {
+            return Err(anyhow!("Truncated font data: needed {} bytes at offset {}, but only {} available", len, self.pos, self.remaining()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn i16(&mut self) -> Result<i16, anyhow::Error> {
+        Ok(self.u16()? as i16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// Formats a table tag as a string (e.g. "glyf")
+pub(crate) fn tag_to_string(tag: u32) -> String {
+    tag.to_be_bytes().iter().map(|&b| b as char).collect()
+}
+
+/// Reads single tables of a face from a font file, without loading the whole file. Table records
+/// that point outside the file are rejected when the file is opened.
+pub(crate) struct FontFile {
+    file: File,
+    /// Tag, offset and length of each table of the face
+    records: Vec<(u32, u64, u64)>,
+}
+
+impl FontFile {
+    pub fn open(path: &Path, index: usize) -> Result<Self, anyhow::Error> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)?;
+
+        // Collections start with a list of offsets to the table directories of the faces
+        let mut directory_offset = 0;
+        match &header[0..4] {
+            b"ttcf" => {
+                let num_fonts = Reader::at(&header, 8)?.u32()? as usize;
+                if index >= num_fonts {
+                    return Err(anyhow!("Face {} not found in collection of {} faces", index, num_fonts));
+                }
+
+                let mut offset = [0u8; 4];
+                file.seek(SeekFrom::Start(12 + 4 * index as u64))?;
+                file.read_exact(&mut offset)?;
+                directory_offset = u32::from_be_bytes(offset) as u64;
+
+                file.seek(SeekFrom::Start(directory_offset))?;
+                file.read_exact(&mut header)?;
+            }
+            b"wOFF" | b"wOF2" => return Err(anyhow!("Tables of WOFF files cannot be read directly")),
+            _ if index > 0 => return Err(anyhow!("Face {} not found, the file is not a collection", index)),
+            _ => {}
+        }
+
+        let num_tables = Reader::at(&header, 4)?.u16()? as usize;
+        let mut data = vec![0u8; num_tables * 16];
+        file.seek(SeekFrom::Start(directory_offset + 12))?;
+        file.read_exact(&mut data)?;
+
+        let mut r = Reader::new(&data);
+        let mut records = Vec::with_capacity(num_tables);
+        for _ in 0..num_tables {
+            let tag = r.u32()?;
+            let _checksum = r.u32()?;
+            let offset = r.u32()? as u64;
+            let length = r.u32()? as u64;
+            if offset + length > file_len {
+                return Err(anyhow!("Table {} exceeds the file length of {} bytes", tag_to_string(tag), file_len));
+            }
+            records.push((tag, offset, length));
+        }
+
+        Ok(Self { file, records })
+    }
+
+    pub fn has_table(&self, tag: u32) -> bool {
+        self.records.iter().any(|(t, _, _)| *t == tag)
+    }
+
+    /// Reads a table of the face, or returns None when the face has no such table
+    pub fn table(&mut self, tag: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let Some(&(_, offset, length)) = self.records.iter().find(|(t, _, _)| *t == tag) else {
+            return Ok(None);
+        };
+
+        let mut data = vec![0u8; length as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+}
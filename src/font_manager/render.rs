@@ -0,0 +1,172 @@
+//! Renderers that draw text laid out with parley and the font manager onto a 2D graphics backend
+
+use std::borrow::Cow;
+use parley::layout::{Alignment, Glyph, GlyphRun, Layout};
+use parley::style::{FontSettings, FontStretch, FontWeight, StyleProperty};
+use parley::LayoutContext;
+// The vello and swash crates are shadowed by the modules of the same name
+use ::vello::peniko::Color;
+use log::warn;
+use crate::font_manager::color::FontPalette;
+use crate::font_manager::features::FontFeatures;
+use crate::font_manager::font_info::{FontInfo, FontStyle};
+use crate::font_manager::glyph_cache::{AtlasFormat, GlyphCache, GlyphColors};
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::sources::parley::ParleySource;
+use crate::font_manager::synthesis::Synthesis;
+
+pub mod cairo;
+pub mod swash;
+pub mod vello;
+
+/// Options for laying out a block of text
+#[derive(Clone, Debug)]
+pub struct TextOptions {
+    /// Font size in pixels
+    pub size: f32,
+    /// Lines longer than this width (in pixels) are wrapped
+    pub max_width: Option<f32>,
+    /// Height of a line, relative to the font size
+    pub line_height: f32,
+    pub color: Color,
+    /// OpenType features to turn on or off while shaping
+    pub features: FontFeatures,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            max_width: None,
+            line_height: 1.2,
+            color: Color::BLACK,
+            features: FontFeatures::default(),
+        }
+    }
+}
+
+/// Lays out the text with the face through the font context of the parley source. The weight, style,
+/// stretch, variation coordinates and synthesis of the face are used to select it from its family.
+pub fn layout_text(parley: &ParleySource, text: &str, font_info: &FontInfo, options: &TextOptions) -> Layout<Color> {
+    let font_cx = parley.context();
+    let mut font_cx = font_cx.borrow_mut();
+    let mut layout_cx = LayoutContext::new();
+
+    // Styles that are synthesized are asked for, so parley selects the same face and synthesizes them
+    let style = match font_info.style {
+        _ if font_info.synthesis.italic => parley::style::FontStyle::Italic,
+        FontStyle::Normal => parley::style::FontStyle::Normal,
+        FontStyle::Italic => parley::style::FontStyle::Italic,
+        FontStyle::Oblique => parley::style::FontStyle::Oblique(None),
+    };
+    let weight = if font_info.synthesis.bold { FontWeight::BOLD } else { FontWeight::new(font_info.weight) };
+    let variations = parley.get_font_variations(font_info);
+    let features = parley.get_font_features(&options.features);
+
+    let mut builder = layout_cx.ranged_builder(&mut font_cx, text, 1.0);
+    builder.push_default(parley.get_font_stack(font_info.family.clone()));
+    builder.push_default(StyleProperty::FontSize(options.size));
+    builder.push_default(StyleProperty::FontWeight(weight));
+    builder.push_default(StyleProperty::FontStretch(FontStretch::from_ratio(font_info.stretch)));
+    builder.push_default(StyleProperty::FontStyle(style));
+    builder.push_default(StyleProperty::FontVariations(FontSettings::List(Cow::Owned(variations))));
+    builder.push_default(StyleProperty::FontFeatures(FontSettings::List(Cow::Owned(features))));
+    builder.push_default(StyleProperty::LineHeight(options.line_height));
+    builder.push_default(StyleProperty::Brush(options.color));
+
+    let mut layout = builder.build(text);
+    layout.break_all_lines(options.max_width);
+    layout.align(options.max_width, Alignment::Start);
+    layout
+}
+
+/// Color glyph of a run that is rasterized by the font manager, in RGBA (not premultiplied)
+pub(crate) struct RunColorGlyph {
+    /// Position of the top left corner of the image, relative to the layout
+    pub x: f32,
+    pub y: f32,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Rasterizes the color glyphs of the run that a backend can not draw itself: the glyphs of faces
+/// with OpenType-SVG glyphs, and of faces with COLR glyphs as well when `colr` is set (faces with
+/// both are left to the backend when it is not). They are painted with the palette and the color of
+/// the run, and kept in the glyph cache. Returns the rasterized glyphs and the glyphs that are left
+/// for the backend.
+pub(crate) fn split_color_glyphs(cache: &mut GlyphCache, glyph_run: &GlyphRun<'_, Color>, palette: &FontPalette, colr: bool) -> (Vec<RunColorGlyph>, Vec<Glyph>) {
+    let run = glyph_run.run();
+    let font = run.font();
+    let glyphs: Vec<Glyph> = glyph_run.positioned_glyphs().collect();
+
+    let Some(font_ref) = ::swash::FontRef::from_index(font.data.as_ref(), font.index as usize) else {
+        return (vec![], glyphs);
+    };
+    let font_id = cache.blob_font_id(font.data.id(), font.data.as_ref(), font.index);
+    let formats = cache.color_formats(font_id, &font_ref);
+    let rasterize = match colr {
+        true => formats.has_colr() || formats.svg,
+        false => formats.svg && !formats.has_colr(),
+    };
+    if !rasterize {
+        return (vec![], glyphs);
+    }
+
+    let colors = GlyphColors {
+        palette: palette.clone(),
+        foreground: glyph_run.style().brush,
+    };
+    let mut color_glyphs = vec![];
+    let mut other_glyphs = vec![];
+    for glyph in glyphs {
+        // Glyphs without a color image are rasterized from their outline, and left to the backend
+        let image = match cache.get_colored(font_id, &font_ref, run.normalized_coords(), Synthesis::default(), &colors, glyph.id, run.font_size(), glyph.x, glyph.y) {
+            Ok(image) => image.filter(|image| image.format == AtlasFormat::Color),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Unable to render color glyph {}: {}", glyph.id, e);
+                None
+            }
+        };
+
+        match image {
+            Some(image) => color_glyphs.push(RunColorGlyph {
+                x: image.x as f32,
+                y: image.y as f32,
+                width: image.width,
+                height: image.height,
+                data: cache.image_data(&image),
+            }),
+            None => other_glyphs.push(glyph),
+        }
+    }
+
+    (color_glyphs, other_glyphs)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use parley::style::{FontFamily, FontStack};
+    use parley::FontContext;
+    use crate::font_manager::fixtures::FIXTURE;
+
+    /// Lays out the text with the fixture font at 32 pixels in the color, underlined and struck through
+    pub(crate) fn fixture_layout(text: &str, color: Color) -> Layout<Color> {
+        let mut font_cx = FontContext::new();
+        font_cx.collection.register_fonts(FIXTURE.to_vec());
+        let mut layout_cx = LayoutContext::new();
+
+        let mut builder = layout_cx.ranged_builder(&mut font_cx, text, 1.0);
+        builder.push_default(StyleProperty::FontStack(FontStack::Single(FontFamily::Named(Cow::Borrowed("Fixture")))));
+        builder.push_default(StyleProperty::FontSize(32.0));
+        builder.push_default(StyleProperty::Brush(color));
+        builder.push_default(StyleProperty::Underline(true));
+        builder.push_default(StyleProperty::Strikethrough(true));
+
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout.align(None, Alignment::Start);
+        layout
+    }
+}
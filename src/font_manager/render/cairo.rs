@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use anyhow::anyhow;
+use cairo::freetype::Library;
+use cairo::{Context, FontFace, FtSynthesize, Glyph, ImageSurface};
+use image::RgbaImage;
+use parley::layout::{GlyphRun, Layout, PositionedInlineBox, PositionedLayoutItem};
+use vello::peniko::Color;
+use crate::font_manager::color::FontPalette;
+use crate::font_manager::glyph_cache::GlyphCache;
+use crate::font_manager::render::{split_color_glyphs, RunColorGlyph};
+
+/// Everything that selects the font face of a glyph run
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct FaceKey {
+    /// Id of the font data blob of parley
+    blob: u64,
+    index: u32,
+    coords: Vec<i16>,
+    bold: bool,
+    italic: bool,
+}
+
+/// Draws parley layouts with cairo. Glyph runs are drawn at once with `show_glyphs`, with a cairo
+/// font face that is created (through freetype) from the exact font data and face index parley
+/// selected for the run. Font faces are cached, so a renderer should be reused between draws. COLR and
+/// OpenType-SVG glyphs are rasterized by the font manager with the palette, kept in a glyph cache and
+/// painted as images.
+pub struct CairoRenderer {
+    library: Library,
+    faces: HashMap<FaceKey, FontFace>,
+    cache: GlyphCache,
+    /// Color to fill inline boxes with. Inline boxes are left empty when not set.
+    pub inline_box_color: Option<Color>,
+    /// Palette that COLR glyphs are painted with
+    pub palette: FontPalette,
+}
+
+impl CairoRenderer {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            library: Library::init()?,
+            faces: HashMap::new(),
+            cache: GlyphCache::default(),
+            inline_box_color: None,
+            palette: FontPalette::default(),
+        })
+    }
+
+    /// Draws the glyph runs, decorations and inline boxes of the layout, with the top left of the
+    /// layout at (x, y)
+    pub fn draw_layout(&mut self, cr: &Context, layout: &Layout<Color>, x: f64, y: f64) -> Result<(), anyhow::Error> {
+        // Color glyphs are copied out of the glyph cache as soon as they are rasterized
+        self.cache.begin_frame();
+
+        cr.save()?;
+        cr.translate(x, y);
+
+        let result = self.draw_items(cr, layout);
+
+        cr.restore()?;
+        result
+    }
+
+    fn draw_items(&mut self, cr: &Context, layout: &Layout<Color>) -> Result<(), anyhow::Error> {
+        for line in layout.lines() {
+            for item in line.items() {
+                match item {
+                    PositionedLayoutItem::GlyphRun(glyph_run) => self.draw_glyph_run(cr, &glyph_run)?,
+                    PositionedLayoutItem::InlineBox(inline_box) => self.draw_inline_box(cr, &inline_box)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_glyph_run(&mut self, cr: &Context, glyph_run: &GlyphRun<'_, Color>) -> Result<(), anyhow::Error> {
+        let run = glyph_run.run();
+        let style = glyph_run.style();
+
+        let (color_glyphs, glyphs) = split_color_glyphs(&mut self.cache, glyph_run, &self.palette, true);
+        for glyph in &color_glyphs {
+            let surface = color_image_surface(glyph)?;
+            cr.set_source_surface(&surface, glyph.x as f64, glyph.y as f64)?;
+            cr.paint()?;
+        }
+
+        let face = self.font_face(glyph_run)?;
+        let glyphs: Vec<Glyph> = glyphs.iter()
+            .map(|glyph| Glyph::new(glyph.id as _, glyph.x as f64, glyph.y as f64))
+            .collect();
+
+        cr.set_font_face(&face);
+        cr.set_font_size(run.font_size() as f64);
+        set_color(cr, style.brush);
+        cr.show_glyphs(&glyphs)?;
+
+        let metrics = run.metrics();
+        if let Some(decoration) = &style.underline {
+            let offset = decoration.offset.unwrap_or(metrics.underline_offset);
+            let size = decoration.size.unwrap_or(metrics.underline_size);
+            draw_decoration(cr, glyph_run, decoration.brush, offset, size)?;
+        }
+        if let Some(decoration) = &style.strikethrough {
+            let offset = decoration.offset.unwrap_or(metrics.strikethrough_offset);
+            let size = decoration.size.unwrap_or(metrics.strikethrough_size);
+            draw_decoration(cr, glyph_run, decoration.brush, offset, size)?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_inline_box(&self, cr: &Context, inline_box: &PositionedInlineBox) -> Result<(), anyhow::Error> {
+        let Some(color) = self.inline_box_color else {
+            return Ok(());
+        };
+
+        cr.rectangle(inline_box.x as f64, inline_box.y as f64, inline_box.width as f64, inline_box.height as f64);
+        set_color(cr, color);
+        cr.fill()?;
+        Ok(())
+    }
+
+    /// Returns the cairo font face for the font, variation coordinates and synthesis of the run
+    fn font_face(&mut self, glyph_run: &GlyphRun<'_, Color>) -> Result<FontFace, anyhow::Error> {
+        let run = glyph_run.run();
+        let font = run.font();
+        let synthesis = run.synthesis();
+
+        let key = FaceKey {
+            blob: font.data.id(),
+            index: font.index,
+            coords: run.normalized_coords().to_vec(),
+            bold: synthesis.embolden(),
+            italic: synthesis.skew().is_some(),
+        };
+        if let Some(face) = self.faces.get(&key) {
+            return Ok(face.clone());
+        }
+
+        let mut ft_face = self.library.new_memory_face(Rc::new(font.data.as_ref().to_vec()), font.index as isize)?;
+        if !key.coords.is_empty() {
+            // Normalized coordinates are 2.14 fixed point, freetype expects 16.16
+            let coords: Vec<cairo::freetype::ffi::FT_Fixed> = key.coords.iter()
+                .map(|&coord| (coord as cairo::freetype::ffi::FT_Fixed) << 2)
+                .collect();
+            let err = unsafe {
+                cairo::freetype::ffi::FT_Set_Var_Blend_Coordinates(ft_face.raw_mut(), coords.len() as _, coords.as_ptr())
+            };
+            if err != 0 {
+                return Err(anyhow!("Unable to set variation coordinates: freetype error {}", err));
+            }
+        }
+
+        let face = FontFace::create_from_ft(&ft_face)?;
+        if key.bold {
+            face.set_synthesize(FtSynthesize::Bold);
+        }
+        if key.italic {
+            face.set_synthesize(FtSynthesize::Oblique);
+        }
+
+        self.faces.insert(key, face.clone());
+        Ok(face)
+    }
+}
+
+fn draw_decoration(cr: &Context, glyph_run: &GlyphRun<'_, Color>, color: Color, offset: f32, size: f32) -> Result<(), anyhow::Error> {
+    let y = (glyph_run.baseline() - offset) as f64;
+    cr.rectangle(glyph_run.offset() as f64, y, glyph_run.advance() as f64, size as f64);
+    set_color(cr, color);
+    cr.fill()?;
+    Ok(())
+}
+
+fn set_color(cr: &Context, color: Color) {
+    cr.set_source_rgba(
+        color.r as f64 / 255.0,
+        color.g as f64 / 255.0,
+        color.b as f64 / 255.0,
+        color.a as f64 / 255.0,
+    );
+}
+
+/// Converts a rasterized color glyph into an ARGB32 image surface
+fn color_image_surface(image: &RunColorGlyph) -> Result<ImageSurface, anyhow::Error> {
+    let mut surface = ImageSurface::create(cairo::Format::ARgb32, image.width as i32, image.height as i32)?;
+    let stride = surface.stride() as usize;
+    {
+        let mut data = surface.data()?;
+        for (y, row) in image.data.chunks_exact(image.width as usize * 4).enumerate() {
+            for (x, pixel) in row.chunks_exact(4).enumerate() {
+                // Pixels are premultiplied 32 bit values in native endianness
+                let a = pixel[3] as u32;
+                let premultiply = |c: u8| (c as u32 * a + 127) / 255;
+                let argb = (a << 24) | (premultiply(pixel[0]) << 16) | (premultiply(pixel[1]) << 8) | premultiply(pixel[2]);
+                let start = y * stride + x * 4;
+                data[start..start + 4].copy_from_slice(&argb.to_ne_bytes());
+            }
+        }
+    }
+
+    Ok(surface)
+}
+
+/// Renders the layout onto a new image surface of the given size. No display is needed.
+pub fn render_to_surface(renderer: &mut CairoRenderer, layout: &Layout<Color>, width: i32, height: i32, background: Color) -> Result<ImageSurface, anyhow::Error> {
+    let surface = ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+    {
+        let cr = Context::new(&surface)?;
+        set_color(&cr, background);
+        cr.paint()?;
+        renderer.draw_layout(&cr, layout, 0.0, 0.0)?;
+    }
+    surface.flush();
+
+    Ok(surface)
+}
+
+/// Converts an ARGB32 image surface into an RGBA image
+pub fn surface_to_image(surface: &ImageSurface) -> Result<RgbaImage, anyhow::Error> {
+    let width = surface.width() as u32;
+    let height = surface.height() as u32;
+    let stride = surface.stride() as usize;
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    surface.with_data(|data| {
+        for row in data.chunks(stride).take(height as usize) {
+            for pixel in row[..width as usize * 4].chunks_exact(4) {
+                // Pixels are premultiplied 32 bit values in native endianness
+                let argb = u32::from_ne_bytes(pixel.try_into().unwrap());
+                let a = (argb >> 24) as u8;
+                let unpremultiply = |c: u32| if a == 0 { 0 } else { ((c & 0xff) * 255 / a as u32) as u8 };
+                pixels.extend_from_slice(&[unpremultiply(argb >> 16), unpremultiply(argb >> 8), unpremultiply(argb), a]);
+            }
+        }
+    })?;
+
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("Surface has an invalid size"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::render::tests::fixture_layout;
+
+    #[test]
+    fn layout_is_rendered_to_a_surface() {
+        let mut renderer = CairoRenderer::new().unwrap();
+        let surface = render_to_surface(&mut renderer, &fixture_layout("AB", Color::BLACK), 100, 60, Color::WHITE).unwrap();
+        let image = surface_to_image(&surface).unwrap();
+
+        assert_eq!(image.dimensions(), (100, 60));
+        assert!(image.pixels().all(|pixel| pixel[3] == 255));
+        let dark = image.pixels().filter(|pixel| pixel[0] < 128).count();
+        assert!(dark > 100, "only {} dark pixels", dark);
+    }
+}
@@ -0,0 +1,276 @@
+use anyhow::anyhow;
+use parley::layout::{GlyphRun, Layout, PositionedLayoutItem};
+use swash::FontRef;
+use vello::peniko::Color;
+use crate::font_manager::color::FontPalette;
+use crate::font_manager::glyph_cache::{AtlasFormat, GlyphCache, GlyphColors, GlyphImage, RasterOptions};
+use crate::font_manager::synthesis::Synthesis;
+
+/// Gamma that is used to blend glyphs when no other gamma is set
+pub const DEFAULT_GAMMA: f32 = 2.2;
+/// Number of entries in the table that converts linear values back to the gamma of the target
+const LINEAR_STEPS: usize = 4096;
+
+/// RGBA pixels (one byte per channel, not premultiplied) to draw into. Rows follow each other
+/// without padding.
+pub struct RgbaBuffer<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+
+impl<'a> RgbaBuffer<'a> {
+    pub fn new(data: &'a mut [u8], width: u32, height: u32) -> Result<Self, anyhow::Error> {
+        if data.len() != width as usize * height as usize * 4 {
+            return Err(anyhow!("Buffer of {} bytes does not match {}x{} RGBA pixels", data.len(), width, height));
+        }
+        Ok(Self { data, width, height })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixel_mut(&mut self, x: i32, y: i32) -> Option<&mut [u8]> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return None;
+        }
+        let start = (y as usize * self.width as usize + x as usize) * 4;
+        Some(&mut self.data[start..start + 4])
+    }
+}
+
+/// Draws parley layouts on the CPU. Glyphs are rasterized with swash (in grayscale or with LCD
+/// subpixel masks) and kept in a glyph cache. Coverage is blended in linear light, so text keeps
+/// the same weight on light and dark backgrounds. LCD masks assume an opaque target.
+pub struct SwashRenderer {
+    cache: GlyphCache,
+    gamma: f32,
+    to_linear: Vec<f32>,
+    from_linear: Vec<u8>,
+    palette: FontPalette,
+}
+
+impl Default for SwashRenderer {
+    fn default() -> Self {
+        Self::new(RasterOptions::default())
+    }
+}
+
+impl SwashRenderer {
+    pub fn new(options: RasterOptions) -> Self {
+        let mut cache = GlyphCache::default();
+        cache.set_options(options);
+
+        let mut renderer = Self {
+            cache,
+            gamma: DEFAULT_GAMMA,
+            to_linear: Vec::new(),
+            from_linear: Vec::new(),
+            palette: FontPalette::default(),
+        };
+        renderer.set_gamma(DEFAULT_GAMMA);
+        renderer
+    }
+
+    pub fn options(&self) -> RasterOptions {
+        self.cache.options()
+    }
+
+    pub fn set_options(&mut self, options: RasterOptions) {
+        self.cache.set_options(options);
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    /// Sets the gamma of the target. A gamma of 1.0 blends the values of the buffer as they are.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = gamma;
+        self.to_linear = (0..256).map(|v| (v as f32 / 255.0).powf(gamma)).collect();
+        self.from_linear = (0..LINEAR_STEPS)
+            .map(|v| ((v as f32 / (LINEAR_STEPS - 1) as f32).powf(1.0 / gamma) * 255.0).round() as u8)
+            .collect();
+    }
+
+    pub fn palette(&self) -> &FontPalette {
+        &self.palette
+    }
+
+    /// Sets the palette that COLR glyphs are painted with
+    pub fn set_palette(&mut self, palette: FontPalette) {
+        self.palette = palette;
+    }
+
+    pub fn glyph_cache(&self) -> &GlyphCache {
+        &self.cache
+    }
+
+    /// Draws the glyph runs (and their underlines and strikethroughs) of the layout, with the top left
+    /// of the layout at (x, y). Inline boxes are left to the caller.
+    pub fn draw_layout(&mut self, target: &mut RgbaBuffer, layout: &Layout<Color>, x: f32, y: f32) -> Result<(), anyhow::Error> {
+        // Glyphs are drawn as soon as they are rasterized, so every layout can be a frame of its own
+        self.cache.begin_frame();
+
+        for line in layout.lines() {
+            for item in line.items() {
+                if let PositionedLayoutItem::GlyphRun(glyph_run) = item {
+                    self.draw_glyph_run(target, &glyph_run, x, y)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_glyph_run(&mut self, target: &mut RgbaBuffer, glyph_run: &GlyphRun<'_, Color>, x: f32, y: f32) -> Result<(), anyhow::Error> {
+        let run = glyph_run.run();
+        let style = glyph_run.style();
+        let font = run.font();
+        let font_ref = FontRef::from_index(font.data.as_ref(), font.index as usize)
+            .ok_or_else(|| anyhow!("Unable to parse the font of a glyph run"))?;
+        let font_id = self.cache.blob_font_id(font.data.id(), font.data.as_ref(), font.index);
+        let synthesis = Synthesis {
+            bold: run.synthesis().embolden(),
+            italic: run.synthesis().skew().is_some(),
+        };
+        let colors = GlyphColors {
+            palette: self.palette.clone(),
+            foreground: style.brush,
+        };
+
+        for glyph in glyph_run.positioned_glyphs() {
+            let image = self.cache.get_colored(font_id, &font_ref, run.normalized_coords(), synthesis, &colors, glyph.id, run.font_size(), x + glyph.x, y + glyph.y)?;
+            if let Some(image) = image {
+                self.draw_glyph(target, &image, style.brush);
+            }
+        }
+
+        let metrics = run.metrics();
+        if let Some(decoration) = &style.underline {
+            let offset = decoration.offset.unwrap_or(metrics.underline_offset);
+            let size = decoration.size.unwrap_or(metrics.underline_size);
+            self.draw_decoration(target, glyph_run, decoration.brush, x, y, offset, size);
+        }
+        if let Some(decoration) = &style.strikethrough {
+            let offset = decoration.offset.unwrap_or(metrics.strikethrough_offset);
+            let size = decoration.size.unwrap_or(metrics.strikethrough_size);
+            self.draw_decoration(target, glyph_run, decoration.brush, x, y, offset, size);
+        }
+
+        Ok(())
+    }
+
+    fn draw_glyph(&self, target: &mut RgbaBuffer, image: &GlyphImage, color: Color) {
+        let Some(page) = self.cache.page(image.page) else {
+            return;
+        };
+        let bpp = page.format().bytes_per_pixel();
+        let alpha = color.a as f32 / 255.0;
+
+        for row in 0..image.height {
+            for col in 0..image.width {
+                let Some(pixel) = target.pixel_mut(image.x + col as i32, image.y + row as i32) else {
+                    continue;
+                };
+                let idx = (((image.atlas_y + row) * page.width() + image.atlas_x + col) as usize) * bpp;
+                let src = &page.data()[idx..idx + bpp];
+
+                match page.format() {
+                    AtlasFormat::Mask => {
+                        let coverage = src[0] as f32 / 255.0 * alpha;
+                        self.blend(pixel, [color.r, color.g, color.b], [coverage; 3]);
+                    }
+                    AtlasFormat::Subpixel => {
+                        let coverage = [src[0], src[1], src[2]].map(|c| c as f32 / 255.0 * alpha);
+                        self.blend(pixel, [color.r, color.g, color.b], coverage);
+                    }
+                    AtlasFormat::Color => {
+                        let coverage = src[3] as f32 / 255.0;
+                        self.blend(pixel, [src[0], src[1], src[2]], [coverage; 3]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills the line below the glyph run from the offset (relative to the baseline, positive is up)
+    /// downwards, with the top left of the layout at (x, y)
+    #[allow(clippy::too_many_arguments)]
+    fn draw_decoration(&self, target: &mut RgbaBuffer, glyph_run: &GlyphRun<'_, Color>, color: Color, x: f32, y: f32, offset: f32, size: f32) {
+        let top = (y + glyph_run.baseline() - offset).round() as i32;
+        let bottom = top + (size.round() as i32).max(1);
+        let left = (x + glyph_run.offset()).round() as i32;
+        let right = (x + glyph_run.offset() + glyph_run.advance()).round() as i32;
+        let coverage = color.a as f32 / 255.0;
+
+        for y in top..bottom {
+            for x in left..right {
+                if let Some(pixel) = target.pixel_mut(x, y) {
+                    self.blend(pixel, [color.r, color.g, color.b], [coverage; 3]);
+                }
+            }
+        }
+    }
+
+    /// Composites the color over the pixel with a coverage for each channel, in linear light. The
+    /// alpha of the pixel is taken into account, so colors are not mixed with the color of
+    /// transparent pixels.
+    fn blend(&self, pixel: &mut [u8], color: [u8; 3], coverage: [f32; 3]) {
+        let dst_alpha = pixel[3] as f32 / 255.0;
+        let max_coverage = coverage[0].max(coverage[1]).max(coverage[2]);
+        let alpha = max_coverage + dst_alpha * (1.0 - max_coverage);
+        if alpha <= 0.0 {
+            return;
+        }
+
+        for c in 0..3 {
+            let dst = self.to_linear[pixel[c] as usize];
+            let src = self.to_linear[color[c] as usize];
+            let value = ((src * coverage[c] + dst * dst_alpha * (1.0 - coverage[c])) / alpha).min(1.0);
+            pixel[c] = self.from_linear[(value * (LINEAR_STEPS - 1) as f32).round() as usize];
+        }
+        pixel[3] = (alpha * 255.0).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::render::tests::fixture_layout;
+
+    fn draw(layout: &Layout<Color>, x: f32, y: f32) -> Vec<u8> {
+        let mut data = vec![0; 100 * 100 * 4];
+        let mut target = RgbaBuffer::new(&mut data, 100, 100).unwrap();
+        SwashRenderer::default().draw_layout(&mut target, layout, x, y).unwrap();
+        data
+    }
+
+    #[test]
+    fn layout_is_drawn_at_its_position() {
+        let layout = fixture_layout("AB", Color::BLACK);
+        let at_origin = draw(&layout, 0.0, 0.0);
+        let moved = draw(&layout, 0.0, 30.0);
+        assert!(at_origin.iter().any(|v| *v != 0));
+
+        // Glyphs, underlines and strikethroughs all move down by the same amount
+        let row = 100 * 4;
+        assert_eq!(&moved[30 * row..], &at_origin[..70 * row]);
+        assert!(moved[..30 * row].iter().all(|v| *v == 0));
+    }
+
+    #[test]
+    fn edges_on_a_transparent_target_keep_the_text_color() {
+        let data = draw(&fixture_layout("AB", Color::WHITE), 0.0, 0.0);
+        let pixels: Vec<&[u8]> = data.chunks(4).filter(|p| p[3] != 0).collect();
+
+        // Antialiased edges are only more transparent, not darker
+        assert!(pixels.iter().any(|p| p[3] < 255));
+        assert!(pixels.iter().all(|p| p[..3] == [255, 255, 255]), "{:?}", pixels.iter().find(|p| p[..3] != [255, 255, 255]));
+    }
+}
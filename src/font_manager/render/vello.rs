@@ -0,0 +1,253 @@
+use std::num::NonZeroUsize;
+use anyhow::anyhow;
+use image::RgbaImage;
+use log::info;
+use parley::layout::{GlyphRun, Layout, PositionedLayoutItem};
+use vello::kurbo::{Affine, Rect};
+use vello::peniko::{Color, Fill, Format, Image};
+use vello::skrifa::instance::NormalizedCoord;
+use vello::{wgpu, AaConfig, AaSupport, Glyph, RenderParams, Renderer, RendererOptions, Scene};
+use crate::font_manager::color::FontPalette;
+use crate::font_manager::glyph_cache::GlyphCache;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::render::split_color_glyphs;
+
+/// Draws parley layouts into vello scenes. Synthetic bold and italic that parley selected for a run
+/// are applied. Vello draws COLR and bitmap glyphs itself, but only with the first palette.
+/// OpenType-SVG glyphs, and COLR glyphs with any other palette, are rasterized by the font manager,
+/// kept in a glyph cache and drawn as images. A renderer should be reused between draws.
+#[derive(Default)]
+pub struct VelloRenderer {
+    cache: GlyphCache,
+    /// Palette that COLR glyphs are painted with
+    pub palette: FontPalette,
+}
+
+impl VelloRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draws the glyph runs (and their underlines and strikethroughs) of the layout into the scene
+    pub fn draw_layout(&mut self, scene: &mut Scene, layout: &Layout<Color>, transform: Affine) {
+        // Color glyphs are copied out of the glyph cache as soon as they are rasterized
+        self.cache.begin_frame();
+
+        for line in layout.lines() {
+            for item in line.items() {
+                if let PositionedLayoutItem::GlyphRun(glyph_run) = item {
+                    self.draw_glyph_run(scene, &glyph_run, transform);
+                }
+            }
+        }
+    }
+
+    fn draw_glyph_run(&mut self, scene: &mut Scene, glyph_run: &GlyphRun<'_, Color>, transform: Affine) {
+        let run = glyph_run.run();
+        let style = glyph_run.style();
+        let synthesis = run.synthesis();
+
+        let coords: Vec<NormalizedCoord> = run.normalized_coords().iter()
+            .map(|&coord| NormalizedCoord::from_bits(coord))
+            .collect();
+        let glyph_transform = synthesis.skew()
+            .map(|angle| Affine::skew(angle.to_radians().tan() as f64, 0.0));
+
+        let colr = self.palette != FontPalette::default();
+        let (color_glyphs, glyphs) = split_color_glyphs(&mut self.cache, glyph_run, &self.palette, colr);
+        for glyph in color_glyphs {
+            let image = Image::new(glyph.data.into(), Format::Rgba8, glyph.width, glyph.height);
+            scene.draw_image(&image, transform * Affine::translate((glyph.x as f64, glyph.y as f64)));
+        }
+
+        let glyphs = glyphs.iter().map(|glyph| Glyph {
+            id: glyph.id as u32,
+            x: glyph.x,
+            y: glyph.y,
+        });
+
+        scene.draw_glyphs(run.font())
+            .font_size(run.font_size())
+            .transform(transform)
+            .glyph_transform(glyph_transform)
+            .normalized_coords(&coords)
+            .brush(style.brush)
+            .draw(Fill::NonZero, glyphs.clone());
+
+        if synthesis.embolden() {
+            // Vello can not embolden outlines, so the glyphs are stroked as well
+            let stroke = vello::kurbo::Stroke::new((run.font_size() / 24.0) as f64);
+            scene.draw_glyphs(run.font())
+                .font_size(run.font_size())
+                .transform(transform)
+                .glyph_transform(glyph_transform)
+                .normalized_coords(&coords)
+                .brush(style.brush)
+                .draw(&stroke, glyphs);
+        }
+
+        let metrics = run.metrics();
+        if let Some(decoration) = &style.underline {
+            let offset = decoration.offset.unwrap_or(metrics.underline_offset);
+            let size = decoration.size.unwrap_or(metrics.underline_size);
+            draw_decoration(scene, glyph_run, decoration.brush, offset, size, transform);
+        }
+        if let Some(decoration) = &style.strikethrough {
+            let offset = decoration.offset.unwrap_or(metrics.strikethrough_offset);
+            let size = decoration.size.unwrap_or(metrics.strikethrough_size);
+            draw_decoration(scene, glyph_run, decoration.brush, offset, size, transform);
+        }
+    }
+}
+
+fn draw_decoration(scene: &mut Scene, glyph_run: &GlyphRun<'_, Color>, color: Color, offset: f32, size: f32, transform: Affine) {
+    let y = (glyph_run.baseline() - offset) as f64;
+    let x = glyph_run.offset() as f64;
+    let rect = Rect::new(x, y, x + glyph_run.advance() as f64, y + size as f64);
+    scene.fill(Fill::NonZero, transform, color, None, &rect);
+}
+
+/// Renders the scene into an image without a window. Vello always renders with wgpu, so a wgpu
+/// adapter is required; there is no path that only uses the CPU. When there is no GPU, the fallback
+/// adapter of the system is used, which has to be installed (like llvmpipe or lavapipe on Linux, or
+/// WARP on Windows). An error is returned when no adapter is found, or when the size is zero or
+/// larger than the adapter supports.
+pub fn render_to_image(scene: &Scene, width: u32, height: u32, background: Color) -> Result<RgbaImage, anyhow::Error> {
+    if width == 0 || height == 0 {
+        return Err(anyhow!("Unable to render an image of {}x{} pixels", width, height));
+    }
+
+    let adapter = request_adapter().ok_or_else(|| anyhow!("No wgpu adapter found, not even a fallback adapter"))?;
+    info!(target: LOG_TARGET, "Rendering with adapter {}", adapter.get_info().name);
+
+    let limits = adapter.limits();
+    let max_size = limits.max_texture_dimension_2d;
+    if width > max_size || height > max_size {
+        return Err(anyhow!("Unable to render an image of {}x{} pixels, the adapter supports up to {}x{}", width, height, max_size, max_size));
+    }
+
+    // Rows of a texture copy must be aligned to 256 bytes
+    let row_len = width * 4;
+    let padded_row_len = row_len.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    if padded_row_len as u64 * height as u64 > limits.max_buffer_size {
+        return Err(anyhow!("Unable to render an image of {}x{} pixels, it does not fit in a buffer of the adapter", width, height));
+    }
+
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        label: Some("gosub-fontmanager"),
+        required_features: wgpu::Features::empty(),
+        required_limits: limits,
+        memory_hints: wgpu::MemoryHints::default(),
+    }, None))?;
+
+    let mut renderer = Renderer::new(&device, RendererOptions {
+        surface_format: None,
+        use_cpu: true,
+        antialiasing_support: AaSupport::area_only(),
+        num_init_threads: NonZeroUsize::new(1),
+    }).map_err(|e| anyhow!("Unable to create vello renderer: {}", e))?;
+
+    let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("gosub-fontmanager target"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    renderer.render_to_texture(&device, &queue, scene, &view, &RenderParams {
+        base_color: background,
+        width,
+        height,
+        antialiasing_method: AaConfig::Area,
+    }).map_err(|e| anyhow!("Unable to render scene: {}", e))?;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("gosub-fontmanager readback"),
+        size: padded_row_len as u64 * height as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_len),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv()??;
+
+    let mapped = slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity((row_len * height) as usize);
+    for row in mapped.chunks_exact(padded_row_len as usize) {
+        pixels.extend_from_slice(&row[..row_len as usize]);
+    }
+    drop(mapped);
+    buffer.unmap();
+
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("Rendered image has an invalid size"))
+}
+
+/// Returns the adapter of a GPU, or the fallback adapter when there is none
+fn request_adapter() -> Option<wgpu::Adapter> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    [false, true].into_iter().find_map(|force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter,
+            compatible_surface: None,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::render::tests::fixture_layout;
+
+    const REFERENCE: &[u8] = include_bytes!("../../../tests/fixtures/vello-reference.png");
+
+    /// Requires a wgpu adapter, see the crate documentation
+    #[test]
+    fn layout_matches_the_reference_image() {
+        let mut scene = Scene::new();
+        VelloRenderer::new().draw_layout(&mut scene, &fixture_layout("AB", Color::BLACK), Affine::translate((4.0, 4.0)));
+        let image = render_to_image(&scene, 100, 60, Color::WHITE).unwrap();
+
+        // Adapters antialias a little differently, so only pixels that differ a lot are counted
+        let reference = image::load_from_memory(REFERENCE).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), reference.dimensions());
+        let differences = image.pixels().zip(reference.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0.iter()).any(|(a, b)| a.abs_diff(*b) > 48))
+            .count();
+        assert!(differences < 30, "{} pixels differ from the reference image", differences);
+    }
+
+    #[test]
+    fn empty_or_oversized_images_are_rejected() {
+        let scene = Scene::new();
+        assert!(render_to_image(&scene, 0, 10, Color::WHITE).is_err());
+        assert!(render_to_image(&scene, 10, 0, Color::WHITE).is_err());
+        assert!(render_to_image(&scene, u32::MAX, 10, Color::WHITE).is_err());
+    }
+}
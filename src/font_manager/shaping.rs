@@ -0,0 +1,172 @@
+use std::ops::Range;
+use anyhow::anyhow;
+use swash::shape::ShapeContext;
+use swash::text::{Codepoint, Language, Script};
+use swash::FontRef;
+use crate::font_manager::features::FontFeatures;
+use crate::font_manager::font_info::FontInfo;
+
+/// Direction in which text is shaped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Options for shaping text
+#[derive(Clone, Debug, Default)]
+pub struct ShapeOptions {
+    /// OpenType features to turn on or off
+    pub features: FontFeatures,
+    pub direction: Direction,
+    /// OpenType script tag (like "latn" or "arab"). Detected from the text when not given.
+    pub script: Option<String>,
+    /// BCP 47 language tag (like "en" or "nl-BE")
+    pub language: Option<String>,
+}
+
+/// Shaped glyph. Offsets and advance are in pixels, with a y axis that points up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapedGlyph {
+    pub id: u16,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub advance: f32,
+}
+
+/// Glyphs that are shaped from a range of the text. A cluster can contain multiple characters (like
+/// a ligature) and multiple glyphs (like a base with marks), and may have no glyphs at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapedCluster {
+    /// Byte range of the cluster in the shaped text
+    pub range: Range<usize>,
+    pub glyphs: Vec<ShapedGlyph>,
+    pub advance: f32,
+    /// Cluster is whitespace, which is where lines can be broken
+    pub whitespace: bool,
+}
+
+/// Result of shaping text with a single face. Clusters are in visual order, so right-to-left text
+/// starts with the cluster at the end of the text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapedText {
+    pub clusters: Vec<ShapedCluster>,
+    pub direction: Direction,
+}
+
+impl ShapedText {
+    /// Returns the glyphs of all clusters, in visual order
+    pub fn glyphs(&self) -> impl Iterator<Item = &ShapedGlyph> {
+        self.clusters.iter().flat_map(|cluster| cluster.glyphs.iter())
+    }
+
+    /// Returns the total advance of the text
+    pub fn advance(&self) -> f32 {
+        self.clusters.iter().map(|cluster| cluster.advance).sum()
+    }
+}
+
+/// Shapes the text with the face at the given size (in pixels per em). The font data is loaded from
+/// the file or memory of the face, so the result does not depend on the source the face was found
+/// through. The coordinates of variable faces are applied.
+pub fn shape(text: &str, font_info: &FontInfo, size: f32, options: &ShapeOptions) -> Result<ShapedText, anyhow::Error> {
+    let data = font_info.load_data()?;
+    let font = FontRef::from_index(&data, font_info.face_index())
+        .ok_or_else(|| anyhow!("Unable to parse font data for {}", font_info.family))?;
+
+    let mut context = ShapeContext::new();
+    Ok(shape_font(&mut context, &font, &font_info.normalized_coords_of(&font), text, size, options))
+}
+
+/// Shapes the text with an already parsed face and normalized variation coordinates
+pub(crate) fn shape_font(context: &mut ShapeContext, font: &FontRef, coords: &[i16], text: &str, size: f32, options: &ShapeOptions) -> ShapedText {
+    let script = options.script.as_deref()
+        .and_then(script_from_tag)
+        .unwrap_or_else(|| detect_script(text));
+    let direction = match options.direction {
+        Direction::LeftToRight => swash::shape::Direction::LeftToRight,
+        Direction::RightToLeft => swash::shape::Direction::RightToLeft,
+    };
+    let settings = options.features.settings();
+    let features: Vec<(&str, u16)> = settings.iter().map(|(tag, value)| (tag.as_str(), *value)).collect();
+
+    let mut shaper = context.builder(*font)
+        .script(script)
+        .language(options.language.as_deref().and_then(Language::parse))
+        .direction(direction)
+        .size(size)
+        .features(features)
+        .normalized_coords(coords)
+        .build();
+    shaper.add_str(text);
+
+    let mut clusters = Vec::new();
+    shaper.shape_with(|cluster| {
+        clusters.push(ShapedCluster {
+            range: cluster.source.start as usize..cluster.source.end as usize,
+            glyphs: cluster.glyphs.iter()
+                .map(|g| ShapedGlyph { id: g.id, x_offset: g.x, y_offset: g.y, advance: g.advance })
+                .collect(),
+            advance: cluster.advance(),
+            whitespace: cluster.info.is_whitespace(),
+        });
+    });
+
+    // Clusters are shaped in logical order
+    if options.direction == Direction::RightToLeft {
+        clusters.reverse();
+    }
+
+    ShapedText { clusters, direction: options.direction }
+}
+
+/// Returns the script of the first character that has a specific script, or latin when there is none
+fn detect_script(text: &str) -> Script {
+    text.chars()
+        .map(|ch| ch.script())
+        .find(|script| !matches!(script, Script::Common | Script::Inherited | Script::Unknown))
+        .unwrap_or(Script::Latin)
+}
+
+/// Splits the text into runs of a single script. Characters that are shared between scripts (like
+/// spaces, digits and punctuation) and combining marks belong to the run before them, or to the first
+/// run when the text starts with them.
+pub(crate) fn script_runs(text: &str) -> Vec<Range<usize>> {
+    let mut starts = vec![0];
+    let mut current = None;
+    for (idx, ch) in text.char_indices() {
+        let script = ch.script();
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+        if current.is_some_and(|current| current != script) {
+            starts.push(idx);
+        }
+        current = Some(script);
+    }
+
+    let ends = starts.iter().skip(1).copied().chain([text.len()]);
+    starts.iter().zip(ends).map(|(start, end)| *start..end).collect()
+}
+
+fn script_from_tag(tag: &str) -> Option<Script> {
+    let bytes: [u8; 4] = tag.to_ascii_lowercase().as_bytes().try_into().ok()?;
+    Script::from_opentype(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_split_into_script_runs() {
+        assert_eq!(script_runs(""), vec![0..0]);
+        assert_eq!(script_runs("abc, def"), vec![0..8]);
+
+        // Shared characters belong to the run before them
+        let text = "(abc) \u{5e9}\u{5dc}\u{5d5}\u{5dd}! \u{3b1}\u{3b2}";
+        let runs: Vec<&str> = script_runs(text).into_iter().map(|run| &text[run]).collect();
+        assert_eq!(runs, vec!["(abc) ", "\u{5e9}\u{5dc}\u{5d5}\u{5dd}! ", "\u{3b1}\u{3b2}"]);
+    }
+}
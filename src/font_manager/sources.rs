@@ -1,7 +1,10 @@
+use crate::font_manager::error::FontError;
 use crate::font_manager::font_info::FontInfo;
+use crate::font_manager::generic::GenericFamily;
 use std::any::Any;
 use std::path::{Path, PathBuf};
 
+pub mod memory;
 #[cfg(feature = "source_fontkit")]
 pub mod fontkit;
 #[cfg(feature = "source_parley")]
@@ -10,8 +13,30 @@ pub mod parley;
 pub mod pango;
 
 pub trait FontSource: AsAny {
-    fn new() -> Self where Self: Sized;
+    fn new() -> Result<Self, FontError> where Self: Sized;
     fn available_fonts(&self) -> &[FontInfo];
+
+    /// Returns the errors of the font files that have been skipped because they could not be loaded
+    fn diagnostics(&self) -> &[FontError] {
+        &[]
+    }
+
+    /// Returns the concrete family names the source maps the generic family onto, in order of
+    /// preference. Sources that have no knowledge of generic families return an empty list.
+    fn generic_families(&self, _generic: GenericFamily) -> Vec<String> {
+        vec![]
+    }
+
+    /// Adds the faces of a font file that has been installed while running. Returns the added faces.
+    fn add_font_file(&mut self, _path: &Path) -> Vec<FontInfo> {
+        vec![]
+    }
+
+    /// Removes the faces of a font file that has been uninstalled while running. Returns the removed
+    /// faces.
+    fn remove_font_file(&mut self, _path: &Path) -> Vec<FontInfo> {
+        vec![]
+    }
 }
 
 pub trait AsAny {
@@ -33,10 +58,12 @@ pub enum FontSourceType {
     Parley,
     #[cfg(feature = "source_pango")]
     Pango,
+    /// Fonts registered from memory (web fonts)
+    Memory,
 }
 
 /// Resolves a symlinked path
-fn resolve_symlink(path: PathBuf) -> PathBuf {
+pub(crate) fn resolve_symlink(path: PathBuf) -> PathBuf {
     let mut resolved_path = path.clone();
 
     loop {
@@ -1,13 +1,22 @@
 use crate::font_manager::manager::LOG_TARGET;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
 use anyhow::anyhow;
+use font_kit::error::FontLoadingError;
+use font_kit::family_name::FamilyName;
 use font_kit::handle::Handle;
-use font_kit::source::SystemSource;
+use font_kit::source::{Source, SystemSource};
+use freetype::face::LoadFlag;
 use freetype::{Face, Library};
-use log::{error, info};
-use crate::font_manager::font_info::{FontInfo, FontStyle};
+use log::{error, info, warn};
+use crate::font_manager::cache::FontCache;
+use crate::font_manager::catalogue::{key_for, FaceKey};
+use crate::font_manager::error::FontError;
+use crate::font_manager::font_info::{scanned_variations, FontData, FontInfo, FontStyle};
+use crate::font_manager::generic::GenericFamily;
+use crate::font_manager::monospace::is_monospaced_file;
+use crate::font_manager::reader::FontFile;
 use crate::font_manager::sources::{resolve_symlink, FontSource, FontSourceType};
 
 #[allow(unused)]
@@ -18,87 +27,339 @@ pub struct FontKitSource {
     font_info: Vec<FontInfo>,
     /// List of all font handles
     handles: Vec<Handle>,
-    /// Cache of font faces that are loaded through freetype
-    face_cache: Arc<Mutex<HashMap<String, Face>>>,
+    /// Faces that are loaded through freetype, by face instance
+    face_cache: RefCell<HashMap<FreetypeKey, Face<FontData>>>,
+    /// Font files that have been skipped because they could not be loaded
+    diagnostics: Vec<FontError>,
 }
 
 impl FontSource for FontKitSource {
-    fn new() -> Self {
-        let library = Library::init().expect("unable to init freetype library");
+    fn new() -> Result<Self, FontError> {
+        let library = Library::init().map_err(|e| FontError::BackendInit {
+            source_type: FontSourceType::Fontkit,
+            reason: format!("unable to init freetype library: {}", e),
+        })?;
 
         let source = SystemSource::new();
-        let handles = source.all_fonts().unwrap();
+        let mut cache = FontCache::default_path(FontSourceType::Fontkit).map(|path| FontCache::load(&path));
+        let mut diagnostics = Vec::new();
 
-        let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+        // When no fonts have been installed or removed, the fonts are taken from the cache as-is
+        let (font_info, handles) = match cache.as_ref().and_then(cached_fonts) {
+            Some(font_info) => {
+                info!("Loaded {} fonts from fontkit cache.", font_info.len());
 
-        let mut font_info = Vec::new();
-        for handle in &handles {
-            match handle_to_info(&mut seen_paths, handle) {
-                Ok(info) => font_info.push(info),
-                Err(_) => {}
+                let handles = font_info.iter()
+                    .filter_map(|fi| Some(Handle::from_path(fi.path.clone()?, fi.index.unwrap_or(0) as u32)))
+                    .collect();
+                (font_info, handles)
             }
-        }
+            None => {
+                let handles = source.all_fonts().map_err(|e| FontError::BackendInit {
+                    source_type: FontSourceType::Fontkit,
+                    reason: format!("unable to list fonts: {}", e),
+                })?;
+
+                let mut seen_faces: HashSet<(PathBuf, u32)> = HashSet::new();
+
+                let mut font_info = Vec::new();
+                for handle in &handles {
+                    match handle_to_info(&mut seen_faces, handle, cache.as_ref()) {
+                        Ok(Some(info)) => font_info.push(info),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!(target: LOG_TARGET, "Skipping font: {}", e);
+                            diagnostics.push(e);
+                        }
+                    }
+                }
 
-        info!("Loaded {} fonts from fontkit.", font_info.len());
+                info!("Loaded {} fonts from fontkit.", font_info.len());
+
+                if let Some(cache) = cache.as_mut() {
+                    cache.update(&font_info);
+                    if let Err(e) = cache.save() {
+                        warn!(target: LOG_TARGET, "Unable to write font cache: {}", e);
+                    }
+                }
+                (font_info, handles)
+            }
+        };
 
-        Self {
+        Ok(Self {
             source,
             ft_library: library,
             font_info,
             handles,
-            face_cache: Arc::new(Mutex::new(HashMap::new())),
-        }
+            face_cache: RefCell::new(HashMap::new()),
+            diagnostics,
+        })
     }
 
     fn available_fonts(&self) -> &[FontInfo] {
         &self.font_info
     }
+
+    fn diagnostics(&self) -> &[FontError] {
+        &self.diagnostics
+    }
+
+    /// Font-kit resolves the CSS 2 generic families through the fontconfig aliases on Linux
+    fn generic_families(&self, generic: GenericFamily) -> Vec<String> {
+        let family_name = match generic {
+            GenericFamily::Serif => FamilyName::Serif,
+            GenericFamily::SansSerif => FamilyName::SansSerif,
+            GenericFamily::Monospace => FamilyName::Monospace,
+            GenericFamily::Cursive => FamilyName::Cursive,
+            GenericFamily::Fantasy => FamilyName::Fantasy,
+            _ => return vec![],
+        };
+
+        let Ok(family) = self.source.select_family_by_generic_name(&family_name) else {
+            return vec![];
+        };
+
+        family.fonts().first()
+            .and_then(|handle| handle.load().ok())
+            .map(|font| vec![font.family_name()])
+            .unwrap_or_default()
+    }
+
+    fn add_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let face_count = match std::fs::read(path) {
+            Ok(data) => swash::FontDataRef::new(&data).map(|fd| fd.len()).unwrap_or(0),
+            Err(e) => {
+                let e = FontError::UnreadableFile { path: path.to_path_buf(), reason: e.to_string() };
+                warn!(target: LOG_TARGET, "Skipping font: {}", e);
+                self.diagnostics.push(e);
+                return vec![];
+            }
+        };
+
+        let mut added = Vec::new();
+        for index in 0..face_count {
+            let handle = Handle::from_path(path.to_path_buf(), index as u32);
+            match handle_to_info(&mut HashSet::new(), &handle, None) {
+                Ok(Some(info)) => {
+                    added.push(info);
+                    self.handles.push(handle);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Skipping font: {}", e);
+                    self.diagnostics.push(e);
+                }
+            }
+        }
+
+        self.font_info.extend(added.iter().cloned());
+        added
+    }
+
+    fn remove_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let (removed, kept) = self.font_info.drain(..).partition(|fi| fi.path.as_deref() == Some(path));
+        self.font_info = kept;
+
+        self.handles.retain(|handle| !matches!(handle, Handle::Path { path: p, .. } if resolve_symlink(p.clone()) == path));
+        removed
+    }
 }
 
+/// Face instance of a face loaded through freetype: the face, its variation coordinates (as bits)
+/// and synthetic italic
+type FreetypeKey = (FaceKey, Vec<(String, u32)>, bool);
+
 impl FontKitSource {
-    pub fn load_freetype_font(&self, font_info: &FontInfo) -> Result<Face, anyhow::Error> {
-        let path = font_info.path.as_ref().ok_or_else(|| anyhow!("No path in font info"))?;
-        let face = self.ft_library.new_face(path, font_info.index.unwrap_or(0) as isize)?;
+    /// Loads the face through freetype. The variation coordinates of the font info are applied to
+    /// variable fonts, and synthetic italic is applied through the transform of the face. Freetype can
+    /// only embolden glyphs after they are loaded, so glyphs must be loaded with `load_freetype_glyph`
+    /// to apply synthetic bold.
+    ///
+    /// Freetype reads the face from the font data that is shared with the font info, and every face
+    /// instance is loaded only once. The returned face is shared with later calls for the same instance.
+    pub fn load_freetype_font(&self, font_info: &FontInfo) -> Result<Face<FontData>, anyhow::Error> {
+        let key = (
+            key_for(font_info),
+            font_info.coordinates.iter().map(|(tag, value)| (tag.clone(), value.to_bits())).collect(),
+            font_info.synthesis.italic,
+        );
+        if let Some(face) = self.face_cache.borrow().get(&key) {
+            return Ok(face.clone());
+        }
+
+        let mut face = self.ft_library.new_memory_face2(font_info.font_data()?, font_info.face_index() as isize)?;
+
+        if !font_info.coordinates.is_empty() {
+            set_variation_coordinates(&mut face, font_info)?;
+        }
+
+        if font_info.synthesis.italic {
+            // Glyphs are skewed to the right while they are loaded, the matrix is in 16.16 fixed point
+            let mut matrix = freetype::Matrix {
+                xx: 0x10000,
+                xy: (font_info.synthesis.skew() * 65536.0).round() as freetype::ffi::FT_Fixed,
+                yx: 0,
+                yy: 0x10000,
+            };
+            let mut delta = freetype::Vector { x: 0, y: 0 };
+            face.set_transform(&mut matrix, &mut delta);
+        }
+
+        self.face_cache.borrow_mut().insert(key, face.clone());
         Ok(face)
     }
-}
 
-/// Converts a font handle to a gosub font info structure
-fn handle_to_info(seen_paths: &mut HashSet<PathBuf>, handle: &Handle) -> Result<FontInfo, anyhow::Error> {
-    let font = handle.load().unwrap();
+    /// Loads the glyph into the glyph slot of a face loaded with `load_freetype_font`, at the size the
+    /// face is set to. When the font info asks for synthetic bold, the outline is emboldened by the
+    /// strength of the synthesis and the advance of the glyph grows by the same amount.
+    pub fn load_freetype_glyph(face: &Face<FontData>, font_info: &FontInfo, glyph_id: u32, flags: LoadFlag) -> Result<(), anyhow::Error> {
+        face.load_glyph(glyph_id, flags)?;
+        if !font_info.synthesis.bold {
+            return Ok(());
+        }
 
-    let family = font.family_name();
-    let props = font.properties();
+        let metrics = face.size_metrics().ok_or_else(|| anyhow!("No size set on the face"))?;
+        let size = face.raw().units_per_EM as f32 * metrics.y_scale as f32 / 65536.0 / 64.0;
+        // Freetype positions are in 26.6 fixed point
+        let strength = (font_info.synthesis.embolden_strength(size) * 64.0).round() as freetype::ffi::FT_Pos;
 
-    let style = match props.style {
-        font_kit::properties::Style::Normal => FontStyle::Normal,
-        font_kit::properties::Style::Italic => FontStyle::Italic,
-        font_kit::properties::Style::Oblique => FontStyle::Oblique,
+        let slot = face.raw().glyph;
+        unsafe {
+            if (*slot).format != freetype::ffi::FT_GLYPH_FORMAT_OUTLINE {
+                return Ok(());
+            }
+
+            let err = freetype::ffi::FT_Outline_Embolden(&mut (*slot).outline, strength);
+            if err != 0 {
+                return Err(anyhow!("Unable to embolden glyph {}: freetype error {}", glyph_id, err));
+            }
+
+            (*slot).metrics.width += strength;
+            (*slot).metrics.height += strength;
+            (*slot).metrics.horiAdvance += strength;
+            (*slot).advance.x += strength;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the design coordinates of a variable font face. Freetype expects a value (in 16.16 fixed
+/// point) for every axis, in the order of the fvar table.
+fn set_variation_coordinates(face: &mut Face<FontData>, font_info: &FontInfo) -> Result<(), anyhow::Error> {
+    let coords: Vec<freetype::ffi::FT_Fixed> = font_info.design_coordinates()
+        .into_iter()
+        .map(|value| (value * 65536.0).round() as freetype::ffi::FT_Fixed)
+        .collect();
+
+    let err = unsafe {
+        freetype::ffi::FT_Set_Var_Design_Coordinates(face.raw_mut(), coords.len() as freetype::ffi::FT_UInt, coords.as_ptr())
     };
+    if err != 0 {
+        return Err(anyhow!("Unable to set variation coordinates for {}: freetype error {}", font_info.family, err));
+    }
 
+    Ok(())
+}
+
+/// Returns all fonts from the cache, or None when the cache is outdated
+fn cached_fonts(cache: &FontCache) -> Option<Vec<FontInfo>> {
+    if !cache.is_fresh() {
+        return None;
+    }
+
+    let mut font_info = Vec::new();
+    for path in cache.paths() {
+        font_info.extend(cache.faces(&path, FontSourceType::Fontkit)?);
+    }
+
+    Some(font_info)
+}
+
+/// Converts a font handle to a gosub font info structure. Faces of font files that have not changed
+/// since they were cached are taken from the cache instead of being loaded. Returns None for handles
+/// that are skipped (like faces that have been seen before).
+fn handle_to_info(seen_faces: &mut HashSet<(PathBuf, u32)>, handle: &Handle, cache: Option<&FontCache>) -> Result<Option<FontInfo>, FontError> {
     let Handle::Path {
         ref path,
         font_index,
     } = handle else {
         error!(target: LOG_TARGET, "Expected a path handle. Got: {:?}", handle);
-        return Err(anyhow!("Expected a path handle"));
+        return Ok(None);
     };
 
     // Check if the path is symlinked
     let resolved_path = resolve_symlink(path.to_path_buf());
-    if seen_paths.contains(&resolved_path) {
-        return Err(anyhow!("Path already seen"));
+    if !seen_faces.insert((resolved_path.clone(), *font_index)) {
+        return Ok(None);
+    }
+
+    if let Some(info) = cache.and_then(|cache| cache.face(&resolved_path, *font_index as i32, FontSourceType::Fontkit)) {
+        return Ok(Some(info));
     }
-    seen_paths.insert(resolved_path.clone());
 
-    Ok(FontInfo {
-        source_type: FontSourceType::Fontkit,
-        family,
+    let font = handle.load().map_err(|e| match e {
+        FontLoadingError::Io(e) => FontError::UnreadableFile { path: resolved_path.clone(), reason: e.to_string() },
+        FontLoadingError::NoSuchFontInCollection => FontError::FaceIndexOutOfRange { path: Some(resolved_path.clone()), index: *font_index as usize },
+        e => FontError::UnsupportedFormat { path: Some(resolved_path.clone()), reason: e.to_string() },
+    })?;
+
+    let family = font.family_name();
+    let props = font.properties();
+
+    // Font-kit only looks at the isFixedPitch flag, so detect monospacing the same way as the other
+    // sources do. Files that are not sfnt fonts (like Type 1 fonts) fall back to the font-kit flag.
+    let monospaced = is_monospaced_file(&resolved_path, *font_index as usize)
+        .unwrap_or_else(|_| font.is_monospace());
+    let variable = FontFile::open(&resolved_path, *font_index as usize)
+        .is_ok_and(|file| file.has_table(u32::from_be_bytes(*b"fvar")));
+
+    let style = match props.style {
+        font_kit::properties::Style::Normal => FontStyle::Normal,
+        font_kit::properties::Style::Italic => FontStyle::Italic,
+        font_kit::properties::Style::Oblique => FontStyle::Oblique,
+    };
+
+    Ok(Some(FontInfo {
         style,
         weight: props.weight.0,
         stretch: props.stretch.0,
-        monospaced: font.is_monospace(),
+        monospaced,
         path: Some(resolved_path.clone()),
         index: Some(*font_index as i32),
-    })
+        variations: scanned_variations(variable),
+        ..FontInfo::new(FontSourceType::Fontkit, family)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::fixtures::memory_face;
+    use crate::font_manager::synthesis::Synthesis;
+
+    /// Returns the horizontal extent of the outline of the glyph in the glyph slot
+    fn outline_width(face: &Face<FontData>) -> freetype::ffi::FT_Pos {
+        let outline = face.glyph().outline().unwrap();
+        let xs = outline.points().iter().map(|point| point.x);
+        xs.clone().max().unwrap() - xs.min().unwrap()
+    }
+
+    #[test]
+    fn synthetic_bold_widens_the_outline() {
+        let library = Library::init().unwrap();
+        let plain = memory_face();
+        let bold = FontInfo { synthesis: Synthesis { bold: true, italic: false }, ..plain.clone() };
+        let face = library.new_memory_face2(plain.data.clone().unwrap(), 0).unwrap();
+        face.set_pixel_sizes(0, 48).unwrap();
+
+        // The .notdef glyph is a box, which grows by the full strength (unlike the sharp corners of A)
+        FontKitSource::load_freetype_glyph(&face, &plain, 0, LoadFlag::NO_HINTING).unwrap();
+        let (width, advance) = (outline_width(&face), face.glyph().advance().x);
+
+        FontKitSource::load_freetype_glyph(&face, &bold, 0, LoadFlag::NO_HINTING).unwrap();
+        // 1/24 em at 48 pixels is 2 pixels, in 26.6 fixed point
+        assert_eq!(outline_width(&face), width + 128);
+        assert_eq!(face.glyph().advance().x, advance + 128);
+    }
 }
@@ -0,0 +1,133 @@
+use log::info;
+use swash::{FontDataRef, FontRef, StringId};
+use crate::font_manager::error::FontError;
+use crate::font_manager::font_info::{FontData, FontInfo, FontStyle};
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::monospace::is_monospaced;
+use crate::font_manager::sources::{FontSource, FontSourceType};
+
+/// Descriptors of a font as given by a CSS `@font-face` rule. Any descriptor that is set overrides
+/// the value found in the font data itself.
+#[derive(Clone, Debug, Default)]
+pub struct FontDescriptors {
+    /// Family name the font is registered under (`font-family`)
+    pub family: Option<String>,
+    /// Style of the font (`font-style`)
+    pub style: Option<FontStyle>,
+    /// Weight of the font (`font-weight`)
+    pub weight: Option<f32>,
+    /// Stretch of the font (`font-stretch`)
+    pub stretch: Option<f32>,
+}
+
+/// Font source for fonts that are not installed on the system, but are registered at runtime from
+/// data in memory (for instance web fonts loaded through `@font-face`).
+pub struct MemorySource {
+    font_info: Vec<FontInfo>,
+}
+
+impl FontSource for MemorySource {
+    fn new() -> Result<Self, FontError> {
+        Ok(Self {
+            font_info: Vec::new(),
+        })
+    }
+
+    fn available_fonts(&self) -> &[FontInfo] {
+        &self.font_info
+    }
+}
+
+impl MemorySource {
+    /// Parses the TTF/OTF (or TTC/OTC collection) data and registers all faces found in it
+    pub fn register(&mut self, data: Vec<u8>, descriptors: &FontDescriptors) -> Result<Vec<FontInfo>, FontError> {
+        let data = FontData::new(data);
+
+        let font_data = FontDataRef::new(&data).ok_or_else(|| unsupported("unable to parse font data"))?;
+        if font_data.is_empty() {
+            return Err(unsupported("no faces found in font data"));
+        }
+
+        let mut fonts = Vec::new();
+        for index in 0..font_data.len() {
+            // The offset of a face in a collection may point outside the data
+            let font = font_data.get(index).ok_or(FontError::FaceIndexOutOfRange { path: None, index })?;
+            fonts.push(font_to_info(&font, data.clone(), index, descriptors)?);
+        }
+
+        info!(target: LOG_TARGET, "Registered {} faces from memory for family {}", fonts.len(), fonts[0].family);
+
+        self.font_info.extend(fonts.iter().cloned());
+        Ok(fonts)
+    }
+}
+
+/// Returns the error for font data that can not be registered
+fn unsupported(reason: &str) -> FontError {
+    FontError::UnsupportedFormat { path: None, reason: reason.to_string() }
+}
+
+/// Converts a parsed face into a gosub font info structure
+fn font_to_info(font: &FontRef, data: FontData, index: usize, descriptors: &FontDescriptors) -> Result<FontInfo, FontError> {
+    let family = match &descriptors.family {
+        Some(family) => family.clone(),
+        None => family_name(font).ok_or_else(|| unsupported("font data has no family name"))?,
+    };
+
+    let attributes = font.attributes();
+    let style = descriptors.style.clone().unwrap_or(match attributes.style() {
+        swash::Style::Normal => FontStyle::Normal,
+        swash::Style::Italic => FontStyle::Italic,
+        swash::Style::Oblique(_) => FontStyle::Oblique,
+    });
+
+    Ok(FontInfo {
+        style,
+        weight: descriptors.weight.unwrap_or(attributes.weight().0 as f32),
+        stretch: descriptors.stretch.unwrap_or(attributes.stretch().to_percentage() / 100.0),
+        monospaced: is_monospaced(font),
+        index: Some(index as i32),
+        data: Some(data),
+        ..FontInfo::new(FontSourceType::Memory, family)
+    })
+}
+
+/// Returns the family name of the face, preferring the typographic family name
+pub(crate) fn family_name(font: &FontRef) -> Option<String> {
+    let strings = font.localized_strings();
+
+    [StringId::TypographicFamily, StringId::Family]
+        .into_iter()
+        .find_map(|id| strings.find_by_id(id, Some("en")).or_else(|| strings.find_by_id(id, None)))
+        .map(|name| name.chars().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::font_manager::fixtures::FIXTURE;
+
+    #[test]
+    fn faces_are_registered_with_their_index() {
+        let mut source = MemorySource::new().unwrap();
+        let fonts = source.register(FIXTURE.to_vec(), &FontDescriptors::default()).unwrap();
+        assert_eq!(fonts.len(), 1);
+        assert_eq!(fonts[0].family, "Fixture");
+        assert_eq!(fonts[0].index, Some(0));
+        assert_eq!(source.available_fonts().len(), 1);
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        let mut source = MemorySource::new().unwrap();
+        let error = source.register(b"not a font".to_vec(), &FontDescriptors::default()).unwrap_err();
+        assert!(matches!(error, FontError::UnsupportedFormat { path: None, .. }));
+
+        // A collection with a single face, at an offset beyond the end of the data
+        let collection = [b"ttcf".as_slice(), &[0, 1, 0, 0], &[0, 0, 0, 1], &[0, 0, 0xff, 0xff]].concat();
+        let error = source.register(collection, &FontDescriptors::default()).unwrap_err();
+        assert_eq!(error, FontError::FaceIndexOutOfRange { path: None, index: 0 });
+        assert!(source.available_fonts().is_empty());
+    }
+}
@@ -1,92 +1,156 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use anyhow::Error;
-use log::info;
-use pangocairo::pango::{Context, FontMap};
-use pangocairo::prelude::{FontFaceExt, FontFamilyExt, FontMapExt};
-use crate::font_manager::font_info::{FontInfo, FontStyle};
-use crate::font_manager::sources::{FontSource, FontSourceType};
+use font_kit::handle::Handle;
+use font_kit::source::SystemSource;
+use log::{info, warn};
+use swash::StringId;
+use pangocairo::pango::{Context, FontFace, FontFamily, FontMap};
+use pangocairo::prelude::{FontExt, FontFaceExt, FontFamilyExt, FontMapExt};
+use crate::font_manager::error::FontError;
+use crate::font_manager::font_info::{scanned_variations, FontInfo, FontStyle};
+use crate::font_manager::generic::GenericFamily;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::matching::{STRETCH_NORMAL, WEIGHT_NORMAL};
+use crate::font_manager::reader::{FontFile, Reader};
+use crate::font_manager::sources::{resolve_symlink, FontSource, FontSourceType};
 
 #[allow(unused)]
 pub struct PangoSource {
     font_map: FontMap,
     context: Context,
-    font_info: Vec<FontInfo>
+    font_info: Vec<FontInfo>,
+    /// Font files that have been skipped because they could not be loaded
+    diagnostics: Vec<FontError>,
 }
 
 impl FontSource for PangoSource {
-    fn new() -> Self {
+    fn new() -> Result<Self, FontError> {
         let font_map = pangocairo::FontMap::new();
         let context = font_map.create_context();
 
-        let mut font_info = vec![];
-        for family in context.list_families() {
-
-            for face in family.list_faces() {
-                let desc = face.describe();
-
-                let style = match desc.style() {
-                    pangocairo::pango::Style::Normal => FontStyle::Normal,
-                    pangocairo::pango::Style::Oblique => FontStyle::Oblique,
-                    pangocairo::pango::Style::Italic => FontStyle::Italic,
-                    _ => FontStyle::Normal,
-                };
-
-                // Conversion taken from: https://developer.mozilla.org/en-US/docs/Web/CSS/font-stretch
-                let stretch = match desc.stretch() {
-                    pangocairo::pango::Stretch::UltraCondensed => 0.5,
-                    pangocairo::pango::Stretch::ExtraCondensed => 0.625,
-                    pangocairo::pango::Stretch::Condensed => 0.75,
-                    pangocairo::pango::Stretch::SemiCondensed => 87.5,
-                    pangocairo::pango::Stretch::Normal => 1.0,
-                    pangocairo::pango::Stretch::SemiExpanded => 1.125,
-                    pangocairo::pango::Stretch::Expanded => 1.25,
-                    pangocairo::pango::Stretch::ExtraExpanded => 1.5,
-                    pangocairo::pango::Stretch::UltraExpanded => 2.0,
-                    _ => 1.0,
-                };
-
-                let weight = match desc.weight() {
-                    pangocairo::pango::Weight::Thin => 100,
-                    pangocairo::pango::Weight::Ultralight => 200,
-                    pangocairo::pango::Weight::Semilight => 250,
-                    pangocairo::pango::Weight::Light => 300,
-                    pangocairo::pango::Weight::Book => 350,
-                    pangocairo::pango::Weight::Normal => 400,
-                    pangocairo::pango::Weight::Medium => 500,
-                    pangocairo::pango::Weight::Semibold => 600,
-                    pangocairo::pango::Weight::Bold => 700,
-                    pangocairo::pango::Weight::Ultrabold => 800,
-                    pangocairo::pango::Weight::Heavy => 900,
-                    _ => 400,
-                };
-
-                font_info.push(FontInfo {
-                    family: family.name().to_string(),
-                    style,
-                    weight: weight as f32,
-                    stretch,
-                    monospaced: family.is_monospace(),
-                    source_type: FontSourceType::Pango,
-                    path: None,
-                    index: None,
-                })
-            }
-        }
+        let font_info = list_fonts(&context);
 
         info!("Loaded {} fonts from pango.", font_info.len());
 
-        Self {
+        Ok(Self {
             font_map,
             context,
             font_info,
-        }
+            diagnostics: Vec::new(),
+        })
     }
 
     fn available_fonts(&self) -> &[FontInfo] {
         &self.font_info
     }
+
+    fn diagnostics(&self) -> &[FontError] {
+        &self.diagnostics
+    }
+
+    /// Pango passes the generic family name to fontconfig, which resolves it through its aliases
+    fn generic_families(&self, generic: GenericFamily) -> Vec<String> {
+        let mut desc = pangocairo::pango::FontDescription::new();
+        desc.set_family(generic.keyword());
+
+        let Some(font) = self.font_map.load_font(&self.context, &desc) else {
+            return vec![];
+        };
+
+        font.describe().family()
+            .map(|family| vec![family.to_string()])
+            .unwrap_or_default()
+    }
+
+    /// Pango does not know the files of its faces. The file is added to fontconfig (which would only
+    /// pick it up after rescanning its directories) and the font map is replaced, so pango sees the
+    /// new faces. The new faces of the families found in the file are returned.
+    fn add_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                let e = FontError::UnreadableFile { path: path.to_path_buf(), reason: e.to_string() };
+                warn!(target: LOG_TARGET, "Skipping font: {}", e);
+                self.diagnostics.push(e);
+                return vec![];
+            }
+        };
+
+        let families = file_families(&data);
+        let faces: Vec<FileFace> = (0..swash::FontDataRef::new(&data).map_or(0, |fd| fd.len()))
+            .filter_map(|index| read_file_face(path.to_path_buf(), index as i32).ok())
+            .collect();
+
+        if let Err(e) = add_fontconfig_file(path) {
+            let e = FontError::UnsupportedFormat { path: Some(path.to_path_buf()), reason: e.to_string() };
+            warn!(target: LOG_TARGET, "Skipping font: {}", e);
+            self.diagnostics.push(e);
+            return vec![];
+        }
+        self.reload_font_map();
+
+        let mut added = Vec::new();
+        for family in self.context.list_families() {
+            if !families.contains(&family.name().to_lowercase()) {
+                continue;
+            }
+
+            for face in family.list_faces() {
+                let mut info = face_to_info(&family, &face);
+                if self.font_info.iter().chain(&added).any(|old| same_face(old, &info)) {
+                    continue;
+                }
+
+                if !face.is_synthesized() {
+                    if let Some(file_face) = closest_face(&faces, &info) {
+                        info.path = Some(file_face.path.clone());
+                        info.index = Some(file_face.index);
+                        info.variations = scanned_variations(file_face.variable);
+                    }
+                }
+                added.push(info);
+            }
+        }
+
+        self.font_info.extend(added.iter().cloned());
+        added
+    }
+
+    /// Removes the faces of the file. Synthesized faces of the same families are removed as well
+    /// when pango no longer lists them.
+    fn remove_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let families: HashSet<String> = self.font_info.iter()
+            .filter(|fi| fi.path.as_deref() == Some(path))
+            .map(|fi| fi.family.to_lowercase())
+            .collect();
+        if families.is_empty() {
+            return vec![];
+        }
+
+        self.reload_font_map();
+
+        let listed: Vec<FontInfo> = self.context.list_families().iter()
+            .filter(|family| families.contains(&family.name().to_lowercase()))
+            .flat_map(|family| family.list_faces().iter().map(|face| face_to_info(family, face)).collect::<Vec<_>>())
+            .collect();
+
+        let (removed, kept) = self.font_info.drain(..).partition(|fi| {
+            fi.path.as_deref() == Some(path)
+                || (fi.path.is_none() && families.contains(&fi.family.to_lowercase()) && !listed.iter().any(|l| same_face(l, fi)))
+        });
+        self.font_info = kept;
+        removed
+    }
 }
 
 impl PangoSource {
+    /// Replaces the font map, so pango sees the fonts that fontconfig currently knows about
+    fn reload_font_map(&mut self) {
+        self.font_map = pangocairo::FontMap::new();
+        self.context = self.font_map.create_context();
+    }
+
     pub fn load_font(&self, info: &FontInfo) -> Result<pangocairo::pango::Font, Error> {
         let family = self.font_map.load_font(&self.context, &self.get_description(info, 12.0))
             .ok_or_else(|| Error::msg("Failed to load font"))?;
@@ -108,4 +172,210 @@ impl PangoSource {
 
         desc
     }
-}
\ No newline at end of file
+}
+
+/// Adds the font file to the fonts of the current fontconfig configuration, which pango uses
+fn add_fontconfig_file(path: &Path) -> Result<(), Error> {
+    let path = path.to_str().ok_or_else(|| Error::msg("Path is not valid UTF-8"))?;
+    let path = std::ffi::CString::new(path)?;
+
+    let added = unsafe { fontconfig_sys::FcConfigAppFontAddFile(std::ptr::null_mut(), path.as_ptr() as *const u8) };
+    if added == 0 {
+        return Err(Error::msg("Fontconfig is unable to add the font file"));
+    }
+
+    Ok(())
+}
+
+/// Returns all faces known to the pango context
+fn list_fonts(context: &Context) -> Vec<FontInfo> {
+    let mut files = FileResolver::new();
+
+    let mut font_info = vec![];
+    for family in context.list_families() {
+        for face in family.list_faces() {
+            let mut info = face_to_info(&family, &face);
+
+            // Synthesized faces (like an oblique version of a regular face) have no file of their own
+            if !face.is_synthesized() {
+                if let Some(file_face) = files.resolve(&info) {
+                    info.path = Some(file_face.path.clone());
+                    info.index = Some(file_face.index);
+                    info.variations = scanned_variations(file_face.variable);
+                }
+            }
+
+            font_info.push(info);
+        }
+    }
+
+    font_info
+}
+
+/// Converts a pango face to a gosub font info structure, without a path
+fn face_to_info(family: &FontFamily, face: &FontFace) -> FontInfo {
+    let desc = face.describe();
+
+    let style = match desc.style() {
+        pangocairo::pango::Style::Normal => FontStyle::Normal,
+        pangocairo::pango::Style::Oblique => FontStyle::Oblique,
+        pangocairo::pango::Style::Italic => FontStyle::Italic,
+        _ => FontStyle::Normal,
+    };
+
+    // Conversion taken from: https://developer.mozilla.org/en-US/docs/Web/CSS/font-stretch
+    let stretch = match desc.stretch() {
+        pangocairo::pango::Stretch::UltraCondensed => 0.5,
+        pangocairo::pango::Stretch::ExtraCondensed => 0.625,
+        pangocairo::pango::Stretch::Condensed => 0.75,
+        pangocairo::pango::Stretch::SemiCondensed => 0.875,
+        pangocairo::pango::Stretch::Normal => 1.0,
+        pangocairo::pango::Stretch::SemiExpanded => 1.125,
+        pangocairo::pango::Stretch::Expanded => 1.25,
+        pangocairo::pango::Stretch::ExtraExpanded => 1.5,
+        pangocairo::pango::Stretch::UltraExpanded => 2.0,
+        _ => 1.0,
+    };
+
+    let weight = match desc.weight() {
+        pangocairo::pango::Weight::Thin => 100,
+        pangocairo::pango::Weight::Ultralight => 200,
+        pangocairo::pango::Weight::Semilight => 250,
+        pangocairo::pango::Weight::Light => 300,
+        pangocairo::pango::Weight::Book => 350,
+        pangocairo::pango::Weight::Normal => 400,
+        pangocairo::pango::Weight::Medium => 500,
+        pangocairo::pango::Weight::Semibold => 600,
+        pangocairo::pango::Weight::Bold => 700,
+        pangocairo::pango::Weight::Ultrabold => 800,
+        pangocairo::pango::Weight::Heavy => 900,
+        _ => 400,
+    };
+
+    FontInfo {
+        style,
+        weight: weight as f32,
+        stretch,
+        monospaced: family.is_monospace(),
+        // Faces without a file can not be variable
+        variations: scanned_variations(false),
+        ..FontInfo::new(FontSourceType::Pango, family.name())
+    }
+}
+
+/// Pango does not expose the files of its faces. Both pango and font-kit get their fonts from
+/// fontconfig (on Linux), so the files of a family are looked up through font-kit. The faces are
+/// matched on the style, weight and width of their OS/2 table, which is the only part of the file
+/// that is read.
+struct FileResolver {
+    source: SystemSource,
+    /// Faces of each family (lowercased) that has been looked up
+    families: HashMap<String, Vec<FileFace>>,
+}
+
+/// A face found in a font file
+struct FileFace {
+    style: FontStyle,
+    weight: f32,
+    stretch: f32,
+    path: PathBuf,
+    index: i32,
+    /// True when the face has variation axes
+    variable: bool,
+}
+
+impl FileResolver {
+    fn new() -> Self {
+        Self {
+            source: SystemSource::new(),
+            families: HashMap::new(),
+        }
+    }
+
+    /// Returns the file face of the face
+    fn resolve(&mut self, info: &FontInfo) -> Option<&FileFace> {
+        let source = &self.source;
+        let faces = self.families.entry(info.family.to_lowercase()).or_insert_with(|| {
+            let Ok(family) = source.select_family_by_name(&info.family) else {
+                return vec![];
+            };
+
+            family.fonts().iter()
+                .filter_map(|handle| {
+                    let Handle::Path { path, font_index } = handle else {
+                        return None;
+                    };
+                    read_file_face(resolve_symlink(path.clone()), *font_index as i32).ok()
+                })
+                .collect()
+        });
+
+        closest_face(faces, info)
+    }
+}
+
+/// Returns the file face that matches the pango face. Pango rounds weights to named weights, so
+/// the closest weight is used.
+fn closest_face<'a>(faces: &'a [FileFace], info: &FontInfo) -> Option<&'a FileFace> {
+    faces.iter()
+        .filter(|face| face.style == info.style && (face.stretch - info.stretch).abs() < 0.01)
+        .min_by_key(|face| (face.weight - info.weight).abs() as i32)
+        .filter(|face| (face.weight - info.weight).abs() <= 50.0)
+}
+
+/// Returns the (lowercased) family names of all faces in the font data, as fontconfig lists a
+/// face under both its family and its typographic family name.
+fn file_families(data: &[u8]) -> HashSet<String> {
+    let Some(font_data) = swash::FontDataRef::new(data) else {
+        return HashSet::new();
+    };
+
+    font_data.fonts()
+        .flat_map(|font| font.localized_strings()
+            .filter(|s| matches!(s.id(), StringId::Family | StringId::TypographicFamily))
+            .map(|s| s.to_string().to_lowercase())
+            .collect::<Vec<_>>())
+        .collect()
+}
+
+/// Reads the style, weight and width of a face from its OS/2 table (the same way font-kit does),
+/// and whether the face is variable
+fn read_file_face(path: PathBuf, index: i32) -> Result<FileFace, Error> {
+    // OS/2 width classes 1 to 9, as ratios of the normal width
+    const STRETCHES: [f32; 9] = [0.5, 0.625, 0.75, 0.875, 1.0, 1.125, 1.25, 1.5, 2.0];
+    const FS_SELECTION_ITALIC: u16 = 1 << 0;
+    const FS_SELECTION_OBLIQUE: u16 = 1 << 9;
+
+    let mut file = FontFile::open(&path, index as usize)?;
+    let variable = file.has_table(u32::from_be_bytes(*b"fvar"));
+    let Some(os2) = file.table(u32::from_be_bytes(*b"OS/2"))? else {
+        return Ok(FileFace { style: FontStyle::Normal, weight: WEIGHT_NORMAL, stretch: STRETCH_NORMAL, path, index, variable });
+    };
+
+    let mut r = Reader::at(&os2, 4)?;
+    let weight = r.u16()? as f32;
+    let width = r.u16()? as usize;
+    let fs_selection = Reader::at(&os2, 62)?.u16()?;
+
+    let style = if fs_selection & FS_SELECTION_OBLIQUE != 0 {
+        FontStyle::Oblique
+    } else if fs_selection & FS_SELECTION_ITALIC != 0 {
+        FontStyle::Italic
+    } else {
+        FontStyle::Normal
+    };
+
+    Ok(FileFace {
+        style,
+        weight,
+        stretch: STRETCHES.get(width.wrapping_sub(1)).copied().unwrap_or(STRETCH_NORMAL),
+        path,
+        index,
+        variable,
+    })
+}
+
+/// Faces are compared on their description, as synthesized faces have no path
+fn same_face(a: &FontInfo, b: &FontInfo) -> bool {
+    a.family == b.family && a.style == b.style && a.weight == b.weight && a.stretch == b.stretch
+}
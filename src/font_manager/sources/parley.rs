@@ -1,79 +1,150 @@
 use std::borrow::Cow;
-use std::collections::HashSet;
-use std::path::PathBuf;
-use log::info;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use log::{info, warn};
 use std::cell::RefCell;
 use std::sync::Arc;
-use crate::font_manager::font_info::{FontInfo, FontStyle};
+use crate::font_manager::cache::FontCache;
+use crate::font_manager::error::FontError;
+use crate::font_manager::features::FontFeatures;
+use crate::font_manager::font_info::{scanned_variations, FontInfo, FontStyle};
+use crate::font_manager::generic::GenericFamily;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::monospace::{is_monospaced, is_monospaced_file};
 use crate::font_manager::sources::{resolve_symlink, FontSource, FontSourceType};
 
 // #[allow(unused)]
 pub struct ParleySource {
     context: Arc<RefCell<parley::FontContext>>,
-    font_info: Vec<FontInfo>
+    font_info: Vec<FontInfo>,
+    /// Family names of registered fonts (lowercased) mapped onto the family name parley knows them by
+    aliases: RefCell<HashMap<String, String>>,
+    /// Font files that have been skipped because they could not be loaded
+    diagnostics: Vec<FontError>,
 }
 
 impl FontSource for ParleySource {
-    fn new() -> Self {
+    fn new() -> Result<Self, FontError> {
         let mut context = parley::FontContext::new();
         let mut font_info = Vec::new();
         let coll = &mut context.collection;
 
-        let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+        let mut seen_faces: HashSet<(PathBuf, u32)> = HashSet::new();
+        let mut cache = FontCache::default_path(FontSourceType::Parley).map(|path| FontCache::load(&path));
 
         let names: Vec<String> = coll.family_names().map(|n| n.to_string()).collect();
         for name in names {
             if let Some(family) = coll.family_by_name(&name) {
                 for font in family.fonts() {
-                    let style = match font.style() {
-                        parley::FontStyle::Normal => FontStyle::Normal,
-                        parley::FontStyle::Oblique(_) => FontStyle::Oblique,
-                        parley::FontStyle::Italic => FontStyle::Italic,
-                    };
-
-                    let stretch: f32 = font.stretch().ratio();
-                    let weight: f32 = font.weight().value();
-
                     let path = match &font.source().kind {
                         parley::fontique::SourceKind::Path(path) => {
                             // Check if the path is symlinked
                             let resolved_path = resolve_symlink(path.to_path_buf());
-                            if seen_paths.contains(&resolved_path) {
+                            if !seen_faces.insert((resolved_path.clone(), font.index())) {
                                 continue;
                             }
-                            seen_paths.insert(resolved_path.clone());
                             Some(resolved_path)
                         },
                         _ => None,
                     };
 
-                    font_info.push(FontInfo {
-                        family: name.to_string(),
-                        style,
-                        weight,
-                        stretch,
-                        monospaced: false,  // We just don't know
-                        path,
-                        index: None,
-                        source_type: FontSourceType::Parley,
-                    });
-
-
+                    font_info.push(font_to_info(&name, font, path, cache.as_ref()));
                 }
             }
         }
 
         info!("Loaded {} fonts from parley.", font_info.len());
 
-        Self {
+        if let Some(cache) = cache.as_mut() {
+            let outdated = !cache.is_fresh() || font_info.iter().any(|fi| {
+                fi.path.as_ref().is_some_and(|path| cache.face(path, fi.index.unwrap_or(0), FontSourceType::Parley).is_none())
+            });
+            if outdated {
+                cache.update(&font_info);
+                if let Err(e) = cache.save() {
+                    warn!(target: LOG_TARGET, "Unable to write font cache: {}", e);
+                }
+            }
+        }
+
+        Ok(Self {
             context: Arc::new(RefCell::new(context)),
             font_info,
-        }
+            aliases: RefCell::new(HashMap::new()),
+            diagnostics: Vec::new(),
+        })
     }
 
     fn available_fonts(&self) -> &[FontInfo] {
         &self.font_info
     }
+
+    fn diagnostics(&self) -> &[FontError] {
+        &self.diagnostics
+    }
+
+    /// Fontique keeps its own list of families for each generic family
+    fn generic_families(&self, generic: GenericFamily) -> Vec<String> {
+        let generic = match generic {
+            GenericFamily::Serif => parley::fontique::GenericFamily::Serif,
+            GenericFamily::SansSerif => parley::fontique::GenericFamily::SansSerif,
+            GenericFamily::Monospace => parley::fontique::GenericFamily::Monospace,
+            GenericFamily::Cursive => parley::fontique::GenericFamily::Cursive,
+            GenericFamily::Fantasy => parley::fontique::GenericFamily::Fantasy,
+            GenericFamily::SystemUi => parley::fontique::GenericFamily::SystemUi,
+            GenericFamily::Emoji => parley::fontique::GenericFamily::Emoji,
+            GenericFamily::Math => parley::fontique::GenericFamily::Math,
+        };
+
+        let mut context = self.context.borrow_mut();
+        let ids: Vec<_> = context.collection.generic_families(generic).collect();
+        ids.into_iter()
+            .filter_map(|id| context.collection.family_name(id).map(|name| name.to_string()))
+            .collect()
+    }
+
+    /// The font file is registered in the parley font collection as font data
+    fn add_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                let e = FontError::UnreadableFile { path: path.to_path_buf(), reason: e.to_string() };
+                warn!(target: LOG_TARGET, "Skipping font: {}", e);
+                self.diagnostics.push(e);
+                return vec![];
+            }
+        };
+
+        let mut context = self.context.borrow_mut();
+        let registered = context.collection.register_fonts(data);
+        if registered.is_empty() {
+            let e = FontError::UnsupportedFormat { path: Some(path.to_path_buf()), reason: "no faces found".into() };
+            warn!(target: LOG_TARGET, "Skipping font: {}", e);
+            self.diagnostics.push(e);
+            return vec![];
+        }
+
+        let mut added = Vec::new();
+        for (family_id, fonts) in registered {
+            let Some(name) = context.collection.family_name(family_id) else {
+                continue;
+            };
+            for font in &fonts {
+                added.push(font_to_info(name, font, Some(path.to_path_buf()), None));
+            }
+        }
+
+        self.font_info.extend(added.iter().cloned());
+        added
+    }
+
+    /// Fontique is unable to unregister fonts, so the faces are only removed from the available fonts
+    /// and remain known to the parley font context until restarted.
+    fn remove_font_file(&mut self, path: &Path) -> Vec<FontInfo> {
+        let (removed, kept) = self.font_info.drain(..).partition(|fi| fi.path.as_deref() == Some(path));
+        self.font_info = kept;
+        removed
+    }
 }
 
 impl ParleySource {
@@ -82,8 +153,96 @@ impl ParleySource {
     }
 
     pub fn get_font_stack(&self, family: String) -> parley::FontStack {
+        let family = self.aliases.borrow().get(&family.to_lowercase()).cloned().unwrap_or(family);
+
         parley::FontStack::Single(
             parley::style::FontFamily::Named(Cow::Owned(family))
         )
     }
-}
\ No newline at end of file
+
+    /// Returns the variation settings for the coordinates of the font info, to be used with the
+    /// `FontVariations` style property of a parley layout.
+    pub fn get_font_variations(&self, font_info: &FontInfo) -> Vec<parley::style::FontVariation> {
+        font_info.coordinates.iter()
+            .map(|(tag, value)| parley::style::FontVariation::from((tag.as_str(), *value)))
+            .collect()
+    }
+
+    /// Returns the feature settings, to be used with the `FontFeatures` style property of a parley
+    /// layout.
+    pub fn get_font_features(&self, features: &FontFeatures) -> Vec<parley::style::FontFeature> {
+        features.settings().iter()
+            .map(|(tag, value)| parley::style::FontFeature::from((tag.as_str(), *value)))
+            .collect()
+    }
+
+    /// Registers fonts that are loaded from memory into the parley font collection. When the fonts
+    /// are registered under a different family name than found in the font data, the family name is
+    /// aliased so `get_font_stack` resolves to the registered fonts.
+    pub fn register_fonts(&self, fonts: &[FontInfo]) {
+        let Some(data) = fonts.first().and_then(|fi| fi.data.as_ref()) else {
+            return;
+        };
+
+        // Fontique 0.2 only takes ownership of a vec (it has no way to register a shared blob), so the
+        // data is copied once for all faces in it. Parley shares that copy between its fonts.
+        let mut context = self.context.borrow_mut();
+        let registered = context.collection.register_fonts(data.to_vec());
+        if registered.is_empty() {
+            warn!(target: LOG_TARGET, "Parley did not find any fonts in the registered font data");
+            return;
+        }
+
+        for (family_id, _) in registered {
+            let Some(name) = context.collection.family_name(family_id) else {
+                continue;
+            };
+
+            for fi in fonts {
+                if !fi.family.eq_ignore_ascii_case(name) {
+                    self.aliases.borrow_mut().entry(fi.family.to_lowercase()).or_insert_with(|| name.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Converts a fontique font into a gosub font info structure. The monospace flag is taken from the
+/// cache when the font file has not changed since it was cached.
+fn font_to_info(family: &str, font: &parley::fontique::FontInfo, path: Option<PathBuf>, cache: Option<&FontCache>) -> FontInfo {
+    let style = match font.style() {
+        parley::FontStyle::Normal => FontStyle::Normal,
+        parley::FontStyle::Oblique(_) => FontStyle::Oblique,
+        parley::FontStyle::Italic => FontStyle::Italic,
+    };
+
+    FontInfo {
+        style,
+        weight: font.weight().value(),
+        stretch: font.stretch().ratio(),
+        monospaced: path.as_ref()
+            .and_then(|path| cache?.face(path, font.index() as i32, FontSourceType::Parley))
+            .map_or_else(|| font_is_monospaced(font), |cached| cached.monospaced),
+        path,
+        index: Some(font.index() as i32),
+        variations: scanned_variations(!font.axes().is_empty()),
+        ..FontInfo::new(FontSourceType::Parley, family)
+    }
+}
+
+/// Detects monospacing from the font data, as fontique does not provide it
+fn font_is_monospaced(font: &parley::fontique::FontInfo) -> bool {
+    let index = font.index() as usize;
+    match &font.source().kind {
+        parley::fontique::SourceKind::Path(path) => match is_monospaced_file(path, index) {
+            Ok(monospaced) => monospaced,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Unable to detect monospacing of {}: {}", path.display(), e);
+                false
+            }
+        },
+        parley::fontique::SourceKind::Memory(data) => {
+            swash::FontRef::from_index(data.as_ref(), index).is_some_and(|f| is_monospaced(&f))
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeSet;
+use anyhow::anyhow;
+use swash::FontRef;
+use crate::font_manager::reader::Reader;
+use crate::font_manager::woff::write_sfnt;
+
+const TAG_GLYF: u32 = u32::from_be_bytes(*b"glyf");
+const TAG_LOCA: u32 = u32::from_be_bytes(*b"loca");
+const TAG_HEAD: u32 = u32::from_be_bytes(*b"head");
+const TAG_MAXP: u32 = u32::from_be_bytes(*b"maxp");
+const TAG_DSIG: u32 = u32::from_be_bytes(*b"DSIG");
+const TAG_GVAR: u32 = u32::from_be_bytes(*b"gvar");
+
+/// Creates a standalone font with only the outlines of the given glyphs (and the glyphs they are
+/// composed of). Glyph ids are kept as-is, the outlines of all other glyphs are left empty, so the
+/// other tables can be copied without changes. Faces from a collection are extracted into a single
+/// font. CFF based faces and variable faces (whose gvar table has variations for every glyph) are
+/// extracted without removing any glyphs.
+pub(crate) fn subset(font: &FontRef, glyphs: &[u16]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut r = Reader::at(font.data, font.offset as usize)?;
+    let flavor = r.u32()?;
+    let num_tables = r.u16()? as usize;
+    r.skip(6)?;
+
+    let mut tables = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let tag = r.u32()?;
+        let _checksum = r.u32()?;
+        let offset = r.u32()? as usize;
+        let length = r.u32()? as usize;
+
+        // The signature is no longer valid for the changed font
+        if tag == TAG_DSIG {
+            continue;
+        }
+
+        let data = Reader::at(font.data, offset)?.bytes(length)?;
+        tables.push((tag, data.to_vec()));
+    }
+
+    let table = |tag: u32| tables.iter().find(|(t, _)| *t == tag).map(|(_, data)| data.as_slice());
+    let (Some(glyf), Some(loca), Some(head), Some(maxp)) = (table(TAG_GLYF), table(TAG_LOCA), table(TAG_HEAD), table(TAG_MAXP)) else {
+        return Ok(write_sfnt(flavor, &tables));
+    };
+    if table(TAG_GVAR).is_some() {
+        return Ok(write_sfnt(flavor, &tables));
+    }
+
+    let num_glyphs = Reader::at(maxp, 4)?.u16()? as usize;
+    let long_offsets = Reader::at(head, 50)?.i16()? != 0;
+    let offsets = read_loca(loca, num_glyphs, long_offsets)?;
+
+    let glyph_data = |gid: usize| -> Result<&[u8], anyhow::Error> {
+        let (start, end) = (offsets[gid], offsets[gid + 1]);
+        if start > end || end > glyf.len() {
+            return Err(anyhow!("Glyph {} has an invalid location", gid));
+        }
+        Ok(&glyf[start..end])
+    };
+
+    // Glyph 0 (.notdef) is always kept, and composite glyphs need their components
+    let mut keep: BTreeSet<usize> = BTreeSet::new();
+    let mut pending: Vec<usize> = vec![0];
+    pending.extend(glyphs.iter().map(|&gid| gid as usize));
+    while let Some(gid) = pending.pop() {
+        if gid >= num_glyphs || !keep.insert(gid) {
+            continue;
+        }
+        pending.extend(composite_components(glyph_data(gid)?)?.into_iter().map(|gid| gid as usize));
+    }
+
+    // The subsetted glyf table, with long offsets in the loca table
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity((num_glyphs + 1) * 4);
+    for gid in 0..num_glyphs {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if keep.contains(&gid) {
+            new_glyf.extend_from_slice(glyph_data(gid)?);
+            new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    let mut new_head = head.to_vec();
+    new_head[50..52].copy_from_slice(&1i16.to_be_bytes());
+
+    for (tag, data) in tables.iter_mut() {
+        match *tag {
+            TAG_GLYF => *data = std::mem::take(&mut new_glyf),
+            TAG_LOCA => *data = std::mem::take(&mut new_loca),
+            TAG_HEAD => *data = std::mem::take(&mut new_head),
+            _ => {}
+        }
+    }
+
+    Ok(write_sfnt(flavor, &tables))
+}
+
+/// Reads the glyph offsets of the loca table (one more than the number of glyphs)
+fn read_loca(loca: &[u8], num_glyphs: usize, long_offsets: bool) -> Result<Vec<usize>, anyhow::Error> {
+    let mut r = Reader::new(loca);
+    (0..=num_glyphs)
+        .map(|_| if long_offsets { r.u32().map(|o| o as usize) } else { r.u16().map(|o| o as usize * 2) })
+        .collect()
+}
+
+/// Returns the glyph ids of the components of a composite glyph (none for simple glyphs)
+fn composite_components(data: &[u8]) -> Result<Vec<u16>, anyhow::Error> {
+    const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+    const WE_HAVE_A_SCALE: u16 = 0x0008;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut r = Reader::new(data);
+    if r.i16()? >= 0 {
+        return Ok(vec![]);
+    }
+    r.skip(8)?;
+
+    let mut components = Vec::new();
+    loop {
+        let flags = r.u16()?;
+        components.push(r.u16()?);
+
+        let mut len = if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            len += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            len += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            len += 8;
+        }
+        r.skip(len)?;
+
+        if flags & MORE_COMPONENTS == 0 {
+            return Ok(components);
+        }
+    }
+}
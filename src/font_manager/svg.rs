@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+use anyhow::anyhow;
+use log::warn;
+use swash::scale::ScaleContext;
+use swash::shape::ShapeContext;
+use swash::FontRef;
+use crate::font_manager::features::FontFeatures;
+use crate::font_manager::font_info::FontInfo;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::metrics::FontMetrics;
+use crate::font_manager::outline::GlyphOutline;
+use crate::font_manager::shaping::{script_runs, shape_font, Direction, ShapeOptions, ShapedCluster};
+use crate::font_manager::subset::subset;
+
+/// Family name of the font that is embedded in the SVG document
+const EMBEDDED_FAMILY: &str = "gosub-embedded";
+
+/// How the text is written into the SVG document
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SvgText {
+    /// Every glyph is written as a path. The document looks the same everywhere, but the text can
+    /// not be selected or searched.
+    #[default]
+    Outlines,
+    /// The text is written as `<text>` elements, with a subset of the font embedded in the document
+    EmbeddedFont,
+}
+
+/// Options for writing text as SVG
+#[derive(Clone, Debug)]
+pub struct SvgOptions {
+    pub text: SvgText,
+    /// Lines longer than this width (in pixels) are wrapped at whitespace
+    pub max_width: Option<f32>,
+    /// Height of a line in pixels. Defaults to the line height of the font.
+    pub line_height: Option<f32>,
+    /// Fill color of the text
+    pub color: String,
+    /// OpenType features to turn on or off while shaping
+    pub features: FontFeatures,
+    /// Direction of the paragraphs. Right-to-left lines are aligned to the right. The runs of a
+    /// paragraph are all shaped in this direction, they are not reordered with the bidi algorithm.
+    pub direction: Direction,
+    /// OpenType script tag (like "latn" or "arab") of all text. When not given, paragraphs are split
+    /// into runs of a single script that are shaped on their own.
+    pub script: Option<String>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            text: SvgText::Outlines,
+            max_width: None,
+            line_height: None,
+            color: "black".into(),
+            features: FontFeatures::default(),
+            direction: Direction::LeftToRight,
+            script: None,
+        }
+    }
+}
+
+/// Glyph positioned on a line, relative to the start of the line at the baseline
+struct PositionedGlyph {
+    id: u16,
+    x: f32,
+    y: f32,
+}
+
+/// Line of shaped text
+struct Line {
+    /// Source text of the line
+    text: String,
+    glyphs: Vec<PositionedGlyph>,
+    width: f32,
+    /// Position of the left of the line in the document
+    x: f32,
+}
+
+/// Shapes the text with the face at the given size and writes it as an SVG document. Lines are
+/// broken at newlines and, when a maximum width is given, wrapped at whitespace. The view box of the
+/// document fits the text.
+pub fn text_to_svg(text: &str, font_info: &FontInfo, size: f32, options: &SvgOptions) -> Result<String, anyhow::Error> {
+    let data = font_info.load_data()?;
+    let font = FontRef::from_index(&data, font_info.face_index())
+        .ok_or_else(|| anyhow!("Unable to parse font data for {}", font_info.family))?;
+    let coords = font_info.normalized_coords_of(&font);
+
+    let metrics = FontMetrics::from_font(&font, &coords, size);
+    let line_height = options.line_height.unwrap_or(metrics.line_height());
+    // The ascent is placed in the middle of the extra space of the line, like CSS does
+    let baseline = (line_height - metrics.ascent - metrics.descent) / 2.0 + metrics.ascent;
+
+    let mut lines = layout(&font, &coords, size, text, options);
+    let width = lines.iter().map(|line| line.width).fold(0.0, f32::max).ceil().max(1.0);
+    if options.direction == Direction::RightToLeft {
+        for line in lines.iter_mut() {
+            line.x = width - line.width;
+        }
+    }
+    let height = (line_height * lines.len() as f32).ceil().max(1.0);
+
+    let mut svg = String::new();
+    writeln!(svg, "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>")?;
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" version=\"1.1\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        width, height, width, height
+    )?;
+
+    match options.text {
+        SvgText::Outlines => write_outlines(&mut svg, &font, font_info, &coords, size, &lines, line_height, baseline, &options.color)?,
+        SvgText::EmbeddedFont => write_text(&mut svg, &font, font_info, size, &lines, line_height, baseline, options)?,
+    }
+
+    writeln!(svg, "</svg>")?;
+    Ok(svg)
+}
+
+/// Writes every distinct glyph once as a path definition, and uses it for every glyph on the lines
+#[allow(clippy::too_many_arguments)]
+fn write_outlines(svg: &mut String, font: &FontRef, font_info: &FontInfo, coords: &[i16], size: f32, lines: &[Line], line_height: f32, baseline: f32, color: &str) -> Result<(), anyhow::Error> {
+    let mut context = ScaleContext::new();
+    let mut scaler = context.builder(*font)
+        .size(size)
+        .normalized_coords(coords)
+        .build();
+
+    let glyph_ids: BTreeSet<u16> = lines.iter().flat_map(|line| line.glyphs.iter().map(|g| g.id)).collect();
+
+    writeln!(svg, "<defs>")?;
+    let mut defined = BTreeSet::new();
+    for id in glyph_ids {
+        match GlyphOutline::from_scaler(&mut scaler, id, size, font_info.synthesis) {
+            Ok(outline) if !outline.is_empty() => {
+                writeln!(svg, "<path id=\"g{}\" d=\"{}\"/>", id, outline.to_svg_path())?;
+                defined.insert(id);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(target: LOG_TARGET, "Glyph {} is left out of the SVG document: {}", id, e),
+        }
+    }
+    writeln!(svg, "</defs>")?;
+
+    writeln!(svg, "<g fill=\"{}\">", escape(color))?;
+    for (idx, line) in lines.iter().enumerate() {
+        let y = idx as f32 * line_height + baseline;
+        for glyph in line.glyphs.iter().filter(|g| defined.contains(&g.id)) {
+            writeln!(svg, "<use xlink:href=\"#g{}\" x=\"{}\" y=\"{}\"/>", glyph.id, num(line.x + glyph.x), num(y - glyph.y))?;
+        }
+    }
+    writeln!(svg, "</g>")?;
+
+    Ok(())
+}
+
+/// Writes the lines as text elements, with the glyphs that are used embedded as a font
+#[allow(clippy::too_many_arguments)]
+fn write_text(svg: &mut String, font: &FontRef, font_info: &FontInfo, size: f32, lines: &[Line], line_height: f32, baseline: f32, options: &SvgOptions) -> Result<(), anyhow::Error> {
+    let glyph_ids: Vec<u16> = lines.iter().flat_map(|line| line.glyphs.iter().map(|g| g.id)).collect();
+    let font_data = subset(font, &glyph_ids)?;
+
+    writeln!(svg, "<style>")?;
+    writeln!(svg, "@font-face {{ font-family: \"{}\"; src: url(data:font/ttf;base64,{}); }}", EMBEDDED_FAMILY, base64(&font_data))?;
+    writeln!(svg, "</style>")?;
+
+    // The style is written in a double quoted attribute, so CSS strings use single quotes
+    let mut style = format!("font-family: '{}'; font-size: {}px; white-space: pre;", EMBEDDED_FAMILY, num(size));
+    if !font_info.coordinates.is_empty() {
+        let settings: Vec<String> = font_info.coordinates.iter()
+            .map(|(tag, value)| format!("'{}' {}", escape(tag), num(*value)))
+            .collect();
+        style.push_str(&format!(" font-variation-settings: {};", settings.join(", ")));
+    }
+
+    // The text is shaped again by the viewer, so it needs the same features
+    let features = options.features.settings();
+    if !features.is_empty() {
+        let settings: Vec<String> = features.iter()
+            .map(|(tag, value)| format!("'{}' {}", escape(tag), value))
+            .collect();
+        style.push_str(&format!(" font-feature-settings: {};", settings.join(", ")));
+    }
+
+    // The embedded font is a regular face, so the viewer synthesizes the styles the face is missing
+    if font_info.synthesis.bold {
+        style.push_str(" font-weight: bold;");
+    }
+    if font_info.synthesis.italic {
+        style.push_str(" font-style: oblique;");
+    }
+
+    // Right-to-left text starts at the right of the line
+    let rtl = options.direction == Direction::RightToLeft;
+    if rtl {
+        style.push_str(" direction: rtl;");
+    }
+
+    writeln!(svg, "<g fill=\"{}\" style=\"{}\">", escape(&options.color), style)?;
+    for (idx, line) in lines.iter().enumerate() {
+        let x = if rtl { line.x + line.width } else { line.x };
+        let y = idx as f32 * line_height + baseline;
+        writeln!(svg, "<text x=\"{}\" y=\"{}\" xml:space=\"preserve\">{}</text>", num(x), num(y), escape(&line.text))?;
+    }
+    writeln!(svg, "</g>")?;
+
+    Ok(())
+}
+
+/// Shapes the text and breaks it into lines
+fn layout(font: &FontRef, coords: &[i16], size: f32, text: &str, options: &SvgOptions) -> Vec<Line> {
+    let mut context = ShapeContext::new();
+    let mut lines = Vec::new();
+
+    let shape_options = ShapeOptions {
+        features: options.features.clone(),
+        direction: options.direction,
+        script: options.script.clone(),
+        ..Default::default()
+    };
+
+    for paragraph in text.lines() {
+        let runs: Vec<Range<usize>> = match options.script {
+            Some(_) => std::iter::once(0..paragraph.len()).collect(),
+            None => script_runs(paragraph),
+        };
+
+        // Clusters are kept in logical order, so lines are broken in the order of the text
+        let mut clusters = Vec::new();
+        for run in runs {
+            let mut shaped = shape_font(&mut context, font, coords, &paragraph[run.clone()], size, &shape_options);
+            if options.direction == Direction::RightToLeft {
+                shaped.clusters.reverse();
+            }
+            clusters.extend(shaped.clusters.into_iter().map(|cluster| ShapedCluster {
+                range: cluster.range.start + run.start..cluster.range.end + run.start,
+                ..cluster
+            }));
+        }
+
+        break_lines(paragraph, &clusters, options.max_width, options.direction, &mut lines);
+    }
+
+    lines
+}
+
+/// Breaks the clusters of a paragraph into lines, at the last whitespace before the maximum width.
+/// Words that are wider than the maximum width are not broken.
+fn break_lines(paragraph: &str, clusters: &[ShapedCluster], max_width: Option<f32>, direction: Direction, lines: &mut Vec<Line>) {
+    let mut start = 0;
+    let mut width = 0.0;
+    let mut last_break = None;
+
+    for (idx, cluster) in clusters.iter().enumerate() {
+        if cluster.whitespace {
+            last_break = Some(idx);
+        } else if let (Some(max_width), Some(brk)) = (max_width, last_break) {
+            if width + cluster.advance > max_width {
+                lines.push(make_line(paragraph, &clusters[start..brk], direction));
+                start = brk + 1;
+                width = clusters[start..idx].iter().map(|c| c.advance).sum();
+                last_break = None;
+            }
+        }
+        width += cluster.advance;
+    }
+
+    lines.push(make_line(paragraph, &clusters[start..], direction));
+}
+
+/// Places the glyphs of the clusters (in logical order) on a line
+fn make_line(paragraph: &str, clusters: &[ShapedCluster], direction: Direction) -> Line {
+    // Trailing whitespace does not count for the width of the line
+    let visible = clusters.iter().rposition(|c| !c.whitespace).map(|idx| idx + 1).unwrap_or(0);
+
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+    let mut width = 0.0;
+    let mut place = |cluster: &ShapedCluster| {
+        for glyph in &cluster.glyphs {
+            glyphs.push(PositionedGlyph { id: glyph.id, x: x + glyph.x_offset, y: glyph.y_offset });
+            x += glyph.advance;
+        }
+        x
+    };
+    match direction {
+        Direction::LeftToRight => {
+            for (idx, cluster) in clusters.iter().enumerate() {
+                let end = place(cluster);
+                if idx < visible {
+                    width = end;
+                }
+            }
+        }
+        // Trailing whitespace would end up on the left of the line, so it is left out
+        Direction::RightToLeft => {
+            for cluster in clusters[..visible].iter().rev() {
+                width = place(cluster);
+            }
+        }
+    }
+
+    let text = match (clusters.first(), clusters.last()) {
+        (Some(first), Some(last)) => paragraph.get(first.range.start..last.range.end).unwrap_or_default().to_string(),
+        _ => String::new(),
+    };
+
+    Line { text, glyphs, width, x: 0.0 }
+}
+
+/// Formats a number with at most two decimals
+fn num(value: f32) -> String {
+    let s = format!("{:.2}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".into() } else { s.into() }
+}
+
+/// Escapes text for use in XML content and attributes
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Encodes the data as base64 (with padding)
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 { ALPHABET[(n >> 6) as usize & 63] as char } else { '=' });
+        out.push(if chunk.len() > 2 { ALPHABET[n as usize & 63] as char } else { '=' });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_manager::fixtures::memory_face;
+    use crate::font_manager::synthesis::Synthesis;
+
+    fn svg(text: &str, options: SvgOptions) -> String {
+        text_to_svg(text, &memory_face(), 20.0, &options).unwrap()
+    }
+
+    #[test]
+    fn outlines_are_wrapped_at_whitespace() {
+        let svg = svg("AB BA AB\nBA", SvgOptions {
+            max_width: Some(40.0),
+            ..Default::default()
+        });
+        assert_eq!(svg, include_str!("../../tests/fixtures/svg/outlines.svg"));
+    }
+
+    #[test]
+    fn right_to_left_lines_are_aligned_right() {
+        let svg = svg("AB BA AB BA", SvgOptions {
+            max_width: Some(40.0),
+            direction: Direction::RightToLeft,
+            ..Default::default()
+        });
+        assert_eq!(svg, include_str!("../../tests/fixtures/svg/rtl.svg"));
+    }
+
+    #[test]
+    fn text_is_written_with_an_embedded_font() {
+        let svg = svg("AB BA", SvgOptions {
+            text: SvgText::EmbeddedFont,
+            ..Default::default()
+        });
+        assert_eq!(svg, include_str!("../../tests/fixtures/svg/embedded.svg"));
+    }
+
+    #[test]
+    fn feature_settings_are_written_with_the_text() {
+        let svg = svg("AB BA", SvgOptions {
+            text: SvgText::EmbeddedFont,
+            features: FontFeatures::parse("\"liga\" 0").unwrap(),
+            ..Default::default()
+        });
+        assert_eq!(svg, include_str!("../../tests/fixtures/svg/features.svg"));
+    }
+
+    #[test]
+    fn synthesized_styles_are_written_with_the_text() {
+        let face = FontInfo { synthesis: Synthesis::ALL, ..memory_face() };
+        let svg = text_to_svg("AB", &face, 20.0, &SvgOptions { text: SvgText::EmbeddedFont, ..Default::default() }).unwrap();
+        assert!(svg.contains(" font-weight: bold; font-style: oblique;\">"), "{}", svg);
+    }
+
+    #[test]
+    fn runs_of_each_script_are_shaped() {
+        let svg = svg("AB \u{5e9}\u{5dc} \u{3b1}B", SvgOptions::default());
+        assert_eq!(svg, include_str!("../../tests/fixtures/svg/scripts.svg"));
+    }
+}
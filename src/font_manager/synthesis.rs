@@ -0,0 +1,39 @@
+use swash::zeno::{Angle, Transform};
+use crate::font_manager::variation::OBLIQUE_ANGLE;
+
+/// Styles that are synthesized because the face itself does not have them, as described by the
+/// CSS `font-synthesis` property. On a font query it lists the styles that may be synthesized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Synthesis {
+    /// Glyphs are emboldened
+    pub bold: bool,
+    /// Glyphs are skewed to the right
+    pub italic: bool,
+}
+
+impl Synthesis {
+    /// Synthesis of both bold and italic
+    pub const ALL: Synthesis = Synthesis { bold: true, italic: true };
+    /// No synthesis
+    pub const NONE: Synthesis = Synthesis { bold: false, italic: false };
+
+    pub fn is_none(&self) -> bool {
+        !self.bold && !self.italic
+    }
+
+    /// Returns how far (in pixels) outlines are emboldened at the given size. This is 1/24 em, the
+    /// same as freetype uses.
+    pub fn embolden_strength(&self, size: f32) -> f32 {
+        if self.bold { size / 24.0 } else { 0.0 }
+    }
+
+    /// Returns the horizontal skew for each unit of height (the tangent of the oblique angle)
+    pub fn skew(&self) -> f32 {
+        if self.italic { OBLIQUE_ANGLE.to_radians().tan() } else { 0.0 }
+    }
+
+    /// Returns the transform to skew outlines with (with a y axis pointing up), as used by swash
+    pub fn transform(&self) -> Option<Transform> {
+        self.italic.then(|| Transform::skew(Angle::from_degrees(OBLIQUE_ANGLE), Angle::ZERO))
+    }
+}
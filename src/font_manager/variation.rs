@@ -0,0 +1,73 @@
+use swash::FontRef;
+use crate::font_manager::reader::tag_to_string;
+
+/// Angle (in degrees) of an oblique style when no angle is given, as defined by CSS
+pub const OBLIQUE_ANGLE: f32 = 14.0;
+
+/// Variation axis of a variable font, as found in the fvar table
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariationAxis {
+    /// Tag of the axis (e.g. "wght")
+    pub tag: String,
+    /// Name of the axis (e.g. "Weight")
+    pub name: Option<String>,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+impl VariationAxis {
+    /// Returns true when the value lies within the range of the axis
+    pub fn contains(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    /// Clamps the value to the range of the axis
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// Named instance of a variable font (e.g. "Bold Condensed")
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedInstance {
+    pub name: Option<String>,
+    /// Axis coordinates of the instance, in user space
+    pub coordinates: Vec<(String, f32)>,
+}
+
+/// Axes and named instances of a face. Both are empty for faces that are not variable.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Variations {
+    pub axes: Vec<VariationAxis>,
+    pub instances: Vec<NamedInstance>,
+}
+
+impl Variations {
+    /// Reads the axes and named instances from the fvar table of the font
+    pub fn from_font(font: &FontRef) -> Self {
+        let axes: Vec<VariationAxis> = font.variations()
+            .map(|axis| VariationAxis {
+                tag: tag_to_string(axis.tag()),
+                name: axis.name(Some("en")).or_else(|| axis.name(None)).map(|n| n.chars().collect()),
+                min: axis.min_value(),
+                default: axis.default_value(),
+                max: axis.max_value(),
+            })
+            .collect();
+
+        let instances = font.instances()
+            .map(|instance| NamedInstance {
+                name: instance.name(Some("en")).or_else(|| instance.name(None)).map(|n| n.chars().collect()),
+                coordinates: axes.iter().map(|axis| axis.tag.clone()).zip(instance.values()).collect(),
+            })
+            .collect();
+
+        Self { axes, instances }
+    }
+
+    /// Returns the axis with the given tag
+    pub fn axis(&self, tag: &str) -> Option<&VariationAxis> {
+        self.axes.iter().find(|axis| axis.tag == tag)
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use crate::font_manager::cache::Stamp;
+use crate::font_manager::font_info::FontInfo;
+use crate::font_manager::manager::LOG_TARGET;
+use crate::font_manager::sources::resolve_symlink;
+
+/// File extensions of the font files that are watched
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf", "ttc", "otc"];
+
+/// Change of a font file in one of the watched directories. Paths are resolved when symlinked.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontChange {
+    /// A font file has been installed
+    Added(PathBuf),
+    /// A font file has been removed
+    Removed(PathBuf),
+    /// A font file has been replaced by a different version
+    Modified(PathBuf),
+}
+
+/// Faces that have been added and removed by applying font changes
+#[derive(Clone, Debug, Default)]
+pub struct FontUpdate {
+    pub added: Vec<FontInfo>,
+    pub removed: Vec<FontInfo>,
+}
+
+impl FontUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Callback that is called with the faces that have been added and removed
+pub type FontUpdateCallback = Box<dyn Fn(&FontUpdate)>;
+
+/// Watches font directories for added, removed and modified font files. The directories are scanned
+/// periodically on a background thread, and changes are queued until they are picked up with
+/// `changes`. The thread is stopped when the watcher is dropped.
+pub struct FontWatcher {
+    receiver: Receiver<FontChange>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FontWatcher {
+    /// Starts watching the given directories (recursively), scanning them every `interval`
+    pub fn new(directories: Vec<PathBuf>, interval: Duration) -> Self {
+        let (sender, receiver) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // The initial snapshot is taken before returning, so files added directly afterwards are seen
+        // as changes.
+        let snapshot = scan(&directories);
+        info!(target: LOG_TARGET, "Watching {} font files in {} directories", snapshot.len(), directories.len());
+
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("font-watcher".into())
+            .spawn(move || watch(directories, interval, snapshot, sender, thread_stop));
+
+        let thread = match thread {
+            Ok(thread) => Some(thread),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Unable to start font watcher thread: {}", e);
+                None
+            }
+        };
+
+        Self { receiver, stop, thread }
+    }
+
+    /// Returns all changes found since the last call
+    pub fn changes(&self) -> Vec<FontChange> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for FontWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Scans the directories until stopped, and sends the differences between successive scans
+fn watch(directories: Vec<PathBuf>, interval: Duration, mut snapshot: HashMap<PathBuf, Stamp>, sender: Sender<FontChange>, stop: Arc<AtomicBool>) {
+    let mut last_scan = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        // Sleep in small steps, so dropping the watcher does not block for a whole interval
+        std::thread::sleep(Duration::from_millis(50).min(interval));
+        if last_scan.elapsed() < interval {
+            continue;
+        }
+        last_scan = Instant::now();
+
+        let current = scan(&directories);
+        for change in diff(&snapshot, &current) {
+            if sender.send(change).is_err() {
+                return;
+            }
+        }
+        snapshot = current;
+    }
+}
+
+/// Returns the changes between two scans
+fn diff(old: &HashMap<PathBuf, Stamp>, new: &HashMap<PathBuf, Stamp>) -> Vec<FontChange> {
+    let mut changes = Vec::new();
+
+    for (path, stamp) in new {
+        match old.get(path) {
+            None => changes.push(FontChange::Added(path.clone())),
+            Some(old_stamp) if old_stamp != stamp => changes.push(FontChange::Modified(path.clone())),
+            _ => {}
+        }
+    }
+    for path in old.keys() {
+        if !new.contains_key(path) {
+            changes.push(FontChange::Removed(path.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Returns all font files (with resolved paths) found in the directories and their subdirectories
+fn scan(directories: &[PathBuf]) -> HashMap<PathBuf, Stamp> {
+    let mut files = HashMap::new();
+    for dir in directories {
+        scan_dir(dir, &mut files, 0);
+    }
+    files
+}
+
+fn scan_dir(dir: &Path, files: &mut HashMap<PathBuf, Stamp>, depth: usize) {
+    // Protect against symlink loops
+    if depth > 16 {
+        return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan_dir(&path, files, depth + 1);
+            continue;
+        }
+
+        let is_font = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| FONT_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(ext)));
+        if !is_font {
+            continue;
+        }
+
+        let path = resolve_symlink(path);
+        if let Some(stamp) = Stamp::of(&path) {
+            files.insert(path, stamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::font_manager::fixtures::FIXTURE;
+    use crate::font_manager::manager::FontManager;
+    #[cfg(feature = "source_pango")]
+    use crate::font_manager::sources::FontSourceType;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Font directory in the temp directory, which is removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("gosub-watcher-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(resolve_symlink(path))
+        }
+
+        fn font_path(&self) -> PathBuf {
+            self.0.join("fixture.ttf")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes the font file under another name first, so the watcher never sees a partial file
+    fn install(path: &Path, data: &[u8]) {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data).unwrap();
+        std::fs::rename(&tmp, path).unwrap();
+    }
+
+    /// Polls until a result is returned, or panics after a few seconds
+    fn wait_for<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(result) = poll() {
+                return result;
+            }
+            std::thread::sleep(INTERVAL);
+        }
+        panic!("No font changes found within 5 seconds");
+    }
+
+    fn wait_for_changes(watcher: &FontWatcher) -> Vec<FontChange> {
+        wait_for(|| Some(watcher.changes()).filter(|changes| !changes.is_empty()))
+    }
+
+    fn wait_for_update(manager: &mut FontManager) -> FontUpdate {
+        wait_for(|| Some(manager.process_font_changes()).filter(|update| !update.is_empty()))
+    }
+
+    #[test]
+    fn watcher_reports_added_modified_and_removed_files() {
+        let dir = TempDir::new("changes");
+        let path = dir.font_path();
+        let watcher = FontWatcher::new(vec![dir.0.clone()], INTERVAL);
+
+        install(&path, FIXTURE);
+        assert_eq!(wait_for_changes(&watcher), vec![FontChange::Added(path.clone())]);
+
+        // A different version of the font, with padding after the last table
+        install(&path, &[FIXTURE, &[0; 4]].concat());
+        assert_eq!(wait_for_changes(&watcher), vec![FontChange::Modified(path.clone())]);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(wait_for_changes(&watcher), vec![FontChange::Removed(path.clone())]);
+    }
+
+    #[test]
+    fn watcher_ignores_other_files() {
+        let dir = TempDir::new("other-files");
+        let watcher = FontWatcher::new(vec![dir.0.clone()], INTERVAL);
+
+        std::fs::write(dir.0.join("fixture.txt"), FIXTURE).unwrap();
+        std::thread::sleep(INTERVAL * 10);
+        assert!(watcher.changes().is_empty());
+    }
+
+    #[test]
+    fn manager_adds_removes_and_replaces_fonts() {
+        let dir = TempDir::new("manager");
+        let path = dir.font_path();
+
+        let mut manager = FontManager::new().unwrap();
+        manager.watch_directories(vec![dir.0.clone()], INTERVAL);
+
+        let notified = Rc::new(RefCell::new(0));
+        let counter = notified.clone();
+        manager.subscribe(move |_| *counter.borrow_mut() += 1);
+
+        // Add
+        install(&path, FIXTURE);
+        let update = wait_for_update(&mut manager);
+        assert!(!update.added.is_empty());
+        assert!(update.added.iter().all(|fi| fi.path.as_ref() == Some(&path) && fi.family == "Fixture"));
+        assert!(update.removed.is_empty());
+        assert_eq!(manager.faces_in_file(&path).len(), 1);
+        #[cfg(feature = "source_pango")]
+        assert!(update.added.iter().any(|fi| fi.source_type == FontSourceType::Pango));
+
+        // Replace
+        install(&path, &[FIXTURE, &[0; 4]].concat());
+        let update = wait_for_update(&mut manager);
+        assert!(!update.removed.is_empty());
+        assert_eq!(update.removed.len(), update.added.len());
+        assert!(update.added.iter().chain(&update.removed).all(|fi| fi.path.as_ref() == Some(&path)));
+        assert_eq!(manager.faces_in_file(&path).len(), 1);
+
+        // Remove
+        std::fs::remove_file(&path).unwrap();
+        let update = wait_for_update(&mut manager);
+        assert!(!update.removed.is_empty());
+        assert!(update.added.is_empty());
+        assert!(manager.faces_in_file(&path).is_empty());
+        assert!(manager.all_fonts().iter().all(|fi| fi.path.as_ref() != Some(&path)));
+
+        assert_eq!(*notified.borrow(), 3);
+    }
+
+    #[cfg(feature = "source_pango")]
+    #[test]
+    fn pango_source_adds_and_removes_fonts() {
+        use crate::font_manager::sources::pango::PangoSource;
+        use crate::font_manager::sources::FontSource;
+
+        let dir = TempDir::new("pango");
+        let path = dir.font_path();
+        let watcher = FontWatcher::new(vec![dir.0.clone()], INTERVAL);
+        let mut source = PangoSource::new().unwrap();
+
+        // The directory is not known to fontconfig, so the file is only found when it is added
+        install(&path, FIXTURE);
+        assert_eq!(wait_for_changes(&watcher), vec![FontChange::Added(path.clone())]);
+        // Pango may add synthesized faces (without a path) for the family as well
+        let added = source.add_font_file(&path);
+        assert!(added.iter().all(|fi| fi.family == "Fixture"));
+        assert_eq!(added.iter().filter(|fi| fi.path.as_ref() == Some(&path)).count(), 1);
+        assert!(source.available_fonts().iter().any(|fi| fi.path.as_ref() == Some(&path)));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(wait_for_changes(&watcher), vec![FontChange::Removed(path.clone())]);
+        assert!(source.remove_font_file(&path).iter().any(|fi| fi.path.as_ref() == Some(&path)));
+        assert!(source.available_fonts().iter().all(|fi| fi.path.as_ref() != Some(&path)));
+    }
+}
@@ -0,0 +1,925 @@
+//! Decoding of WOFF and WOFF2 web font containers into plain sfnt (TTF/OTF/TTC) data.
+//!
+//! See: https://www.w3.org/TR/WOFF/ and https://www.w3.org/TR/WOFF2/
+
+use std::io::Read;
+use anyhow::anyhow;
+use crate::font_manager::error::FontError;
+use crate::font_manager::reader::{tag_to_string, Reader};
+
+const WOFF_SIGNATURE: u32 = 0x774F4646; // 'wOFF'
+const WOFF2_SIGNATURE: u32 = 0x774F4632; // 'wOF2'
+const TTC_TAG: u32 = 0x74746366; // 'ttcf'
+
+const TAG_GLYF: u32 = 0x676C7966;
+const TAG_LOCA: u32 = 0x6C6F6361;
+const TAG_HMTX: u32 = 0x686D7478;
+const TAG_HHEA: u32 = 0x68686561;
+const TAG_HEAD: u32 = 0x68656164;
+
+/// Maximum size of a decoded font. Anything larger is considered corrupt (or malicious).
+const MAX_DECODED_SIZE: usize = 256 * 1024 * 1024;
+
+/// Container format of font data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FontFormat {
+    /// Plain TrueType / OpenType data (or a collection of them)
+    Sfnt,
+    /// WOFF 1.0 container
+    Woff,
+    /// WOFF 2.0 container
+    Woff2,
+}
+
+impl FontFormat {
+    /// Detects the container format from the signature of the data
+    pub fn detect(data: &[u8]) -> FontFormat {
+        match data.get(0..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])) {
+            Some(WOFF_SIGNATURE) => FontFormat::Woff,
+            Some(WOFF2_SIGNATURE) => FontFormat::Woff2,
+            _ => FontFormat::Sfnt,
+        }
+    }
+}
+
+/// Decodes WOFF and WOFF2 data into sfnt data. Data in any other format is returned as-is.
+pub fn decode(data: Vec<u8>) -> Result<Vec<u8>, FontError> {
+    let decoded = match FontFormat::detect(&data) {
+        FontFormat::Sfnt => return Ok(data),
+        FontFormat::Woff => decode_woff(&data),
+        FontFormat::Woff2 => decode_woff2(&data),
+    };
+
+    decoded.map_err(|e| FontError::UnsupportedFormat { path: None, reason: e.to_string() })
+}
+
+/// Decodes a WOFF 1.0 file. Each table is either stored or compressed with zlib.
+pub fn decode_woff(data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut r = Reader::new(data);
+
+    if r.u32()? != WOFF_SIGNATURE {
+        return Err(anyhow!("Not a WOFF file"));
+    }
+    let flavor = r.u32()?;
+    let length = r.u32()? as usize;
+    let num_tables = r.u16()?;
+    r.skip(2)?; // reserved
+    let total_sfnt_size = r.u32()? as usize;
+    r.skip(24)?; // version, metadata and private data blocks
+
+    if length != data.len() {
+        return Err(anyhow!("WOFF length field ({}) does not match the data length ({})", length, data.len()));
+    }
+    if total_sfnt_size > MAX_DECODED_SIZE {
+        return Err(anyhow!("WOFF decoded size of {} bytes is too large", total_sfnt_size));
+    }
+
+    let mut tables = Vec::with_capacity(num_tables as usize);
+    let mut decoded_size = 0;
+    for _ in 0..num_tables {
+        let tag = r.u32()?;
+        let offset = r.u32()? as usize;
+        let comp_length = r.u32()? as usize;
+        let orig_length = r.u32()? as usize;
+        r.skip(4)?; // original checksum, recalculated when writing the sfnt
+
+        // The tables must fit in the declared sfnt size, which is checked before anything is allocated
+        decoded_size += orig_length;
+        if decoded_size > total_sfnt_size {
+            return Err(anyhow!("WOFF table {} does not fit in the declared sfnt size of {} bytes", tag_to_string(tag), total_sfnt_size));
+        }
+
+        let compressed = Reader::at(data, offset)?.bytes(comp_length)
+            .map_err(|e| anyhow!("WOFF table {} is truncated: {}", tag_to_string(tag), e))?;
+
+        let table = if comp_length < orig_length {
+            let mut table = Vec::with_capacity(orig_length);
+            flate2::read::ZlibDecoder::new(compressed)
+                .take(orig_length as u64 + 1)
+                .read_to_end(&mut table)
+                .map_err(|e| anyhow!("WOFF table {} could not be decompressed: {}", tag_to_string(tag), e))?;
+            table
+        } else if comp_length == orig_length {
+            compressed.to_vec()
+        } else {
+            return Err(anyhow!("WOFF table {} has a compressed length larger than its original length", tag_to_string(tag)));
+        };
+
+        if table.len() != orig_length {
+            return Err(anyhow!("WOFF table {} decompressed to {} bytes, expected {}", tag_to_string(tag), table.len(), orig_length));
+        }
+
+        tables.push((tag, table));
+    }
+
+    Ok(write_sfnt(flavor, &tables))
+}
+
+/// Table directory entry of a WOFF2 file
+struct Woff2Table {
+    tag: u32,
+    orig_length: usize,
+    /// Length of the (possibly transformed) table in the decompressed stream
+    stream_length: usize,
+    transformed: bool,
+}
+
+/// A font inside a WOFF2 collection, referencing tables in the table directory
+struct Woff2Font {
+    flavor: u32,
+    tables: Vec<usize>,
+}
+
+/// Decodes a WOFF 2.0 file. The table data is compressed with Brotli as a single stream, and the
+/// glyf, loca and hmtx tables can be transformed which must be reversed.
+pub fn decode_woff2(data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut r = Reader::new(data);
+
+    if r.u32()? != WOFF2_SIGNATURE {
+        return Err(anyhow!("Not a WOFF2 file"));
+    }
+    let flavor = r.u32()?;
+    let length = r.u32()? as usize;
+    let num_tables = r.u16()? as usize;
+    r.skip(2)?; // reserved
+    let total_sfnt_size = r.u32()? as usize;
+    let total_compressed_size = r.u32()? as usize;
+    r.skip(24)?; // version, metadata and private data blocks
+
+    if length != data.len() {
+        return Err(anyhow!("WOFF2 length field ({}) does not match the data length ({})", length, data.len()));
+    }
+    if total_sfnt_size > MAX_DECODED_SIZE {
+        return Err(anyhow!("WOFF2 decoded size of {} bytes is too large", total_sfnt_size));
+    }
+    if num_tables == 0 {
+        return Err(anyhow!("WOFF2 file does not contain any tables"));
+    }
+
+    let mut tables = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let flags = r.u8()?;
+        let tag = match flags & 0x3f {
+            0x3f => r.u32()?,
+            idx => KNOWN_TAGS[idx as usize],
+        };
+        let transform_version = flags >> 6;
+        let orig_length = read_base128(&mut r)? as usize;
+
+        // For glyf and loca, version 0 is the transformed version and 3 is the null transform. For all
+        // other tables version 0 is the null transform.
+        let transformed = if tag == TAG_GLYF || tag == TAG_LOCA {
+            transform_version != 3
+        } else {
+            transform_version != 0
+        };
+        let stream_length = if transformed { read_base128(&mut r)? as usize } else { orig_length };
+
+        if transformed && tag != TAG_GLYF && tag != TAG_LOCA && tag != TAG_HMTX {
+            return Err(anyhow!("WOFF2 table {} uses an unknown transform", tag_to_string(tag)));
+        }
+
+        tables.push(Woff2Table { tag, orig_length, stream_length, transformed });
+    }
+
+    let fonts = if flavor == TTC_TAG {
+        r.skip(4)?; // ttc version
+        let num_fonts = read_255_u16(&mut r)? as usize;
+        if num_fonts == 0 {
+            return Err(anyhow!("WOFF2 collection does not contain any fonts"));
+        }
+        let mut fonts = Vec::with_capacity(num_fonts);
+        for _ in 0..num_fonts {
+            let num_font_tables = read_255_u16(&mut r)? as usize;
+            let flavor = r.u32()?;
+            let mut indices = Vec::with_capacity(num_font_tables);
+            for _ in 0..num_font_tables {
+                let idx = read_255_u16(&mut r)? as usize;
+                if idx >= tables.len() {
+                    return Err(anyhow!("WOFF2 collection references table {} which does not exist", idx));
+                }
+                indices.push(idx);
+            }
+            fonts.push(Woff2Font { flavor, tables: indices });
+        }
+        fonts
+    } else {
+        vec![Woff2Font { flavor, tables: (0..tables.len()).collect() }]
+    };
+
+    // Decompress the table data stream
+    let compressed = r.bytes(total_compressed_size)
+        .map_err(|e| anyhow!("WOFF2 compressed data is truncated: {}", e))?;
+    let expected: usize = tables.iter().map(|t| t.stream_length).sum();
+    if expected > MAX_DECODED_SIZE {
+        return Err(anyhow!("WOFF2 decoded size of {} bytes is too large", expected));
+    }
+
+    let mut stream = Vec::with_capacity(expected);
+    brotli_decompressor::Decompressor::new(compressed, 4096)
+        .take(expected as u64 + 1)
+        .read_to_end(&mut stream)
+        .map_err(|e| anyhow!("WOFF2 data could not be decompressed: {}", e))?;
+    if stream.len() != expected {
+        return Err(anyhow!("WOFF2 data decompressed to {} bytes, expected {}", stream.len(), expected));
+    }
+
+    let mut offset = 0;
+    let mut raw: Vec<&[u8]> = Vec::with_capacity(tables.len());
+    for table in &tables {
+        raw.push(&stream[offset..offset + table.stream_length]);
+        offset += table.stream_length;
+    }
+
+    // Reconstruct the tables. Transformed glyf/loca/hmtx tables depend on other tables of the same font.
+    let mut output: Vec<Option<Vec<u8>>> = vec![None; tables.len()];
+    for font in &fonts {
+        let find = |tag: u32| font.tables.iter().copied().find(|&idx| tables[idx].tag == tag);
+
+        let glyf_idx = find(TAG_GLYF);
+        let loca_idx = find(TAG_LOCA);
+        let mut x_mins = None;
+
+        if let Some(glyf_idx) = glyf_idx {
+            let loca_idx = loca_idx.ok_or_else(|| anyhow!("WOFF2 font has a glyf table without a loca table"))?;
+            if tables[glyf_idx].transformed != tables[loca_idx].transformed {
+                return Err(anyhow!("WOFF2 glyf and loca tables must both be transformed or both not"));
+            }
+
+            if tables[glyf_idx].transformed {
+                let glyf = reconstruct_glyf(raw[glyf_idx])?;
+                if glyf.loca.len() != tables[loca_idx].orig_length {
+                    return Err(anyhow!("WOFF2 reconstructed loca table has an unexpected length"));
+                }
+                x_mins = Some(glyf.x_mins);
+                output[glyf_idx] = Some(glyf.glyf);
+                output[loca_idx] = Some(glyf.loca);
+            }
+        }
+
+        if let Some(hmtx_idx) = find(TAG_HMTX) {
+            if tables[hmtx_idx].transformed && output[hmtx_idx].is_none() {
+                let x_mins = x_mins.as_ref().ok_or_else(|| anyhow!("WOFF2 transformed hmtx table requires a transformed glyf table"))?;
+                let hhea = find(TAG_HHEA).map(|idx| raw[idx]).ok_or_else(|| anyhow!("WOFF2 font has no hhea table"))?;
+                let num_h_metrics = Reader::at(hhea, 34)?.u16()? as usize;
+                output[hmtx_idx] = Some(reconstruct_hmtx(raw[hmtx_idx], num_h_metrics, x_mins)?);
+            }
+        }
+    }
+
+    let tables: Vec<(u32, Vec<u8>)> = tables.iter().enumerate()
+        .map(|(idx, table)| (table.tag, output[idx].take().unwrap_or_else(|| raw[idx].to_vec())))
+        .collect();
+
+    if flavor == TTC_TAG {
+        Ok(write_collection(&fonts, &tables))
+    } else {
+        Ok(write_sfnt(fonts[0].flavor, &tables))
+    }
+}
+
+/// Result of reconstructing a transformed glyf table
+struct Glyf {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    /// Minimum x value of each glyph, needed to reconstruct the left side bearings in hmtx
+    x_mins: Vec<i16>,
+}
+
+/// Reconstructs the glyf and loca tables from the transformed glyf table
+fn reconstruct_glyf(data: &[u8]) -> Result<Glyf, anyhow::Error> {
+    let mut r = Reader::new(data);
+    r.skip(2)?; // reserved
+    let option_flags = r.u16()?;
+    let num_glyphs = r.u16()? as usize;
+    let index_format = r.u16()?;
+
+    let mut stream_sizes = [0usize; 7];
+    for size in stream_sizes.iter_mut() {
+        *size = r.u32()? as usize;
+    }
+
+    let mut n_contour_stream = Reader::new(r.bytes(stream_sizes[0])?);
+    let mut n_points_stream = Reader::new(r.bytes(stream_sizes[1])?);
+    let mut flag_stream = Reader::new(r.bytes(stream_sizes[2])?);
+    let mut glyph_stream = Reader::new(r.bytes(stream_sizes[3])?);
+    let mut composite_stream = Reader::new(r.bytes(stream_sizes[4])?);
+    let bbox_data = r.bytes(stream_sizes[5])?;
+    let mut instruction_stream = Reader::new(r.bytes(stream_sizes[6])?);
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(r.bytes(num_glyphs.div_ceil(8))?)
+    } else {
+        None
+    };
+
+    let bbox_bitmap_len = num_glyphs.div_ceil(32) * 4;
+    if bbox_data.len() < bbox_bitmap_len {
+        return Err(anyhow!("WOFF2 glyf bbox stream is truncated"));
+    }
+    let bbox_bitmap = &bbox_data[..bbox_bitmap_len];
+    let mut bbox_stream = Reader::new(&bbox_data[bbox_bitmap_len..]);
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+
+    for glyph_id in 0..num_glyphs {
+        offsets.push(glyf.len());
+
+        let n_contours = n_contour_stream.i16()?;
+        let has_bbox = bbox_bitmap[glyph_id >> 3] & (0x80 >> (glyph_id & 7)) != 0;
+        let explicit_bbox = if has_bbox {
+            Some([bbox_stream.i16()?, bbox_stream.i16()?, bbox_stream.i16()?, bbox_stream.i16()?])
+        } else {
+            None
+        };
+
+        if n_contours == 0 {
+            if has_bbox {
+                return Err(anyhow!("WOFF2 empty glyph {} has a bounding box", glyph_id));
+            }
+            x_mins.push(0);
+            continue;
+        }
+
+        if n_contours < 0 {
+            if n_contours != -1 {
+                return Err(anyhow!("WOFF2 glyph {} has an invalid number of contours", glyph_id));
+            }
+            let bbox = explicit_bbox.ok_or_else(|| anyhow!("WOFF2 composite glyph {} has no bounding box", glyph_id))?;
+
+            let (components, have_instructions) = read_composite(&mut composite_stream)?;
+            glyf.extend_from_slice(&(-1i16).to_be_bytes());
+            for v in bbox {
+                glyf.extend_from_slice(&v.to_be_bytes());
+            }
+            glyf.extend_from_slice(components);
+
+            if have_instructions {
+                let len = read_255_u16(&mut glyph_stream)? as usize;
+                glyf.extend_from_slice(&(len as u16).to_be_bytes());
+                glyf.extend_from_slice(instruction_stream.bytes(len)?);
+            }
+
+            x_mins.push(bbox[0]);
+            pad_to_4(&mut glyf);
+            continue;
+        }
+
+        // Simple glyph
+        let mut end_points = Vec::with_capacity(n_contours as usize);
+        let mut total_points = 0usize;
+        for _ in 0..n_contours {
+            let n_points = read_255_u16(&mut n_points_stream)? as usize;
+            if n_points == 0 {
+                return Err(anyhow!("WOFF2 glyph {} has a contour without points", glyph_id));
+            }
+            total_points += n_points;
+            if total_points > u16::MAX as usize + 1 {
+                return Err(anyhow!("WOFF2 glyph {} has too many points", glyph_id));
+            }
+            end_points.push((total_points - 1) as u16);
+        }
+
+        let mut points = Vec::with_capacity(total_points);
+        let (mut x, mut y) = (0i32, 0i32);
+        for _ in 0..total_points {
+            let flag = flag_stream.u8()?;
+            let (dx, dy) = decode_triplet(flag & 0x7f, &mut glyph_stream)?;
+            x += dx;
+            y += dy;
+            points.push((x, y, flag & 0x80 == 0));
+        }
+
+        let instruction_len = read_255_u16(&mut glyph_stream)? as usize;
+        let instructions = instruction_stream.bytes(instruction_len)?;
+
+        let bbox = match explicit_bbox {
+            Some(bbox) => bbox,
+            None => compute_bbox(&points),
+        };
+
+        let overlap = overlap_bitmap.is_some_and(|bitmap| bitmap[glyph_id >> 3] & (0x80 >> (glyph_id & 7)) != 0);
+
+        glyf.extend_from_slice(&n_contours.to_be_bytes());
+        for v in bbox {
+            glyf.extend_from_slice(&v.to_be_bytes());
+        }
+        for end_point in end_points {
+            glyf.extend_from_slice(&end_point.to_be_bytes());
+        }
+        glyf.extend_from_slice(&(instruction_len as u16).to_be_bytes());
+        glyf.extend_from_slice(instructions);
+        write_simple_points(&mut glyf, &points, overlap);
+
+        x_mins.push(bbox[0]);
+        pad_to_4(&mut glyf);
+    }
+    offsets.push(glyf.len());
+
+    let mut loca = Vec::new();
+    if index_format == 0 {
+        if glyf.len() > 0x1FFFE {
+            return Err(anyhow!("WOFF2 glyf table is too large for a short loca format"));
+        }
+        for offset in offsets {
+            loca.extend_from_slice(&((offset / 2) as u16).to_be_bytes());
+        }
+    } else {
+        for offset in offsets {
+            loca.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+
+    Ok(Glyf { glyf, loca, x_mins })
+}
+
+/// Reads the components of a composite glyph from the composite stream. Returns the raw component
+/// data and whether the glyph has instructions.
+fn read_composite<'a>(r: &mut Reader<'a>) -> Result<(&'a [u8], bool), anyhow::Error> {
+    const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+    const WE_HAVE_A_SCALE: u16 = 0x0008;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+    const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+    let mut start = r.clone();
+    let mut have_instructions = false;
+    let mut size = 0;
+
+    loop {
+        let flags = r.u16()?;
+        have_instructions |= flags & WE_HAVE_INSTRUCTIONS != 0;
+
+        // glyph index plus arguments
+        let mut len = 2 + if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            len += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            len += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            len += 8;
+        }
+        r.skip(len)?;
+        size += 2 + len;
+
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+
+    Ok((start.bytes(size)?, have_instructions))
+}
+
+/// Decodes a point delta from the glyph stream, as described in section 5.2 of the WOFF2 specification
+fn decode_triplet(flag: u8, r: &mut Reader) -> Result<(i32, i32), anyhow::Error> {
+    fn with_sign(flag: u8, value: i32) -> i32 {
+        if flag & 1 != 0 { value } else { -value }
+    }
+
+    let flag_i = flag as i32;
+    let delta = if flag < 10 {
+        let b0 = r.u8()? as i32;
+        (0, with_sign(flag, ((flag_i & 14) << 7) + b0))
+    } else if flag < 20 {
+        let b0 = r.u8()? as i32;
+        (with_sign(flag, (((flag_i - 10) & 14) << 7) + b0), 0)
+    } else if flag < 84 {
+        let b0 = flag_i - 20;
+        let b1 = r.u8()? as i32;
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)),
+        )
+    } else if flag < 120 {
+        let b0 = flag_i - 84;
+        let b1 = r.u8()? as i32;
+        let b2 = r.u8()? as i32;
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + b1),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b2),
+        )
+    } else if flag < 124 {
+        let b1 = r.u8()? as i32;
+        let b2 = r.u8()? as i32;
+        let b3 = r.u8()? as i32;
+        (
+            with_sign(flag, (b1 << 4) + (b2 >> 4)),
+            with_sign(flag >> 1, ((b2 & 0x0f) << 8) + b3),
+        )
+    } else {
+        let b1 = r.u8()? as i32;
+        let b2 = r.u8()? as i32;
+        let b3 = r.u8()? as i32;
+        let b4 = r.u8()? as i32;
+        (
+            with_sign(flag, (b1 << 8) + b2),
+            with_sign(flag >> 1, (b3 << 8) + b4),
+        )
+    };
+
+    Ok(delta)
+}
+
+/// Computes the bounding box (xMin, yMin, xMax, yMax) of a list of points
+fn compute_bbox(points: &[(i32, i32, bool)]) -> [i16; 4] {
+    if points.is_empty() {
+        return [0; 4];
+    }
+
+    let mut bbox = [i32::MAX, i32::MAX, i32::MIN, i32::MIN];
+    for &(x, y, _) in points {
+        bbox[0] = bbox[0].min(x);
+        bbox[1] = bbox[1].min(y);
+        bbox[2] = bbox[2].max(x);
+        bbox[3] = bbox[3].max(y);
+    }
+
+    bbox.map(|v| v as i16)
+}
+
+/// Writes the flags and coordinates of a simple glyph, using the short and repeat encodings
+fn write_simple_points(out: &mut Vec<u8>, points: &[(i32, i32, bool)], overlap: bool) {
+    const ON_CURVE: u8 = 0x01;
+    const X_SHORT: u8 = 0x02;
+    const Y_SHORT: u8 = 0x04;
+    const REPEAT: u8 = 0x08;
+    const X_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_SAME_OR_POSITIVE: u8 = 0x20;
+    const OVERLAP_SIMPLE: u8 = 0x40;
+
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let (mut last_x, mut last_y) = (0i32, 0i32);
+
+    for &(x, y, on_curve) in points {
+        let mut flag = if on_curve { ON_CURVE } else { 0 };
+        if overlap && flags.is_empty() {
+            flag |= OVERLAP_SIMPLE;
+        }
+
+        let dx = x - last_x;
+        if dx == 0 {
+            flag |= X_SAME_OR_POSITIVE;
+        } else if (-255..=255).contains(&dx) {
+            flag |= X_SHORT;
+            if dx > 0 {
+                flag |= X_SAME_OR_POSITIVE;
+            }
+            xs.push(dx.unsigned_abs() as u8);
+        } else {
+            xs.extend_from_slice(&(dx as i16).to_be_bytes());
+        }
+
+        let dy = y - last_y;
+        if dy == 0 {
+            flag |= Y_SAME_OR_POSITIVE;
+        } else if (-255..=255).contains(&dy) {
+            flag |= Y_SHORT;
+            if dy > 0 {
+                flag |= Y_SAME_OR_POSITIVE;
+            }
+            ys.push(dy.unsigned_abs() as u8);
+        } else {
+            ys.extend_from_slice(&(dy as i16).to_be_bytes());
+        }
+
+        flags.push(flag);
+        last_x = x;
+        last_y = y;
+    }
+
+    // Write the flags, collapsing runs of identical flags with the repeat flag
+    let mut i = 0;
+    while i < flags.len() {
+        let flag = flags[i];
+        let mut run = 1;
+        while i + run < flags.len() && flags[i + run] == flag && run < 256 {
+            run += 1;
+        }
+
+        if run > 1 {
+            out.push(flag | REPEAT);
+            out.push((run - 1) as u8);
+        } else {
+            out.push(flag);
+        }
+        i += run;
+    }
+
+    out.extend_from_slice(&xs);
+    out.extend_from_slice(&ys);
+}
+
+/// Reconstructs the hmtx table from the transformed hmtx table. Left side bearings that are left out
+/// are equal to the xMin of the glyph.
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: usize, x_mins: &[i16]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut r = Reader::new(data);
+    let flags = r.u8()?;
+    let num_glyphs = x_mins.len();
+
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs {
+        return Err(anyhow!("WOFF2 hhea table has an invalid number of horizontal metrics"));
+    }
+
+    let mut advances = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics {
+        advances.push(r.u16()?);
+    }
+
+    // Bit 0 means the proportional left side bearings are left out, bit 1 the monospaced ones
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for (glyph_id, &x_min) in x_mins.iter().enumerate() {
+        let mask = if glyph_id < num_h_metrics { 1 } else { 2 };
+        lsbs.push(if flags & mask == 0 { r.i16()? } else { x_min });
+    }
+
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+    for glyph_id in 0..num_glyphs {
+        if glyph_id < num_h_metrics {
+            hmtx.extend_from_slice(&advances[glyph_id].to_be_bytes());
+        }
+        hmtx.extend_from_slice(&lsbs[glyph_id].to_be_bytes());
+    }
+
+    Ok(hmtx)
+}
+
+/// Writes a single sfnt font with the given tables. Tables are sorted by tag, and all checksums
+/// (including the checksum adjustment in the head table) are recalculated.
+pub(crate) fn write_sfnt(flavor: u32, tables: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let indices: Vec<usize> = (0..tables.len()).collect();
+
+    let header_len = 12 + 16 * tables.len();
+    out.resize(header_len, 0);
+
+    let mut records = Vec::new();
+    for (tag, data) in tables {
+        records.push((*tag, append_table(&mut out, *tag, data)));
+    }
+    write_offset_table(&mut out[..header_len], flavor, &indices, &records);
+
+    // Set the checksum adjustment of the head table, which makes the checksum of the whole font equal to 0xB1B0AFBA
+    if let Some((_, (offset, len, _))) = records.iter().find(|(tag, _)| *tag == TAG_HEAD) {
+        if *len >= 12 {
+            out[offset + 8..offset + 12].fill(0);
+            let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&out));
+            out[offset + 8..offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+        }
+    }
+
+    out
+}
+
+/// Writes a font collection where the fonts reference (possibly shared) tables
+fn write_collection(fonts: &[Woff2Font], tables: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    // TTC header version 2.0, without a digital signature
+    let ttc_header_len = 12 + 4 * fonts.len() + 12;
+    let offset_tables_len: usize = fonts.iter().map(|f| 12 + 16 * f.tables.len()).sum();
+    out.resize(ttc_header_len + offset_tables_len, 0);
+
+    out[0..4].copy_from_slice(&TTC_TAG.to_be_bytes());
+    out[4..8].copy_from_slice(&0x00020000u32.to_be_bytes());
+    out[8..12].copy_from_slice(&(fonts.len() as u32).to_be_bytes());
+
+    let mut records = Vec::new();
+    for (tag, data) in tables {
+        records.push((*tag, append_table(&mut out, *tag, data)));
+    }
+
+    let mut offset = ttc_header_len;
+    for (idx, font) in fonts.iter().enumerate() {
+        out[12 + idx * 4..16 + idx * 4].copy_from_slice(&(offset as u32).to_be_bytes());
+        let len = 12 + 16 * font.tables.len();
+        write_offset_table(&mut out[offset..offset + len], font.flavor, &font.tables, &records);
+        offset += len;
+    }
+
+    out
+}
+
+/// Appends the table data, padded to 4 bytes. Returns the offset, length and checksum of the table.
+fn append_table(out: &mut Vec<u8>, tag: u32, data: &[u8]) -> (usize, usize, u32) {
+    let offset = out.len();
+    out.extend_from_slice(data);
+    pad_to_4(out);
+
+    let mut sum = checksum(&out[offset..]);
+    if tag == TAG_HEAD && data.len() >= 12 {
+        // The checksum adjustment is treated as zero when calculating the head table checksum
+        sum = sum.wrapping_sub(u32::from_be_bytes([data[8], data[9], data[10], data[11]]));
+    }
+
+    (offset, data.len(), sum)
+}
+
+/// Writes the offset table (sfnt header and table records) for the given tables
+fn write_offset_table(out: &mut [u8], flavor: u32, indices: &[usize], records: &[(u32, (usize, usize, u32))]) {
+    let num_tables = indices.len() as u16;
+    let entry_selector = if num_tables == 0 { 0 } else { 15 - num_tables.leading_zeros() as u16 };
+    let search_range = (1u16 << entry_selector) * 16;
+    let range_shift = num_tables * 16 - search_range;
+
+    out[0..4].copy_from_slice(&flavor.to_be_bytes());
+    out[4..6].copy_from_slice(&num_tables.to_be_bytes());
+    out[6..8].copy_from_slice(&search_range.to_be_bytes());
+    out[8..10].copy_from_slice(&entry_selector.to_be_bytes());
+    out[10..12].copy_from_slice(&range_shift.to_be_bytes());
+
+    let mut sorted = indices.to_vec();
+    sorted.sort_by_key(|&idx| records[idx].0);
+
+    for (i, idx) in sorted.into_iter().enumerate() {
+        let (tag, (offset, len, sum)) = records[idx];
+        let rec = &mut out[12 + i * 16..28 + i * 16];
+        rec[0..4].copy_from_slice(&tag.to_be_bytes());
+        rec[4..8].copy_from_slice(&sum.to_be_bytes());
+        rec[8..12].copy_from_slice(&(offset as u32).to_be_bytes());
+        rec[12..16].copy_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+/// Calculates the sfnt checksum of 4-byte aligned data
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn pad_to_4(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+/// Reads a UIntBase128 value
+fn read_base128(r: &mut Reader) -> Result<u32, anyhow::Error> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let byte = r.u8()?;
+        if i == 0 && byte == 0x80 {
+            return Err(anyhow!("WOFF2 UIntBase128 value has leading zeros"));
+        }
+        if value & 0xFE000000 != 0 {
+            return Err(anyhow!("WOFF2 UIntBase128 value overflows"));
+        }
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(anyhow!("WOFF2 UIntBase128 value is longer than 5 bytes"))
+}
+
+/// Reads a 255UInt16 value
+fn read_255_u16(r: &mut Reader) -> Result<u16, anyhow::Error> {
+    const ONE_MORE_BYTE_CODE1: u8 = 255;
+    const ONE_MORE_BYTE_CODE2: u8 = 254;
+    const WORD_CODE: u8 = 253;
+    const LOWEST_U_CODE: u16 = 253;
+
+    let code = r.u8()?;
+    Ok(match code {
+        WORD_CODE => r.u16()?,
+        ONE_MORE_BYTE_CODE1 => r.u8()? as u16 + LOWEST_U_CODE,
+        ONE_MORE_BYTE_CODE2 => r.u8()? as u16 + LOWEST_U_CODE * 2,
+        _ => code as u16,
+    })
+}
+
+/// Table tags that can be referenced by index in the WOFF2 table directory
+const KNOWN_TAGS: [u32; 63] = [
+    tag(b"cmap"), tag(b"head"), tag(b"hhea"), tag(b"hmtx"), tag(b"maxp"), tag(b"name"), tag(b"OS/2"), tag(b"post"),
+    tag(b"cvt "), tag(b"fpgm"), tag(b"glyf"), tag(b"loca"), tag(b"prep"), tag(b"CFF "), tag(b"VORG"), tag(b"EBDT"),
+    tag(b"EBLC"), tag(b"gasp"), tag(b"hdmx"), tag(b"kern"), tag(b"LTSH"), tag(b"PCLT"), tag(b"VDMX"), tag(b"vhea"),
+    tag(b"vmtx"), tag(b"BASE"), tag(b"GDEF"), tag(b"GPOS"), tag(b"GSUB"), tag(b"EBSC"), tag(b"JSTF"), tag(b"MATH"),
+    tag(b"CBDT"), tag(b"CBLC"), tag(b"COLR"), tag(b"CPAL"), tag(b"SVG "), tag(b"sbix"), tag(b"acnt"), tag(b"avar"),
+    tag(b"bdat"), tag(b"bloc"), tag(b"bsln"), tag(b"cvar"), tag(b"fdsc"), tag(b"feat"), tag(b"fmtx"), tag(b"fvar"),
+    tag(b"gvar"), tag(b"hsty"), tag(b"just"), tag(b"lcar"), tag(b"mort"), tag(b"morx"), tag(b"opbd"), tag(b"prop"),
+    tag(b"trak"), tag(b"Zapf"), tag(b"Silf"), tag(b"Glat"), tag(b"Gloc"), tag(b"Feat"), tag(b"Sill"),
+];
+
+const fn tag(b: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*b)
+}
+
+#[cfg(test)]
+mod tests {
+    use swash::scale::ScaleContext;
+    use swash::FontRef;
+    use super::*;
+    use crate::font_manager::fixtures::FIXTURE as TTF;
+
+    const WOFF: &[u8] = include_bytes!("../../tests/fixtures/fixture.woff");
+    const WOFF2: &[u8] = include_bytes!("../../tests/fixtures/fixture.woff2");
+    const WOFF2_ZERO_POINTS: &[u8] = include_bytes!("../../tests/fixtures/zero-points.woff2");
+
+    /// Returns the points and verbs of the outline of every glyph of the font
+    fn outlines(data: &[u8]) -> Vec<(Vec<(f32, f32)>, usize)> {
+        let font = FontRef::from_index(data, 0).expect("decoded font parses");
+        let mut context = ScaleContext::new();
+        let mut scaler = context.builder(font).size(1000.0).build();
+        (0..font.metrics(&[]).glyph_count)
+            .map(|glyph_id| {
+                let outline = scaler.scale_outline(glyph_id).expect("glyph has an outline");
+                (outline.points().iter().map(|p| (p.x, p.y)).collect(), outline.verbs().len())
+            })
+            .collect()
+    }
+
+    fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(FontFormat::detect(TTF), FontFormat::Sfnt);
+        assert_eq!(FontFormat::detect(WOFF), FontFormat::Woff);
+        assert_eq!(FontFormat::detect(WOFF2), FontFormat::Woff2);
+        assert_eq!(FontFormat::detect(&[]), FontFormat::Sfnt);
+    }
+
+    #[test]
+    fn sfnt_is_returned_as_is() {
+        assert_eq!(decode(TTF.to_vec()).unwrap(), TTF);
+    }
+
+    #[test]
+    fn woff_decodes_to_the_original_font() {
+        assert_eq!(decode(WOFF.to_vec()).unwrap(), TTF);
+    }
+
+    #[test]
+    fn woff2_decodes_transformed_tables() {
+        let decoded = decode(WOFF2.to_vec()).unwrap();
+        assert_eq!(outlines(&decoded), outlines(TTF));
+
+        let font = FontRef::from_index(&decoded, 0).unwrap();
+        let original = FontRef::from_index(TTF, 0).unwrap();
+        assert_eq!(font.charmap().map('B'), 2);
+        for tag in [b"hmtx", b"cmap", b"head"] {
+            let tag = u32::from_be_bytes(*tag);
+            let (table, expected) = (font.table(tag).unwrap(), original.table(tag).unwrap());
+            if tag == TAG_HEAD {
+                // Only the checksum adjustment differs
+                assert_eq!(table[..8], expected[..8]);
+                assert_eq!(table[12..], expected[12..]);
+            } else {
+                assert_eq!(table, expected, "table {}", tag_to_string(tag));
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        for data in [WOFF, WOFF2] {
+            for len in [8, 40, data.len() / 2, data.len() - 4] {
+                let mut truncated = data[..len].to_vec();
+                if len >= 12 {
+                    // Keep the length field valid, so the truncated tables are reached
+                    set_u32(&mut truncated, 8, len as u32);
+                }
+                assert!(decode(truncated).is_err(), "{} of {} bytes", len, data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_compressed_data_is_rejected() {
+        // Table data of the WOFF file starts after the header (44 bytes) and table directory (20 bytes per table)
+        let mut woff = WOFF.to_vec();
+        let data_start = 44 + 20 * u16::from_be_bytes([woff[12], woff[13]]) as usize;
+        woff[data_start..].iter_mut().for_each(|b| *b ^= 0x5a);
+        assert!(decode(woff).is_err());
+
+        let mut woff2 = WOFF2.to_vec();
+        let len = woff2.len();
+        woff2[len - 40..len - 4].fill(0xff);
+        assert!(decode(woff2).is_err());
+    }
+
+    #[test]
+    fn table_larger_than_sfnt_size_is_rejected() {
+        // The original length of the first table is far beyond the declared sfnt size
+        let mut woff = WOFF.to_vec();
+        set_u32(&mut woff, 44 + 12, 0xFFFF_FFF0);
+        assert!(decode(woff).is_err());
+
+        let mut woff = WOFF.to_vec();
+        set_u32(&mut woff, 16, MAX_DECODED_SIZE as u32 + 1);
+        assert!(decode(woff).is_err());
+    }
+
+    #[test]
+    fn contour_without_points_is_rejected() {
+        let error = decode(WOFF2_ZERO_POINTS.to_vec()).unwrap_err();
+        assert!(matches!(error, FontError::UnsupportedFormat { path: None, .. }));
+        assert!(error.to_string().contains("contour without points"), "{}", error);
+    }
+}
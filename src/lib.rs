@@ -1,7 +1,36 @@
+//! Font management for gosub: finding the fonts of the system and the fonts registered from memory,
+//! matching, shaping and rendering them.
+//!
+//! Rendering with vello (`render::vello`) always goes through a wgpu adapter. On machines without a
+//! GPU, like CI runners, a software adapter must be installed: llvmpipe (Mesa) or lavapipe (Mesa
+//! Vulkan) on Linux, or WARP on Windows. The tests of the vello renderer fail when no adapter is found.
+
 mod font_manager;
 pub mod flatland;
 
 pub use font_manager::manager::FontManager;
+pub use font_manager::error::FontError;
 pub use font_manager::font_info::FontInfo;
 pub use font_manager::font_info::FontStyle;
-pub use font_manager::sources::FontSourceType;
\ No newline at end of file
+pub use font_manager::font_info::FontData;
+pub use font_manager::font_id::FontId;
+pub use font_manager::sources::FontSourceType;
+pub use font_manager::sources::memory::FontDescriptors;
+pub use font_manager::matching::FontQuery;
+pub use font_manager::metrics::FontMetrics;
+pub use font_manager::outline::{GlyphOutline, OutlineScaler, PathCommand, Point};
+pub use font_manager::glyph_cache::{Antialias, AtlasFormat, AtlasPage, GlyphCache, GlyphColors, GlyphImage, RasterOptions};
+pub use font_manager::shaping::{Direction, ShapeOptions, ShapedCluster, ShapedGlyph, ShapedText};
+pub use font_manager::svg::{SvgOptions, SvgText};
+pub use font_manager::synthesis::Synthesis;
+pub use font_manager::variation::{NamedInstance, VariationAxis, Variations};
+pub use font_manager::generic::GenericFamily;
+pub use font_manager::catalogue::{Catalogue, CatalogueEntry, FaceKey};
+pub use font_manager::fallback::FontRun;
+pub use font_manager::features::{Feature, FontFeatures};
+pub use font_manager::watcher::{FontChange, FontUpdate};
+pub use font_manager::coverage::Coverage;
+pub use font_manager::color::{BasePalette, ColorFormats, ColorImage, ColorInfo, FontPalette, Palette, PaletteUsability};
+pub use font_manager::woff::FontFormat;
+#[cfg(feature = "source_parley")]
+pub use font_manager::render;